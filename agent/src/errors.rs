@@ -13,6 +13,13 @@ error_chain! {
     links {
         Api(intecture_api::errors::Error, intecture_api::errors::ErrorKind);
     }
+
+    errors {
+        Forbidden(r: &'static str) {
+            description("Request type is not allowed"),
+            display("This agent does not allow {} requests", r),
+        }
+    }
 }
 
 impl convert::From<Error> for io::Error {
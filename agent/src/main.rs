@@ -4,42 +4,93 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+extern crate bytes;
 extern crate clap;
 extern crate env_logger;
 #[macro_use] extern crate error_chain;
 extern crate futures;
 extern crate intecture_api;
+#[macro_use] extern crate log;
+extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
 extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_proto;
 extern crate tokio_service;
+#[cfg(unix)] extern crate tokio_signal;
+#[cfg(unix)] extern crate tokio_uds;
 extern crate toml;
 
 mod errors;
 
+use bytes::Bytes;
 use error_chain::ChainedError;
 use errors::*;
-use futures::{future, Future};
+use futures::{future, Future, Sink, Stream};
+use futures::future::{Either, Shared};
+use futures::sync::{mpsc, oneshot};
+use intecture_api::host::{AgentMetrics, RequestMetrics};
 use intecture_api::host::local::Local;
-use intecture_api::host::remote::JsonLineProto;
-use intecture_api::{FromMessage, InMessage, Request};
+use intecture_api::host::remote::{GzipSwitch, JsonFormat, JsonLineProto};
+use intecture_api::command::{hooks, ResourceLimits};
+use intecture_api::telemetry;
+use intecture_api::{drain_body, error_to_msg, ok_to_msg, Envelope, Hello, InMessage, Request, PROTOCOL_VERSION};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{self, Read};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result;
-use std::sync::Arc;
-use tokio_core::reactor::Remote;
-use tokio_proto::streaming::Message;
-use tokio_proto::TcpServer;
-use tokio_service::{NewService, Service};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_proto::BindServer;
+use tokio_proto::streaming::{Body, Message};
+use tokio_service::Service;
+#[cfg(unix)] use std::fs;
+#[cfg(unix)] use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)] use tokio_signal::unix::{Signal, SIGHUP};
+#[cfg(unix)] use tokio_uds::UnixListener;
 
 pub struct Api {
-    host: Local,
+    // Telemetry is loaded in the background for each connection, so
+    // requests wait on this rather than blocking the reactor
+    host: Shared<Box<Future<Item = Local, Error = Error>>>,
+    handle: Handle,
+    gzip: GzipSwitch,
+    settings: Arc<RwLock<Settings>>,
+    metrics: Arc<Mutex<Metrics>>,
+    in_flight: Rc<RefCell<HashMap<u64, oneshot::Sender<()>>>>,
 }
 
 pub struct NewApi {
-    remote: Remote,
+    settings: Arc<RwLock<Settings>>,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+// The config file settings that are reloaded on SIGHUP. Requests read them
+// as they are handled, and connections as they are accepted.
+#[derive(Clone)]
+struct Settings {
+    shell: Option<Vec<String>>,
+    allowed_requests: Option<Vec<String>>,
+    max_frame_size: Option<usize>,
+    json: JsonFormat,
+}
+
+// Request metrics for every connection to the agent.
+struct Metrics {
+    started: Instant,
+    requests: HashMap<&'static str, RequestMetrics>,
+    malformed: u64,
 }
 
 impl Service for Api {
@@ -48,49 +99,291 @@ impl Service for Api {
     type Error = Error;
     type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
 
-    fn call(&self, req: Self::Request) -> Self::Future {
-        let request = match Request::from_msg(req)
+    fn call(&self, mut req: Self::Request) -> Self::Future {
+        let started = Instant::now();
+        let body = req.take_body();
+        let envelope: Envelope = match serde_json::from_value(req.into_inner())
+            .chain_err(|| "Malformed Request")
+        {
+            Ok(e) => e,
+            Err(e) => {
+                if let Some(b) = body {
+                    drain_body(b, &self.handle);
+                }
+                self.metrics.lock().unwrap().record(None, false, started.elapsed());
+                return Box::new(future::ok(error_to_msg(e)));
+            },
+        };
+
+        let (id, cid, value) = match envelope {
+            Envelope::Request { id, cid, request } => (id, cid, request),
+            Envelope::Cancel { id } => {
+                if let Some(b) = body {
+                    drain_body(b, &self.handle);
+                }
+                if let Some(abort) = self.in_flight.borrow_mut().remove(&id) {
+                    let _ = abort.send(());
+                }
+                return Box::new(future::ok(ok_to_msg(Message::WithoutBody(Value::Null))));
+            },
+            Envelope::Hello(client) => {
+                if let Some(b) = body {
+                    drain_body(b, &self.handle);
+                }
+                debug!("Client version {} connected", client.version);
+                if client.protocol != PROTOCOL_VERSION {
+                    warn!("Client speaks protocol v{}, but this agent speaks v{}", client.protocol, PROTOCOL_VERSION);
+                }
+
+                // The client decides whether it can continue, so always
+                // answer with our version. We only agree to gzip if the
+                // client offered it, as older clients can't decompress.
+                let hello = Hello {
+                    version: env!("CARGO_PKG_VERSION").into(),
+                    protocol: PROTOCOL_VERSION,
+                    gzip: self.gzip.negotiate(true, client.gzip),
+                };
+                return Box::new(future::ok(match serde_json::to_value(hello) {
+                    Ok(v) => ok_to_msg(Message::WithoutBody(v)),
+                    Err(e) => error_to_msg(Error::with_chain(e, "Could not serialize handshake")),
+                }));
+            },
+            Envelope::Close => {
+                if let Some(b) = body {
+                    drain_body(b, &self.handle);
+                }
+                debug!("Client is closing the connection");
+                return Box::new(future::ok(ok_to_msg(Message::WithoutBody(Value::Null))));
+            },
+        };
+
+        let name = Request::name_of(&value);
+        let mut request = match Request::from_msg_with_handle(match body {
+                Some(b) => Message::WithBody(value, b),
+                None => Message::WithoutBody(value),
+            }, &self.handle)
             .chain_err(|| "Malformed Request")
         {
             Ok(r) => r,
-            Err(e) => return Box::new(future::ok(error_to_msg(e))),
+            Err(e) => {
+                warn!("Request {} failed: {}", cid, e.display_chain());
+                self.metrics.lock().unwrap().record(name, false, started.elapsed());
+                return Box::new(future::ok(error_to_msg(e)));
+            },
         };
 
-        Box::new(request.exec(&self.host)
-            .chain_err(|| "Failed to execute Request")
-            .then(|mut result| match result {
-                Ok(mut msg) => {
-                    let mut reply = msg.get_mut();
-                    reply = format!("{\"Ok\":\"{}\"}", reply);
-                    future::ok(msg)
-                },
-                Err(e) => future::ok(error_to_msg(e))
+        // Pings are always allowed, as they are only a liveness check
+        let is_ping = match request {
+            Request::Ping(_) => true,
+            _ => false,
+        };
+
+        if let Some(ref allowed) = self.settings.read().unwrap().allowed_requests {
+            if !is_ping && !allowed.iter().any(|r| r == request.name()) {
+                warn!("Request {} rejected: {} requests are not allowed", cid, request.name());
+                self.metrics.lock().unwrap().record(Some(request.name()), false, started.elapsed());
+                return Box::new(future::ok(error_to_msg(Error::from(ErrorKind::Forbidden(request.name())))));
+            }
+        }
+
+        if let Request::MetricsLoad(_) = request {
+            let metrics = self.metrics.lock().unwrap();
+            let response = AgentMetrics {
+                uptime: metrics.started.elapsed().as_secs(),
+                requests: metrics.requests.iter().map(|(k, v)| ((*k).to_owned(), v.clone())).collect(),
+                malformed: metrics.malformed,
+            };
+            return Box::new(future::ok(match serde_json::to_value(response) {
+                Ok(v) => ok_to_msg(Message::WithoutBody(v)),
+                Err(e) => error_to_msg(Error::with_chain(e, "Could not serialize metrics")),
+            }));
+        }
+
+        debug!("Executing request {} with id {}", cid, id);
+
+        if let Request::CommandExec(ref mut exec) = request {
+            if let Some(ref shell) = self.settings.read().unwrap().shell {
+                exec.default_shell(shell);
+            }
+        }
+
+        // Track the request so that it can be aborted by a later `Cancel`
+        let (abort_tx, abort_rx) = oneshot::channel();
+        self.in_flight.borrow_mut().insert(id, abort_tx);
+        let in_flight = self.in_flight.clone();
+        let metrics = self.metrics.clone();
+        let name = request.name();
+        let handle = self.handle.clone();
+
+        Box::new(self.host.clone()
+            .then(|host| match host {
+                Ok(h) => Ok((*h).clone()),
+                Err(e) => Err(Error::from(format!("Could not load host: {}",
+                    e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")))),
+            })
+            .and_then(move |host| request.exec(&host).chain_err(|| "Failed to execute Request"))
+            .select2(abort_rx)
+            .then(move |result| {
+                in_flight.borrow_mut().remove(&id);
+
+                match result {
+                    Ok(Either::A((msg, _))) => {
+                        debug!("Request {} succeeded", cid);
+                        let msg = match msg {
+                            Message::WithBody(header, body) => {
+                                Message::WithBody(header, record_body(body, metrics, name, started, &handle))
+                            },
+                            msg => {
+                                metrics.lock().unwrap().record(Some(name), true, started.elapsed());
+                                msg
+                            },
+                        };
+                        future::ok(ok_to_msg(msg))
+                    },
+                    Err(Either::A((e, _))) => {
+                        warn!("Request {} failed: {}", cid, e.display_chain());
+                        metrics.lock().unwrap().record(Some(name), false, started.elapsed());
+                        future::ok(error_to_msg(e))
+                    },
+                    Ok(Either::B(_)) | Err(Either::B(_)) => {
+                        debug!("Request {} was cancelled", cid);
+                        metrics.lock().unwrap().record(Some(name), false, started.elapsed());
+                        future::ok(error_to_msg(Error::from("Request was cancelled")))
+                    },
+                }
             }))
     }
 }
 
-impl NewService for NewApi {
-    type Request = InMessage;
-    type Response = InMessage;
-    type Error = Error;
-    type Instance = Api;
-    fn new_service(&self) -> io::Result<Self::Instance> {
-        // XXX Danger zone! If we're running multiple threads, this `unwrap()`
-        // will explode. The API requires a `Handle`, but we can only send a
-        // `Remote` to this Service. Currently we force the `Handle`, which is
-        // only safe for the current thread.
-        // See https://github.com/alexcrichton/tokio-process/issues/23
-        let handle = self.remote.handle().unwrap();
-
-        Ok(Api {
-            host: Local::new(&handle).wait().unwrap(),
-        })
+impl Metrics {
+    // Record a request of type `name`, or a request that was too malformed
+    // to tell its type if `name` is `None`.
+    fn record(&mut self, name: Option<&'static str>, success: bool, duration: Duration) {
+        match name {
+            Some(name) => self.requests.entry(name)
+                .or_insert_with(RequestMetrics::default)
+                .record(success, duration),
+            None => self.malformed += 1,
+        }
+    }
+}
+
+// Forward a response body, recording the request once the body has ended.
+// The request fails if the body errors or the client stops reading it.
+fn record_body(body: Body<Bytes, io::Error>, metrics: Arc<Mutex<Metrics>>, name: &'static str, started: Instant, handle: &Handle) -> Body<Bytes, io::Error> {
+    let failed = Rc::new(Cell::new(false));
+    let failed_chunk = failed.clone();
+    let (tx, forwarded) = Body::pair();
+
+    handle.spawn(tx.send_all(body.then(move |chunk| {
+            if chunk.is_err() {
+                failed_chunk.set(true);
+            }
+            Ok::<_, mpsc::SendError<_>>(chunk)
+        }))
+        .then(move |result| {
+            let success = result.is_ok() && !failed.get();
+            metrics.lock().unwrap().record(Some(name), success, started.elapsed());
+            Ok(())
+        }));
+
+    forwarded
+}
+
+impl NewApi {
+    // Serve the API on a new connection. Each connection gets its own
+    // `GzipSwitch`, so that the handshake can turn on compression for it.
+    fn serve<T>(&self, stream: T, proto: &JsonLineProto, handle: &Handle)
+        where T: AsyncRead + AsyncWrite + 'static
+    {
+        let host: Box<Future<Item = Local, Error = Error>> = Box::new(Local::new(handle).map_err(Error::from));
+        let gzip = GzipSwitch::default();
+        let api = Api {
+            host: host.shared(),
+            handle: handle.clone(),
+            gzip: gzip.clone(),
+            settings: self.settings.clone(),
+            metrics: self.metrics.clone(),
+            in_flight: Rc::new(RefCell::new(HashMap::new())),
+        };
+
+        // The frame size and format are fixed for the life of a connection
+        let (max_frame_size, format) = {
+            let settings = self.settings.read().unwrap();
+            (settings.max_frame_size, settings.json)
+        };
+
+        JsonLineProto {
+            format: format,
+            gzip: gzip,
+            max_frame_size: max_frame_size,
+            ..proto.clone()
+        }.bind_server(handle, stream, IoApi(api));
     }
 }
 
 #[derive(Deserialize)]
 struct Config {
-    address: SocketAddr,
+    #[serde(alias = "address", deserialize_with = "de_listen")]
+    listen: Listener,
+    shell: Option<Vec<String>>,
+    allowed_requests: Option<Vec<String>>,
+    max_frame_size: Option<usize>,
+    limits: Option<ResourceLimits>,
+    telemetry_timeout_secs: Option<u64>,
+    json: Option<JsonFormat>,
+}
+
+/// The socket the agent accepts connections on.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Listener {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+// Before Unix sockets were supported, the agent was configured with
+// `address = "<ip>:<port>"`. Keep accepting that as a TCP socket.
+fn de_listen<'de, D>(deserializer: D) -> result::Result<Listener, D::Error>
+    where D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Listen {
+        Address(SocketAddr),
+        Listener(Listener),
+    }
+
+    Ok(match Listen::deserialize(deserializer)? {
+        Listen::Address(addr) => Listener::Tcp(addr),
+        Listen::Listener(l) => l,
+    })
+}
+
+impl Config {
+    fn settings(&self) -> Settings {
+        Settings {
+            shell: self.shell.clone(),
+            allowed_requests: self.allowed_requests.clone(),
+            max_frame_size: self.max_frame_size,
+            json: self.json.unwrap_or_default(),
+        }
+    }
+
+    // Apply the settings that are global to the agent's process.
+    fn apply_globals(&self) {
+        hooks::set_limits(self.limits.clone());
+        telemetry::set_load_timeout(Duration::from_secs(self.telemetry_timeout_secs.unwrap_or(telemetry::LOAD_TIMEOUT_SECS)));
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Listener::Tcp(ref addr) => write!(f, "{}", addr),
+            Listener::Unix(ref path) => write!(f, "{}", path.display()),
+        }
+    }
 }
 
 quick_main!(|| -> Result<()> {
@@ -112,40 +405,164 @@ quick_main!(|| -> Result<()> {
                                 .value_name("ADDR")
                                 .help("Set the socket address this server will listen on (e.g. 0.0.0.0:7101)")
                                 .takes_value(true))
+                            .arg(clap::Arg::with_name("socket")
+                                .short("s")
+                                .long("socket")
+                                .value_name("PATH")
+                                .help("Set the path of a Unix socket this server will listen on")
+                                .takes_value(true))
                             .group(clap::ArgGroup::with_name("config_or_else")
-                                .args(&["config", "addr"])
+                                .args(&["config", "addr", "socket"])
                                 .required(true))
                             .get_matches();
 
-    let config = if let Some(c) = matches.value_of("config") {
-        let mut fh = File::open(c).chain_err(|| "Could not open config file")?;
-        let mut buf = Vec::new();
-        fh.read_to_end(&mut buf).chain_err(|| "Could not read config file")?;
-        toml::from_slice(&buf).chain_err(|| "Config file contained invalid TOML")?
+    let config_path = matches.value_of("config").map(|c| c.to_owned());
+    let config = if let Some(ref c) = config_path {
+        load_config(c)?
+    } else if let Some(path) = matches.value_of("socket") {
+        Config { listen: Listener::Unix(path.into()), shell: None, allowed_requests: None, max_frame_size: None, limits: None, telemetry_timeout_secs: None, json: None }
     } else {
         let address = matches.value_of("addr").unwrap().parse().chain_err(|| "Invalid server address")?;
-        Config { address }
+        Config { listen: Listener::Tcp(address), shell: None, allowed_requests: None, max_frame_size: None, limits: None, telemetry_timeout_secs: None, json: None }
     };
 
-    // XXX We can only run a single thread here, or big boom!!
-    // The API requires a `Handle`, but we can only send a `Remote`.
-    // Currently we force the issue (`unwrap()`), which is only safe
-    // for the current thread.
-    // See https://github.com/alexcrichton/tokio-process/issues/23
-    let server = TcpServer::new(JsonLineProto, config.address);
-    server.with_handle(move |handle| {
-        Arc::new(NewApi {
-            remote: handle.remote().clone(),
-        })
-    });
-    Ok(())
+    config.apply_globals();
+    let settings = Arc::new(RwLock::new(config.settings()));
+    let listen = config.listen;
+    let reload_listen = listen.clone();
+    let metrics = Arc::new(Mutex::new(Metrics {
+        started: Instant::now(),
+        requests: HashMap::new(),
+        malformed: 0,
+    }));
+    let proto = JsonLineProto::default();
+    let new_api = move |handle: &Handle| {
+        if let Some(ref path) = config_path {
+            reload_on_hup(path, reload_listen.clone(), settings.clone(), handle);
+        }
+
+        NewApi {
+            settings: settings.clone(),
+            metrics: metrics.clone(),
+        }
+    };
+
+    match listen {
+        Listener::Tcp(address) => serve_tcp(&address, proto, new_api),
+        Listener::Unix(path) => serve_unix(&path, proto, new_api),
+    }
 });
 
-fn error_to_msg(e: Error) -> InMessage {
-    let response: result::Result<(), String> = Err(format!("{}", e.display_chain()));
-    // If we can't serialize this, we can't serialize anything, so
-    // panicking is appropriate.
-    let value = serde_json::to_value(response)
-        .expect("Cannot serialize ResponseResult::Err. This is bad...");
-    Message::WithoutBody(value)
+// Serve the API on a TCP socket. Requests run on the same thread as the
+// server, as they need its `Handle`.
+fn serve_tcp<F>(address: &SocketAddr, proto: JsonLineProto, new_api: F) -> Result<()>
+    where F: FnOnce(&Handle) -> NewApi
+{
+    let mut core = Core::new().chain_err(|| "Could not start event loop")?;
+    let handle = core.handle();
+
+    let listener = TcpListener::bind(address, &handle)
+        .chain_err(|| format!("Could not bind address {}", address))?;
+    let new_api = new_api(&handle);
+
+    let server = listener.incoming().for_each(|(stream, _)| {
+        new_api.serve(stream, &proto, &handle);
+        Ok(())
+    });
+
+    core.run(server).chain_err(|| "Server failed")
+}
+
+// Serve the API on a Unix socket. Access to the agent is governed by the
+// socket file's permissions.
+#[cfg(unix)]
+fn serve_unix<F>(path: &Path, proto: JsonLineProto, new_api: F) -> Result<()>
+    where F: FnOnce(&Handle) -> NewApi
+{
+    let mut core = Core::new().chain_err(|| "Could not start event loop")?;
+    let handle = core.handle();
+
+    // Clean up a socket left behind by a previous run, but never remove
+    // anything that isn't a socket.
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            fs::remove_file(path).chain_err(|| format!("Could not remove stale socket {}", path.display()))?;
+        }
+    }
+
+    let listener = UnixListener::bind(path, &handle)
+        .chain_err(|| format!("Could not bind socket {}", path.display()))?;
+    let new_api = new_api(&handle);
+
+    let server = listener.incoming().for_each(|(stream, _)| {
+        new_api.serve(stream, &proto, &handle);
+        Ok(())
+    });
+
+    core.run(server).chain_err(|| "Server failed")
 }
+
+// `BindServer` needs a service that errors with `io::Error`
+struct IoApi(Api);
+
+impl Service for IoApi {
+    type Request = InMessage;
+    type Response = InMessage;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        Box::new(self.0.call(req).map_err(io::Error::from))
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_unix<F>(_: &Path, _: JsonLineProto, _: F) -> Result<()>
+    where F: FnOnce(&Handle) -> NewApi
+{
+    Err("Unix sockets are not supported on this platform".into())
+}
+
+fn load_config(path: &str) -> Result<Config> {
+    let mut fh = File::open(path).chain_err(|| "Could not open config file")?;
+    let mut buf = Vec::new();
+    fh.read_to_end(&mut buf).chain_err(|| "Could not read config file")?;
+    Ok(toml::from_slice(&buf).chain_err(|| "Config file contained invalid TOML")?)
+}
+
+// Re-read the config file whenever the agent receives SIGHUP, and apply the
+// settings that can be changed without restarting the server.
+#[cfg(unix)]
+fn reload_on_hup(path: &str, listen: Listener, settings: Arc<RwLock<Settings>>, handle: &Handle) {
+    let path = path.to_owned();
+    let reload = Signal::new(SIGHUP, handle)
+        .flatten_stream()
+        .for_each(move |_| {
+            info!("Reloading config file {}", path);
+
+            match load_config(&path) {
+                Ok(config) => {
+                    if config.listen != listen {
+                        warn!("The agent must be restarted before it will listen on {}", config.listen);
+                    }
+
+                    let new = config.settings();
+                    let mut settings = settings.write().unwrap();
+                    if new.max_frame_size != settings.max_frame_size || new.json != settings.json {
+                        info!("max_frame_size and json only apply to new connections, so existing connections must reconnect to pick them up");
+                    }
+                    *settings = new;
+                    config.apply_globals();
+                },
+                Err(e) => error!("{}", e.display_chain()),
+            }
+
+            Ok(())
+        })
+        .map_err(|e| error!("Could not listen for SIGHUP: {}", e));
+
+    handle.spawn(reload);
+}
+
+#[cfg(not(unix))]
+fn reload_on_hup(_: &str, _: Listener, _: Arc<RwLock<Settings>>, _: &Handle) {}
@@ -6,22 +6,35 @@
 
 use bytes::Bytes;
 use errors::*;
-use futures::{future, Future, Poll, Stream};
+use futures::{future, stream, Future, Poll, Stream};
+use futures::future::Loop;
 use futures::sink::Sink;
 use futures::sync::{mpsc, oneshot};
 use message::{FromMessage, IntoMessage, InMessage};
 use serde_json as json;
 use std::convert::From;
+use std::error::Error as StdError;
 use std::io::{self, BufReader};
 use std::result;
+use std::time::{Duration, Instant};
 use tokio_core::reactor::Handle;
 use tokio_io::io::lines;
 use tokio_process;
 use tokio_proto::streaming::{Body, Message};
 
+/// Default number of output lines that are buffered when streaming a
+/// command's output to a remote client.
+///
+/// Output is only read from the command when there is room in the buffer, so
+/// a slow consumer throttles the command rather than growing the agent's
+/// memory usage.
+pub const DEFAULT_OUTPUT_BUFFER: usize = 64;
+
 /// Represents the status of a running `Command`, including the output stream
 /// and exit status.
 pub struct Child {
+    detached: bool,
+    output_buffer: usize,
     exit_status: Option<Box<Future<Item = ExitStatus, Error = Error>>>,
     stream: Option<Box<Stream<Item = String, Error = Error>>>,
 }
@@ -33,6 +46,25 @@ pub struct CommandResult {
     inner: Box<Future<Item = String, Error = Error>>,
 }
 
+/// The output of a finished command.
+///
+/// This is a replica of
+/// [`std::process::Output`](https://doc.rust-lang.org/std/process/struct.Output.html).
+#[derive(Debug)]
+pub struct Output {
+    /// The exit status of the command.
+    pub status: ExitStatus,
+    /// The output of the command.
+    ///
+    /// Note that stdout and stderr are currently merged into a single stream,
+    /// so this contains the output from both.
+    pub stdout: String,
+    /// The stderr output of the command.
+    ///
+    /// This is always empty for now, as stderr is merged into `stdout`.
+    pub stderr: String,
+}
+
 /// The status of a finished command.
 ///
 /// This is a serializable replica of
@@ -47,9 +79,78 @@ pub struct ExitStatus {
     /// On Unix, this will return `None` if the process was terminated by a
     /// signal.
     pub code: Option<i32>,
+    /// How long the command ran for, from being spawned to exiting.
+    ///
+    /// This is measured on the host that ran the command, so for remote
+    /// hosts it does not include any network latency. Detached commands
+    /// report a duration of zero.
+    pub duration: Duration,
 }
 
 impl Child {
+    // A command that was started without capturing its output. It has no
+    // output and resolves immediately with a successful `ExitStatus`.
+    #[doc(hidden)]
+    pub fn detached() -> Child {
+        Child {
+            detached: true,
+            output_buffer: DEFAULT_OUTPUT_BUFFER,
+            exit_status: Some(Box::new(future::ok(ExitStatus {
+                success: true,
+                code: None,
+                duration: Duration::from_secs(0),
+            }))),
+            stream: Some(Box::new(stream::empty())),
+        }
+    }
+
+    // Set the number of output lines that are buffered when streaming this
+    // command's output to a remote client.
+    #[doc(hidden)]
+    pub fn set_output_buffer(&mut self, lines: usize) {
+        self.output_buffer = lines;
+    }
+
+    // Run `f` once the command has exited and its `ExitStatus` has been
+    // resolved, whether or not the command succeeded.
+    #[doc(hidden)]
+    pub fn on_exit<F: FnOnce() + 'static>(mut self, f: F) -> Child {
+        let status = self.exit_status.take().unwrap();
+        self.exit_status = Some(Box::new(status.then(move |r| {
+            f();
+            r
+        })));
+        self
+    }
+
+    // Treat this child as the last stage of a pipeline. `output` resolves to
+    // the stderr output of the earlier stages, which follows this child's
+    // output, and `statuses` resolves to their exit statuses.
+    #[doc(hidden)]
+    pub fn pipeline<O, S>(mut self, output: O, statuses: S, pipefail: bool) -> Child
+        where O: Future<Item = Vec<String>, Error = Error> + 'static,
+              S: Future<Item = Vec<ExitStatus>, Error = Error> + 'static
+    {
+        let stream = self.stream.take().unwrap();
+        self.stream = Some(Box::new(stream.chain(output
+            .map(|lines| stream::iter_ok(lines))
+            .flatten_stream())));
+
+        let status = self.exit_status.take().unwrap();
+        self.exit_status = Some(Box::new(status.join(statuses).map(move |(last, stages)| {
+            // Like Bash's `pipefail`, report the last stage that failed
+            match stages.into_iter().rev().find(|s| !s.success) {
+                Some(ref failed) if pipefail && last.success => ExitStatus {
+                    success: false,
+                    code: failed.code,
+                    duration: last.duration,
+                },
+                _ => last,
+            }
+        })));
+        self
+    }
+
     /// Take ownership of the output stream.
     ///
     /// The stream is guaranteed to be present only if this is the first call
@@ -65,17 +166,44 @@ impl Child {
     ///
     /// Note that "success" is determined by examining the `ExitStatus::success`
     /// bool. See `ExitStatus` docs for details.
+    ///
+    /// On failure, the error is an `ErrorKind::Command` containing the
+    /// command's output and exit code. Note that stdout and stderr are
+    /// currently merged into a single stream, so all output is in `stdout`
+    /// and `stderr` is empty.
+    ///
+    /// If the output stream fails part way through, the error is also an
+    /// `ErrorKind::Command` containing the output received so far and no exit
+    /// code, with the stream error as its cause.
     pub fn result(self) -> Option<CommandResult> {
         if let Some(stream) = self.stream {
-            let inner = stream.fold(String::new(), |mut acc, line| {
-                    acc.push_str(&line);
-                    future::ok::<_, Error>(acc)
+            // Accumulate the output by hand rather than with `fold()`, so
+            // that the partial output isn't lost if the stream errors.
+            let output = future::loop_fn((stream, String::new()), |(stream, mut acc)| {
+                stream.into_future().then(move |r| match r {
+                    Ok((Some(line), stream)) => {
+                        acc.push_str(&line);
+                        Ok(Loop::Continue((stream, acc)))
+                    },
+                    Ok((None, _)) => Ok(Loop::Break(acc)),
+                    Err((e, _)) => Err(Error::with_chain(e, ErrorKind::Command {
+                        stdout: acc,
+                        stderr: String::new(),
+                        code: None,
+                    })),
                 })
+            });
+
+            let inner = output
                 .join(self.exit_status.unwrap())
                 .and_then(|(output, status)| if status.success {
                     future::ok(output)
                 } else {
-                    future::err(ErrorKind::Command(output).into())
+                    future::err(ErrorKind::Command {
+                        stdout: output,
+                        stderr: String::new(),
+                        code: status.code,
+                    }.into())
                 });
 
             Some(CommandResult {
@@ -85,10 +213,57 @@ impl Child {
             None
         }
     }
+
+    /// Convert this to a future that resolves to the command's `Output`,
+    /// regardless of whether the command succeeded. If the stream has
+    /// already been taken by `take_stream()` then this function will return
+    /// `None`.
+    pub fn output(self) -> Option<Box<Future<Item = Output, Error = Error>>> {
+        if let Some(stream) = self.stream {
+            let inner = stream.fold(String::new(), |mut acc, line| {
+                    acc.push_str(&line);
+                    future::ok::<_, Error>(acc)
+                })
+                .join(self.exit_status.unwrap())
+                .map(|(stdout, status)| Output {
+                    status: status,
+                    stdout: stdout,
+                    stderr: String::new(),
+                });
+
+            Some(Box::new(inner))
+        } else {
+            None
+        }
+    }
+
+    /// Forward the command's output to a `Sink`, such as a log or file
+    /// writer, and resolve to the command's `ExitStatus` once both the output
+    /// and the command have finished. If the stream has already been taken
+    /// by `take_stream()` then this function will return `None`.
+    ///
+    /// Because the output stream is always consumed, this is safe to use with
+    /// remote hosts, where the `ExitStatus` is only sent after the output.
+    pub fn pipe_to<S>(self, sink: S) -> Option<Box<Future<Item = ExitStatus, Error = Error>>>
+        where S: Sink<SinkItem = String> + 'static,
+              S::SinkError: StdError + Send + 'static
+    {
+        if let Some(stream) = self.stream {
+            let inner = stream
+                .forward(sink.sink_map_err(|e| Error::with_chain(e, "Could not forward command output to Sink")))
+                .join(self.exit_status.unwrap())
+                .map(|(_, status)| status);
+
+            Some(Box::new(inner))
+        } else {
+            None
+        }
+    }
 }
 
 impl From<tokio_process::Child> for Child {
     fn from(mut child: tokio_process::Child) -> Self {
+        let started = Instant::now();
         let stdout = child.stdout().take().expect("Child was not configured with stdout");
         let outbuf = BufReader::new(stdout);
         let stderr = child.stderr().take().expect("Child was not configured with stderr");
@@ -98,15 +273,18 @@ impl From<tokio_process::Child> for Child {
             .select(lines(errbuf))
             .map_err(|e| Error::with_chain(e, ErrorKind::Msg("Command execution failed".into())));
 
-        let status = child.map(|s| {
+        let status = child.map(move |s| {
                 ExitStatus {
                     success: s.success(),
                     code: s.code(),
+                    duration: started.elapsed(),
                 }
             })
             .map_err(|e| Error::with_chain(e, ErrorKind::Msg("Command execution failed".into())));
 
         Child {
+            detached: false,
+            output_buffer: DEFAULT_OUTPUT_BUFFER,
             exit_status: Some(Box::new(status)),
             stream: Some(Box::new(stream)),
         }
@@ -130,10 +308,29 @@ impl Future for Child {
 
 impl FromMessage for Child {
     fn from_msg(mut msg: InMessage) -> Result<Self> {
+        // The host replies with `Ok` once the command has been started. An
+        // error should never reach here, as the host's reply is checked
+        // first, but if one does, fail rather than mistaking the missing
+        // body for a detached command.
+        let header: result::Result<json::Value, json::Value> = json::from_value(msg.get_ref().clone())
+            .chain_err(|| "Could not decode reply to Command::exec")?;
+        if let Err(e) = header {
+            let e = match e {
+                json::Value::String(s) => s,
+                v => v.to_string(),
+            };
+            return Err(ErrorKind::Remote(e).into());
+        }
+
+        // Detached commands are sent without a body stream
+        let body = match msg.take_body() {
+            Some(b) => b,
+            None => return Ok(Child::detached()),
+        };
+
         let (tx, rx) = oneshot::channel::<ExitStatus>();
         let mut tx = Some(tx);
-        let stream = msg.take_body()
-            .expect("Command::exec reply missing body stream")
+        let stream = body
             .filter_map(move |v| {
                 let s = String::from_utf8_lossy(&v).to_string();
 
@@ -155,6 +352,8 @@ impl FromMessage for Child {
             .then(|r| r.chain_err(|| "Command execution failed"));
 
         Ok(Child {
+            detached: false,
+            output_buffer: DEFAULT_OUTPUT_BUFFER,
             exit_status: Some(Box::new(rx.chain_err(|| "Stream dropped before ExitStatus was sent"))),
             stream: Some(Box::new(stream)),
         })
@@ -163,7 +362,17 @@ impl FromMessage for Child {
 
 impl IntoMessage for Child {
     fn into_msg(self, handle: &Handle) -> Result<InMessage> {
-        let (tx1, body) = Body::pair();
+        let value: result::Result<_, ()> = Ok(());
+
+        if self.detached {
+            return Ok(Message::WithoutBody(json::to_value(value).unwrap()));
+        }
+
+        // Output is forwarded through a bounded channel, and only read from
+        // the command when the channel has capacity. This way a slow
+        // consumer applies backpressure to the command.
+        let (tx1, rx) = mpsc::channel(self.output_buffer);
+        let body = Body::from(rx);
         let tx2 = tx1.clone();
 
         let status = self.exit_status.unwrap().and_then(|s| {
@@ -190,7 +399,6 @@ impl IntoMessage for Child {
 
         handle.spawn(stream);
 
-        let value: result::Result<_, ()> = Ok(());
         Ok(Message::WithBody(json::to_value(value).unwrap(), body))
     }
 }
@@ -203,3 +411,97 @@ impl Future for CommandResult {
         self.inner.poll()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, stream, Future, Stream};
+    use message::{error_to_msg, FromMessage};
+    use std::time::Duration;
+    use tokio_proto::streaming::{Body, Message};
+    use super::*;
+
+    fn exit_status(success: bool) -> ExitStatus {
+        ExitStatus {
+            success: success,
+            code: Some(if success { 0 } else { 1 }),
+            duration: Duration::from_secs(0),
+        }
+    }
+
+    fn child<S>(stream: S, success: bool) -> Child
+        where S: Stream<Item = String, Error = Error> + 'static
+    {
+        Child {
+            detached: false,
+            output_buffer: DEFAULT_OUTPUT_BUFFER,
+            exit_status: Some(Box::new(future::ok(exit_status(success)))),
+            stream: Some(Box::new(stream)),
+        }
+    }
+
+    #[test]
+    fn test_result_keeps_partial_output() {
+        let lines = vec![Ok("first\n".to_owned()), Ok("second\n".to_owned()), Err("Connection reset".into())];
+        let err = child(stream::iter_result(lines), true).result().unwrap().wait().unwrap_err();
+
+        match *err.kind() {
+            ErrorKind::Command { ref stdout, code, .. } => {
+                assert_eq!(stdout, "first\nsecond\n");
+                assert_eq!(code, None);
+            },
+            ref k => panic!("Unexpected error {:?}", k),
+        }
+        assert_eq!(err.iter().nth(1).unwrap().to_string(), "Connection reset");
+    }
+
+    #[test]
+    fn test_result_failed_command() {
+        let lines = vec![Ok("oops\n".to_owned())];
+        let err = child(stream::iter_result(lines), false).result().unwrap().wait().unwrap_err();
+
+        match *err.kind() {
+            ErrorKind::Command { ref stdout, code, .. } => {
+                assert_eq!(stdout, "oops\n");
+                assert_eq!(code, Some(1));
+            },
+            ref k => panic!("Unexpected error {:?}", k),
+        }
+    }
+
+    #[test]
+    fn test_result_success() {
+        let lines = vec![Ok("done\n".to_owned())];
+        assert_eq!(child(stream::iter_result(lines), true).result().unwrap().wait().unwrap(), "done\n");
+    }
+
+    #[test]
+    fn test_from_msg_error_reply() {
+        // This is what an agent sends when `CommandExec` fails to start
+        let msg = error_to_msg(Error::from("Could not spawn command"));
+
+        match Child::from_msg(msg) {
+            Err(Error(ErrorKind::Remote(ref e), _)) => assert!(e.contains("Could not spawn command")),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Error reply was decoded as a Child"),
+        }
+    }
+
+    #[test]
+    fn test_from_msg_error_reply_with_body() {
+        let (_tx, body) = Body::pair();
+        let msg = Message::WithBody(json::from_str(r#"{"Err": "Could not spawn command"}"#).unwrap(), body);
+
+        match Child::from_msg(msg) {
+            Err(Error(ErrorKind::Remote(ref e), _)) => assert_eq!(e, "Could not spawn command"),
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Error reply was decoded as a Child"),
+        }
+    }
+
+    #[test]
+    fn test_from_msg_without_body_is_detached() {
+        let child = Child::from_msg(Message::WithoutBody(json::from_str(r#"{"Ok": null}"#).unwrap())).unwrap();
+        assert!(child.detached);
+        assert!(child.wait().unwrap().success);
+    }
+}
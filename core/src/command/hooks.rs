@@ -0,0 +1,125 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Hooks that observe the OS commands run by providers.
+//!
+//! Every command that a provider runs, whether it is a `Command` or an
+//! internal call like `systemctl enable nginx`, is passed to the registered
+//! hooks just before it is spawned. This is useful for audit logs.
+//!
+//! Hooks run in the process that spawns the command. For a remote host,
+//! that is the agent, so the hooks must be registered there.
+//!
+//!```
+//!use intecture_api::command::hooks;
+//!
+//!hooks::register(|program, args| println!("Running {} {}", program, args.join(" ")));
+//!```
+
+use std::cell::RefCell;
+use std::process;
+use std::sync::{Arc, Mutex, RwLock};
+use super::ResourceLimits;
+
+/// A function that is called with the program and arguments of each command
+/// that a provider runs.
+pub type CommandHook = Fn(&str, &[&str]) + Send + Sync;
+
+lazy_static! {
+    static ref HOOKS: RwLock<Vec<Arc<CommandHook>>> = RwLock::new(Vec::new());
+    static ref LIMITS: RwLock<Option<ResourceLimits>> = RwLock::new(None);
+}
+
+thread_local! {
+    // Where to note the commands spawned by this thread, if anywhere
+    static WATCHER: RefCell<Option<Arc<Mutex<Option<String>>>>> = RefCell::new(None);
+}
+
+/// Register a hook that is called for every command a provider runs.
+///
+/// Hooks are called in the order they were registered.
+pub fn register<F>(hook: F)
+    where F: Fn(&str, &[&str]) + Send + Sync + 'static
+{
+    HOOKS.write().unwrap_or_else(|e| e.into_inner()).push(Arc::new(hook));
+}
+
+/// Cap the resources of every command that a provider runs from now on, or
+/// lift the caps if `limits` is `None`.
+///
+/// This covers internal calls like `apt-get install` as well as `Command`s,
+/// so set limits that a package manager can live with. Limits are only
+/// supported on Unix, and are ignored elsewhere.
+pub fn set_limits(limits: Option<ResourceLimits>) {
+    *LIMITS.write().unwrap_or_else(|e| e.into_inner()) = limits;
+}
+
+/// Note the command line of each command that the current thread spawns
+/// from now on in `last`, so that another thread can tell what it's stuck on.
+#[doc(hidden)]
+pub fn watch_thread(last: Arc<Mutex<Option<String>>>) {
+    WATCHER.with(|w| *w.borrow_mut() = Some(last));
+}
+
+/// Create a `process::Command` for `program` with `args`, and pass them to
+/// the registered hooks.
+#[doc(hidden)]
+pub fn command(program: &str, args: &[&str]) -> process::Command {
+    // Take a copy so that the lock isn't held while calling hooks
+    let hooks = HOOKS.read().unwrap_or_else(|e| e.into_inner()).clone();
+    for hook in hooks {
+        hook(program, args);
+    }
+
+    WATCHER.with(|w| if let Some(ref last) = *w.borrow() {
+        let line = Some(program).into_iter().chain(args.iter().cloned()).collect::<Vec<_>>().join(" ");
+        *last.lock().unwrap_or_else(|e| e.into_inner()) = Some(line);
+    });
+
+    let mut cmd = process::Command::new(program);
+    cmd.args(args);
+    if let Some(limits) = LIMITS.read().unwrap_or_else(|e| e.into_inner()).clone() {
+        apply_limits(&mut cmd, limits);
+    }
+    cmd
+}
+
+// Apply `limits` in the child process, between `fork()` and `exec()`.
+#[cfg(unix)]
+fn apply_limits(command: &mut process::Command, limits: ResourceLimits) {
+    use libc;
+    use std::io;
+    use std::os::unix::process::CommandExt;
+
+    let resources = [
+        (libc::RLIMIT_CPU, limits.cpu_secs),
+        (libc::RLIMIT_AS, limits.memory),
+        (libc::RLIMIT_NOFILE, limits.open_files),
+    ];
+
+    // Safe, as the closure only calls `setrlimit()`, which is
+    // async-signal-safe, and doesn't allocate.
+    unsafe {
+        command.pre_exec(move || {
+            for &(resource, value) in &resources {
+                if let Some(value) = value {
+                    let limit = libc::rlimit {
+                        rlim_cur: value as libc::rlim_t,
+                        rlim_max: value as libc::rlim_t,
+                    };
+                    if libc::setrlimit(resource, &limit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            }
+
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn apply_limits(_: &mut process::Command, _: ResourceLimits) {}
@@ -10,10 +10,12 @@
 //! idempotent.
 
 mod child;
+pub mod hooks;
 mod providers;
 
-pub use self::child::Child;
-pub use self::providers::{CommandProvider, factory, Generic};
+pub use self::child::{Child, ExitStatus, Output, DEFAULT_OUTPUT_BUFFER};
+pub use self::providers::{CommandProvider, CommandProviderFactory, Docker, ExecOptions, factory,
+                          Generic, register_command_provider, ResourceLimits, Ssh};
 
 use errors::*;
 use futures::Future;
@@ -29,6 +31,12 @@ const DEFAULT_SHELL: [&'static str; 1] = ["yeah...we don't currently support win
 
 /// Represents a shell command to be executed on a host.
 ///
+/// Commands created with `Command::new()` are interpreted by a shell, so
+/// any untrusted input in the command string must be escaped. If you don't
+/// need shell features like pipes or variable expansion, use
+/// [`Command::with_args()`](#method.with_args) instead, which runs the
+/// program directly and passes each argument through untouched.
+///
 ///## Examples
 ///
 /// Here's an example `ls` command that lists the directory `foo/`.
@@ -100,7 +108,9 @@ const DEFAULT_SHELL: [&'static str; 1] = ["yeah...we don't currently support win
 ///            // Our command errored out. Let's grab the output and see what
 ///            // went wrong.
 ///            match *e.kind() {
-///                ErrorKind::Command(ref output) => println!("Oh noes! {}", output),
+///                ErrorKind::Command { ref stdout, code, .. } => {
+///                    println!("Oh noes! Exited with {:?}: {}", code, stdout)
+///                },
 ///                _ => unreachable!(),
 ///            }
 ///            e
@@ -145,13 +155,25 @@ const DEFAULT_SHELL: [&'static str; 1] = ["yeah...we don't currently support win
 ///```
 pub struct Command<H> {
     host: H,
-    cmd: Vec<String>,
+    shell: Option<Vec<String>>,
+    cmd: String,
+    args: Option<Vec<String>>,
+    pipeline: Option<Vec<Vec<String>>>,
+    opts: ExecOptions,
 }
 
 #[doc(hidden)]
 #[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct CommandExec {
-    cmd: Vec<String>,
+    shell: Option<Vec<String>>,
+    cmd: String,
+    // If set, `cmd` is a program to run with these arguments, without a shell
+    #[serde(default)]
+    args: Option<Vec<String>>,
+    // If set, `cmd` is ignored and these programs are run as a pipeline
+    #[serde(default)]
+    pipeline: Option<Vec<Vec<String>>>,
+    opts: ExecOptions,
 }
 
 impl<H: Host + 'static> Command<H> {
@@ -163,17 +185,186 @@ impl<H: Host + 'static> Command<H> {
     /// argument needs to be a separate item in the slice. For example, to use
     /// Bash as your shell, you'd provide the value:
     /// `Some(&["/bin/bash", "-c"])`.
+    ///
+    /// The shell may include any number of flags, e.g.
+    /// `Some(&["/bin/bash", "--login", "-c"])`, and the command is appended
+    /// after them. If the shell needs arguments _after_ the command, use a
+    /// `{}` placeholder to mark where the command goes. For example,
+    /// `Some(&["/bin/sh", "-c", "{}", "myscript"])` runs
+    /// `/bin/sh -c <cmd> myscript`, which sets `$0` to "myscript".
+    ///
+    /// If no shell is provided and the host is remote, the agent may
+    /// substitute its own default shell from its configuration.
     pub fn new(host: &H, cmd: &str, shell: Option<&[&str]>) -> Self {
-        let mut args: Vec<String> = shell.unwrap_or(&DEFAULT_SHELL).to_owned()
-            .iter().map(|a| (*a).to_owned()).collect();
-        args.push(cmd.into());
+        Command {
+            host: host.clone(),
+            shell: shell.map(|s| s.iter().map(|a| (*a).to_owned()).collect()),
+            cmd: cmd.into(),
+            args: None,
+            pipeline: None,
+            opts: ExecOptions::default(),
+        }
+    }
 
+    /// Create a new `Command` that runs `program` directly with `args`,
+    /// without a shell.
+    ///
+    /// Because no shell is involved, the arguments are passed to the program
+    /// exactly as given. Quotes, spaces, `;`, `$()` and the like have no
+    /// special meaning, so this is safe to use with untrusted input. This
+    /// should be your default unless you need shell features.
+    ///
+    /// `program` is looked up in the host's `PATH` if it isn't a path.
+    ///
+    ///```no_run
+    ///extern crate futures;
+    ///extern crate intecture_api;
+    ///extern crate tokio_core;
+    ///
+    ///use futures::Future;
+    ///use intecture_api::prelude::*;
+    ///use tokio_core::reactor::Core;
+    ///
+    ///# fn main() {
+    ///let mut core = Core::new().unwrap();
+    ///let handle = core.handle();
+    ///
+    ///let host = Local::new(&handle).wait().unwrap();
+    ///
+    ///// Even a hostile file name is only ever an argument to `ls`
+    ///let cmd = Command::with_args(&host, "ls", &["-l", "foo; rm -rf /"]);
+    ///let result = cmd.exec().and_then(|status| status.result().unwrap());
+    ///
+    ///core.run(result).unwrap();
+    ///# }
+    ///```
+    pub fn with_args(host: &H, program: &str, args: &[&str]) -> Self {
         Command {
             host: host.clone(),
-            cmd: args,
+            shell: None,
+            cmd: program.into(),
+            args: Some(args.iter().map(|a| (*a).to_owned()).collect()),
+            pipeline: None,
+            opts: ExecOptions::default(),
         }
     }
 
+    /// Create a new `Command` that runs each of `stages`, a program and its
+    /// arguments, with its stdout piped to the stdin of the next stage.
+    ///
+    /// This is the equivalent of `cmd1 | cmd2` in a shell, but as with
+    /// [`Command::with_args()`](#method.with_args), no shell is involved, so
+    /// arguments are passed through untouched. The output stream holds the
+    /// output of the last stage, followed by the stderr output of the
+    /// earlier stages. The `ExitStatus` is that of the last stage, unless
+    /// [`pipefail()`](#method.pipefail) is set.
+    ///
+    /// Pipelines are only supported by the `Generic` provider.
+    ///
+    ///```no_run
+    ///extern crate futures;
+    ///extern crate intecture_api;
+    ///extern crate tokio_core;
+    ///
+    ///use futures::Future;
+    ///use intecture_api::prelude::*;
+    ///use tokio_core::reactor::Core;
+    ///
+    ///# fn main() {
+    ///let mut core = Core::new().unwrap();
+    ///let handle = core.handle();
+    ///
+    ///let host = Local::new(&handle).wait().unwrap();
+    ///
+    ///let cmd = Command::pipeline(&host, &[&["ps", "aux"], &["grep", "nginx"]]);
+    ///let result = cmd.exec().and_then(|status| status.result().unwrap());
+    ///
+    ///core.run(result).unwrap();
+    ///# }
+    ///```
+    pub fn pipeline(host: &H, stages: &[&[&str]]) -> Self {
+        Command {
+            host: host.clone(),
+            shell: None,
+            cmd: String::new(),
+            args: None,
+            pipeline: Some(stages.iter()
+                .map(|s| s.iter().map(|a| (*a).to_owned()).collect())
+                .collect()),
+            opts: ExecOptions::default(),
+        }
+    }
+
+    /// Fail a pipeline if any of its stages fail.
+    ///
+    /// Like Bash's `pipefail` option, the `ExitStatus` is then that of the
+    /// last stage to fail. This has no effect on commands that aren't
+    /// created with [`Command::pipeline()`](#method.pipeline).
+    pub fn pipefail(&mut self) -> &mut Self {
+        self.opts.pipefail = true;
+        self
+    }
+
+    /// Clear the environment before running the command.
+    ///
+    /// By default a command inherits the full environment of the process
+    /// that spawns it, which for remote hosts is the agent. Clearing it
+    /// ensures the command runs in a clean, reproducible environment.
+    pub fn clear_env(&mut self) -> &mut Self {
+        self.opts.clear_env = true;
+        self
+    }
+
+    /// Set an environment variable for the command.
+    ///
+    /// Variables set this way are applied after the environment is cleared
+    /// by [`clear_env()`](#method.clear_env), so they are kept.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.opts.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the number of output lines that are buffered when streaming the
+    /// command's output from a remote host.
+    ///
+    /// The host stops reading the command's output while the buffer is
+    /// full, so a slow consumer throttles the command instead of the output
+    /// piling up in memory. Defaults to
+    /// [`DEFAULT_OUTPUT_BUFFER`](constant.DEFAULT_OUTPUT_BUFFER.html).
+    pub fn output_buffer(&mut self, lines: usize) -> &mut Self {
+        self.opts.output_buffer = Some(lines);
+        self
+    }
+
+    /// Run the command as the user `name`.
+    ///
+    /// This drops privileges rather than escalating them, e.g. to run an
+    /// app's commands under its service account, so the host (or for remote
+    /// hosts, the agent) must be running as root. On Linux the command is
+    /// wrapped in `runuser -u`, or `su -s /bin/sh -c` where `runuser` isn't
+    /// installed. Other platforms aren't supported yet.
+    ///
+    /// For the `Docker` provider, this overrides the provider's user.
+    pub fn as_user(&mut self, name: &str) -> &mut Self {
+        self.opts.run_as = Some(name.into());
+        self
+    }
+
+    /// Run the command in the background without waiting for it to finish.
+    ///
+    /// A detached command's output is discarded, and it is not waited on,
+    /// so the connection to the host is not held open. This is useful for
+    /// "fire and forget" tasks like starting a long running daemon or
+    /// rebooting the host.
+    ///
+    /// The `Child` returned by `exec()` resolves as soon as the command has
+    /// been started, with an empty output stream and a successful
+    /// `ExitStatus` that has no exit code.
+    pub fn detached(&mut self) -> &mut Self {
+        self.opts.detached = true;
+        self
+    }
+
     /// Execute the command.
     ///
     ///## Returns
@@ -190,7 +381,8 @@ impl<H: Host + 'static> Command<H> {
     /// streaming pipe. First we stream the command output, then tack the
     /// `ExitStatus` on as the last frame. Without consuming the output buffer,
     /// we would never be able to get to the last frame, and `ExitStatus` could
-    /// never be resolved.
+    /// never be resolved. `Child::pipe_to()` takes care of this for you by
+    /// forwarding the output to a `Sink` of your choice.
     ///
     ///# Errors
     ///
@@ -201,17 +393,110 @@ impl<H: Host + 'static> Command<H> {
     /// This is the error you'll see if you prematurely drop the output `Stream`
     /// while trying to resolve the `Future<Item = ExitStatus, ...>`.
     pub fn exec(&self) -> Box<Future<Item = Child, Error = Error>> {
-        Box::new(self.host.request(CommandExec { cmd: self.cmd.clone() })
+        Box::new(self.host.request(CommandExec {
+                shell: self.shell.clone(),
+                cmd: self.cmd.clone(),
+                args: self.args.clone(),
+                pipeline: self.pipeline.clone(),
+                opts: self.opts.clone(),
+            })
             .chain_err(|| ErrorKind::Request { endpoint: "Command", func: "exec" }))
     }
 }
 
+impl CommandExec {
+    #[doc(hidden)]
+    pub fn default_shell(&mut self, shell: &[String]) {
+        if self.shell.is_none() && self.args.is_none() && self.pipeline.is_none() {
+            self.shell = Some(shell.to_owned());
+        }
+    }
+}
+
 impl Executable for CommandExec {
     type Response = Child;
     type Future = FutureResult<Self::Response, Error>;
 
     fn exec(self, host: &Local) -> Self::Future {
-        let args: Vec<&str> = self.cmd.iter().map(|a| &**a).collect();
-        host.command().exec(host, &args)
+        if let Some(ref pipeline) = self.pipeline {
+            let stages: Vec<Vec<&str>> = pipeline.iter()
+                .map(|s| s.iter().map(|a| a.as_str()).collect())
+                .collect();
+            let stages: Vec<&[&str]> = stages.iter().map(|s| s.as_slice()).collect();
+            return host.command().pipeline_with(host, &stages, &self.opts);
+        }
+
+        let args = match (&self.args, &self.shell) {
+            (&Some(ref args), _) => {
+                let mut a = vec![self.cmd.as_str()];
+                a.extend(args.iter().map(|a| a.as_str()));
+                a
+            },
+            (&None, &Some(ref shell)) => shell_args(shell, &self.cmd),
+            (&None, &None) => shell_args(&DEFAULT_SHELL, &self.cmd),
+        };
+        host.command().exec_with(host, &args, &self.opts)
+    }
+
+    // Commands run with the default shell are matched by their command
+    // string. Anything else is matched by its arguments, joined by spaces,
+    // so that a custom shell can't sneak in extra work. Extra environment
+    // variables can change what any program does, so those commands are
+    // never allowed.
+    fn command_line(&self) -> Option<String> {
+        if !self.opts.env.is_empty() {
+            return None;
+        }
+
+        if let Some(ref pipeline) = self.pipeline {
+            let stages: Vec<_> = pipeline.iter().map(|s| s.join(" ")).collect();
+            return Some(stages.join(" | "));
+        }
+
+        match (&self.args, &self.shell) {
+            (&Some(ref args), _) => {
+                let mut a = vec![self.cmd.as_str()];
+                a.extend(args.iter().map(|a| a.as_str()));
+                Some(a.join(" "))
+            },
+            (&None, &Some(ref shell)) => Some(shell_args(shell, &self.cmd).join(" ")),
+            (&None, &None) => Some(self.cmd.clone()),
+        }
+    }
+}
+
+// Build the arguments to run `cmd` with `shell`. The command replaces the
+// first `{}` placeholder in the shell, or is appended if there isn't one.
+fn shell_args<'a, S: AsRef<str>>(shell: &'a [S], cmd: &'a str) -> Vec<&'a str> {
+    let mut args: Vec<&str> = shell.iter().map(|a| a.as_ref()).collect();
+
+    match args.iter().position(|a| *a == "{}") {
+        Some(pos) => args[pos] = cmd,
+        None => args.push(cmd),
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shell_args;
+
+    #[test]
+    fn test_shell_args_multi_flag_shell() {
+        let shell = ["/bin/bash", "--login", "-c"];
+        assert_eq!(shell_args(&shell, "echo hi"), vec!["/bin/bash", "--login", "-c", "echo hi"]);
+    }
+
+    #[test]
+    fn test_shell_args_placeholder() {
+        let shell = ["/bin/sh", "-c", "{}", "myscript"];
+        assert_eq!(shell_args(&shell, "echo $0"), vec!["/bin/sh", "-c", "echo $0", "myscript"]);
+    }
+
+    #[test]
+    fn test_shell_args_only_first_placeholder() {
+        let shell = ["/bin/sh", "-c", "{}", "{}"];
+        assert_eq!(shell_args(&shell, "echo $0"), vec!["/bin/sh", "-c", "echo $0", "{}"]);
     }
 }
@@ -0,0 +1,124 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use errors::*;
+use futures::future::FutureResult;
+use host::local::Local;
+use std::process;
+use super::super::hooks;
+use super::{Child, CommandProvider, ExecOptions, Generic};
+
+/// Runs commands inside a running Docker container, using `docker exec`.
+///
+/// To use it, override the command provider of a `Local` host:
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::command::Docker;
+///use intecture_api::prelude::*;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let mut host = Local::new(&handle).wait().unwrap();
+///let mut docker = Docker::new("my_container");
+///docker.user("www-data");
+///host.set_command(docker).unwrap();
+///
+///let cmd = Command::new(&host, "whoami", None);
+///let result = cmd.exec().and_then(|child| child.result().unwrap());
+///println!("{}", core.run(result).unwrap());
+///# }
+///```
+///
+/// The container must already be running. `docker exec` exits with status
+/// 126 or 127 if the command can't be run in the container, and a status
+/// above 125 may also mean that `docker` itself failed.
+///
+/// Note that the `Package` and `Service` providers spawn their own
+/// processes, so they still operate on the local machine.
+pub struct Docker {
+    container: String,
+    user: Option<String>,
+    workdir: Option<String>,
+}
+
+impl Docker {
+    /// Create a new `Docker` provider for the container with the given name
+    /// or id.
+    pub fn new(container: &str) -> Docker {
+        Docker {
+            container: container.into(),
+            user: None,
+            workdir: None,
+        }
+    }
+
+    /// Run commands as the given user, rather than the container's default.
+    pub fn user(&mut self, user: &str) -> &mut Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Run commands in the given directory inside the container.
+    pub fn workdir(&mut self, dir: &str) -> &mut Self {
+        self.workdir = Some(dir.into());
+        self
+    }
+}
+
+impl CommandProvider for Docker {
+    fn available() -> bool {
+        hooks::command("docker", &["--version"])
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+
+    fn exec_with(&self, host: &Local, cmd: &[&str], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let env: Vec<_> = opts.env.iter().map(|&(ref k, ref v)| format!("{}={}", k, v)).collect();
+
+        let mut args = vec!["docker", "exec"];
+        // A user for this command overrides the provider's default user
+        if let Some(user) = opts.run_as.as_ref().or(self.user.as_ref()) {
+            args.push("--user");
+            args.push(user);
+        }
+        if let Some(ref workdir) = self.workdir {
+            args.push("--workdir");
+            args.push(workdir);
+        }
+        args.push(&self.container);
+
+        // `docker exec` passes each argument through untouched, so unlike
+        // SSH there's no need to quote them.
+        if opts.clear_env || !env.is_empty() {
+            args.push("env");
+            if opts.clear_env {
+                args.push("-i");
+            }
+            args.extend(env.iter().map(|e| e.as_str()));
+        }
+        args.extend_from_slice(cmd);
+
+        // The environment and user are set inside the container, not for
+        // the local `docker` process.
+        let mut local_opts = opts.clone();
+        local_opts.clear_env = false;
+        local_opts.env.clear();
+        local_opts.run_as = None;
+
+        Generic.exec_with(host, &args, &local_opts)
+    }
+}
@@ -5,11 +5,17 @@
 // modified, or distributed except according to those terms.
 
 use errors::*;
+use futures::Future;
 use futures::future::{self, FutureResult};
+use futures::sync::oneshot;
 use host::Host;
 use host::local::Local;
-use std::process::{Command, Stdio};
-use super::{Child, CommandProvider};
+use std::io::Read;
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::Instant;
+use super::super::{hooks, ExitStatus};
+use super::{Child, CommandProvider, ExecOptions};
 use tokio_process::CommandExt;
 
 pub struct Generic;
@@ -19,22 +25,216 @@ impl CommandProvider for Generic {
         true
     }
 
-    fn exec(&self, host: &Local, cmd: &[&str]) -> FutureResult<Child, Error> {
+    fn exec_with(&self, host: &Local, cmd: &[&str], opts: &ExecOptions) -> FutureResult<Child, Error> {
         let result = cmd.split_first().ok_or("Invalid shell provided".into());
         let (cmd, cmd_args): (&&str, &[&str]) = match result {
             Ok((c, a)) => (c, a),
             Err(e) => return future::err(e),
         };
 
-        match Command::new(cmd)
-            .args(cmd_args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn_async(host.handle())
-            .chain_err(|| "Command execution failed")
-        {
-            Ok(child) => future::ok(child.into()),
-            Err(e) => future::err(e),
+        let mut command = match build(cmd, cmd_args, opts) {
+            Ok(c) => c,
+            Err(e) => return future::err(e),
+        };
+        if opts.detached {
+            command.stdin(Stdio::null());
         }
+        future::result(spawn(host, command, cmd, cmd_args, opts))
+    }
+
+    fn pipeline_with(&self, host: &Local, stages: &[&[&str]], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let (last, first) = match stages.split_last() {
+            Some(s) => s,
+            None => return future::err("Pipeline has no commands".into()),
+        };
+        if stages.iter().any(|s| s.is_empty()) {
+            return future::err("Invalid pipeline stage provided".into());
+        }
+
+        // Every stage but the last is spawned synchronously, so that its
+        // stdout can be handed to the next stage as a real pipe. Once the
+        // whole pipeline is running, a thread per stage waits for it to
+        // exit.
+        let mut stdin = None;
+        let mut children = Vec::new();
+        for stage in first {
+            let spawned = build(stage[0], &stage[1..], opts).and_then(|mut command| {
+                if let Some(s) = stdin.take() {
+                    command.stdin(Stdio::from(s));
+                }
+                command.stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .chain_err(|| ErrorKind::Spawn { cmd: command_string(stage[0], &stage[1..]) })
+            });
+
+            match spawned {
+                Ok(mut child) => {
+                    stdin = child.stdout.take();
+                    children.push(child);
+                },
+                Err(e) => {
+                    kill_stages(children);
+                    return future::err(e);
+                },
+            }
+        }
+
+        let mut command = match build(last[0], &last[1..], opts) {
+            Ok(c) => c,
+            Err(e) => {
+                kill_stages(children);
+                return future::err(e);
+            },
+        };
+        if let Some(s) = stdin {
+            command.stdin(Stdio::from(s));
+        }
+
+        let child = match spawn(host, command, last[0], &last[1..], opts) {
+            Ok(c) => c,
+            Err(e) => {
+                kill_stages(children);
+                return future::err(e);
+            },
+        };
+
+        let (outputs, statuses): (Vec<_>, Vec<_>) = children.into_iter().map(wait_stage).unzip();
+
+        // The earlier stages are reaped by their threads
+        if opts.detached {
+            return future::ok(child);
+        }
+
+        let output = future::join_all(outputs)
+            .map(|o| o.into_iter().flat_map(|s| s.lines().map(|l| l.to_owned()).collect::<Vec<_>>()).collect())
+            .map_err(|e| Error::with_chain(e, "Pipeline stage output was lost"));
+        let statuses = future::join_all(statuses)
+            .map_err(|e| Error::with_chain(e, "Pipeline stage status was lost"));
+        future::ok(child.pipeline(output, statuses, opts.pipefail))
+    }
+}
+
+// Create a `process::Command` for `cmd` with the environment and user from
+// `opts`.
+fn build(cmd: &str, args: &[&str], opts: &ExecOptions) -> Result<process::Command> {
+    let mut command = match opts.run_as {
+        Some(ref user) => {
+            let wrapped = run_as(user, cmd, args)?;
+            let wrapped: Vec<&str> = wrapped.iter().map(|a| a.as_str()).collect();
+            hooks::command(wrapped[0], &wrapped[1..])
+        },
+        None => hooks::command(cmd, args),
+    };
+    if opts.clear_env {
+        command.env_clear();
+    }
+    for &(ref key, ref value) in &opts.env {
+        command.env(key, value);
+    }
+    Ok(command)
+}
+
+// Wrap `cmd` so that it runs as `user`. This drops privileges, so the
+// process spawning it must be root.
+#[cfg(target_os = "linux")]
+fn run_as(user: &str, cmd: &str, args: &[&str]) -> Result<Vec<String>> {
+    use host::which;
+    use super::quote;
+    use validate;
+
+    validate::user_name(user)?;
+
+    let mut wrapped: Vec<String>;
+    if which("runuser").is_some() {
+        wrapped = vec!["runuser".into(), "-u".into(), user.into(), "--".into(), cmd.into()];
+        wrapped.extend(args.iter().map(|a| (*a).to_owned()));
+    } else {
+        // `su` only takes a command line, so each argument has to be quoted.
+        // Service accounts often have no login shell, hence `-s`.
+        let line: Vec<String> = Some(cmd).iter().chain(args).map(|a| quote(a)).collect();
+        wrapped = vec!["su".into(), "-s".into(), "/bin/sh".into(), "-c".into(), line.join(" "), user.into()];
+    }
+    Ok(wrapped)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_as(_: &str, _: &str, _: &[&str]) -> Result<Vec<String>> {
+    Err("Running a command as another user is only supported on Linux".into())
+}
+
+fn spawn(host: &Local, mut command: process::Command, cmd: &str, cmd_args: &[&str], opts: &ExecOptions) -> Result<Child> {
+    if opts.detached {
+        command.stdout(Stdio::null())
+            .stderr(Stdio::null());
+    } else {
+        command.stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+    }
+
+    let child = command
+        .spawn_async(host.handle())
+        .chain_err(|| ErrorKind::Spawn { cmd: command_string(cmd, cmd_args) })?;
+
+    if opts.detached {
+        // Reap the process in the background once it exits
+        host.handle().spawn(child.map(|_| ()).map_err(|_| ()));
+        Ok(Child::detached())
+    } else {
+        let mut child = Child::from(child);
+        if let Some(lines) = opts.output_buffer {
+            child.set_output_buffer(lines);
+        }
+        Ok(child)
+    }
+}
+
+// Kill and reap the stages of a pipeline that could not be started in full,
+// so that they don't linger waiting on a pipe that nothing will read.
+fn kill_stages(children: Vec<process::Child>) {
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+// Wait for a pipeline stage on its own thread, as it wasn't spawned on the
+// reactor. Resolves to the stage's stderr output and its exit status.
+fn wait_stage(mut child: process::Child) -> (oneshot::Receiver<String>, oneshot::Receiver<ExitStatus>) {
+    let started = Instant::now();
+    let (out_tx, out_rx) = oneshot::channel();
+    let (status_tx, status_rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let mut stderr = String::new();
+        if let Some(mut e) = child.stderr.take() {
+            let _ = e.read_to_string(&mut stderr);
+        }
+        let _ = out_tx.send(stderr);
+
+        let status = match child.wait() {
+            Ok(s) => ExitStatus {
+                success: s.success(),
+                code: s.code(),
+                duration: started.elapsed(),
+            },
+            Err(_) => ExitStatus {
+                success: false,
+                code: None,
+                duration: started.elapsed(),
+            },
+        };
+        let _ = status_tx.send(status);
+    });
+
+    (out_rx, status_rx)
+}
+
+fn command_string(cmd: &str, args: &[&str]) -> String {
+    let mut s = cmd.to_owned();
+    for arg in args {
+        s.push(' ');
+        s.push_str(arg);
     }
+    s
 }
@@ -6,26 +6,114 @@
 
 //! OS abstractions for `Command`.
 
+mod docker;
 mod generic;
+mod ssh;
 
+pub use self::docker::Docker;
 pub use self::generic::Generic;
+pub use self::ssh::Ssh;
 
 use errors::*;
-use futures::future::FutureResult;
+use futures::future::{self, FutureResult};
 use host::local::Local;
+use registry::{Registry, Rejections};
+use std::sync::Arc;
 use super::Child;
 
 #[doc(hidden)]
 pub trait CommandProvider {
     fn available() -> bool where Self: Sized;
-    fn exec(&self, &Local, &[&str]) -> FutureResult<Child, Error>;
+    fn exec_with(&self, &Local, &[&str], &ExecOptions) -> FutureResult<Child, Error>;
+
+    fn exec(&self, host: &Local, cmd: &[&str]) -> FutureResult<Child, Error> {
+        self.exec_with(host, cmd, &ExecOptions::default())
+    }
+
+    /// Run each command in `stages` with its stdout piped to the stdin of
+    /// the next, without a shell.
+    fn pipeline_with(&self, _: &Local, _: &[&[&str]], _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err("This Command provider does not support pipelines".into())
+    }
+}
+
+/// Options that control how a `CommandProvider` spawns a process.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ExecOptions {
+    /// Clear the inherited environment before spawning the process.
+    pub clear_env: bool,
+    /// Spawn the process without capturing its output or waiting for it to
+    /// exit.
+    pub detached: bool,
+    /// Extra environment variables to set for the process, applied after
+    /// the environment is cleared.
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Number of output lines to buffer when streaming output to a remote
+    /// client. Defaults to `DEFAULT_OUTPUT_BUFFER` if `None`.
+    pub output_buffer: Option<usize>,
+    /// Fail a pipeline if any of its stages fail, rather than only if the
+    /// last stage fails.
+    #[serde(default)]
+    pub pipefail: bool,
+    /// Run the process as this user instead of the user that spawns it.
+    #[serde(default)]
+    pub run_as: Option<String>,
+}
+
+/// Resource limits for the processes that providers spawn. See
+/// [`hooks::set_limits()`](../hooks/fn.set_limits.html).
+///
+/// Each limit is applied with `setrlimit()` in the child process before the
+/// command is executed, and sets both the soft and hard limit.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ResourceLimits {
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`).
+    pub cpu_secs: Option<u64>,
+    /// Maximum size of the process' address space in bytes (`RLIMIT_AS`).
+    pub memory: Option<u64>,
+    /// Maximum number of open file descriptors (`RLIMIT_NOFILE`).
+    pub open_files: Option<u64>,
+}
+
+/// A function that creates a custom `CommandProvider`.
+///
+/// The function should return `None` if the provider is not available on the
+/// current host.
+pub type CommandProviderFactory = Fn() -> Result<Option<Box<CommandProvider>>> + Send + Sync;
+
+lazy_static! {
+    static ref REGISTRY: Registry<CommandProviderFactory> = Registry::new();
+}
+
+/// Register a custom `CommandProvider`.
+///
+/// The built-in `Generic` provider is available on every host, so a
+/// provider is only ever chosen if its `priority` is greater than
+/// [`BUILTIN_PRIORITY`](../constant.BUILTIN_PRIORITY.html).
+pub fn register_command_provider<F>(priority: i32, factory: F)
+    where F: Fn() -> Result<Option<Box<CommandProvider>>> + Send + Sync + 'static
+{
+    REGISTRY.register(priority, Arc::new(factory));
 }
 
 #[doc(hidden)]
 pub fn factory() -> Result<Box<CommandProvider>> {
-    if Generic::available() {
-        Ok(Box::new(Generic))
+    let mut rejections = Rejections::new();
+    let provider = REGISTRY.resolve(|| builtin(&mut rejections), |f| f())?;
+    provider.ok_or_else(|| rejections.into_error("Command"))
+}
+
+fn builtin(rejections: &mut Rejections) -> Result<Option<Box<CommandProvider>>> {
+    if rejections.check("generic", Ok(Generic::available())) {
+        Ok(Some(Box::new(Generic)))
     } else {
-        Err(ErrorKind::ProviderUnavailable("Command").into())
+        Ok(None)
     }
 }
+
+// Quote an argument for a POSIX shell.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
@@ -0,0 +1,151 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use errors::*;
+use futures::future::{self, FutureResult};
+use host::local::Local;
+use std::path::Path;
+use std::process;
+use super::super::hooks;
+use super::{quote, Child, CommandProvider, ExecOptions, Generic};
+
+/// Runs commands on another machine over SSH, using the system's `ssh`
+/// client.
+///
+/// This allows `Command` to manage machines that can't run the agent. To use
+/// it, override the command provider of a `Local` host:
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::command::Ssh;
+///use intecture_api::prelude::*;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let mut host = Local::new(&handle).wait().unwrap();
+///let mut ssh = Ssh::new("admin@example.com");
+///ssh.port(2222);
+///host.set_command(ssh).unwrap();
+///
+///let cmd = Command::new(&host, "uptime", None);
+///let result = cmd.exec().and_then(|child| child.result().unwrap());
+///println!("{}", core.run(result).unwrap());
+///# }
+///```
+///
+/// The command's stdout and stderr are streamed back over the SSH channel,
+/// and its exit status is the exit status reported by `ssh`. Note that
+/// `ssh` itself exits with status 255 if the connection fails.
+///
+/// Authentication must not be interactive, so use key based authentication
+/// (e.g. via `ssh-agent` or [`identity()`](#method.identity)).
+///
+/// Note that the `Package` and `Service` providers spawn their own
+/// processes, so they still operate on the local machine.
+pub struct Ssh {
+    destination: String,
+    port: Option<u16>,
+    identity: Option<String>,
+    options: Vec<String>,
+}
+
+impl Ssh {
+    /// Create a new `Ssh` provider for the given destination, e.g.
+    /// `"user@example.com"`.
+    pub fn new(destination: &str) -> Ssh {
+        Ssh {
+            destination: destination.into(),
+            port: None,
+            identity: None,
+            options: Vec::new(),
+        }
+    }
+
+    /// Connect to the given port, rather than the SSH default.
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Authenticate with the private key at the given path.
+    pub fn identity<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.identity = Some(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// Pass an option to `ssh` in `ssh_config` format, e.g.
+    /// `"StrictHostKeyChecking=yes"`.
+    pub fn option(&mut self, option: &str) -> &mut Self {
+        self.options.push(option.into());
+        self
+    }
+}
+
+impl CommandProvider for Ssh {
+    fn available() -> bool {
+        hooks::command("ssh", &["-V"])
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+
+    fn exec_with(&self, host: &Local, cmd: &[&str], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        // We can't know which tools the remote machine has to switch user,
+        // so log in as the right user instead.
+        if opts.run_as.is_some() {
+            return future::err("The Ssh Command provider cannot run commands as another user. Set the user in its destination instead.".into());
+        }
+
+        // `ssh` joins its arguments into a single command line for the
+        // remote shell, so each argument has to be quoted.
+        let mut remote: Vec<String> = Vec::new();
+        if opts.clear_env || !opts.env.is_empty() {
+            remote.push("env".into());
+            if opts.clear_env {
+                remote.push("-i".into());
+            }
+            remote.extend(opts.env.iter().map(|&(ref k, ref v)| quote(&format!("{}={}", k, v))));
+        }
+        remote.extend(cmd.iter().map(|a| quote(a)));
+        let remote = remote.join(" ");
+
+        let port = self.port.map(|p| p.to_string());
+
+        let mut args = vec!["ssh", "-o", "BatchMode=yes"];
+        if let Some(ref port) = port {
+            args.push("-p");
+            args.push(port);
+        }
+        if let Some(ref identity) = self.identity {
+            args.push("-i");
+            args.push(identity);
+        }
+        for option in &self.options {
+            args.push("-o");
+            args.push(option);
+        }
+        args.push(&self.destination);
+        args.push("--");
+        args.push(&remote);
+
+        // The environment is set on the remote machine, not for the local
+        // `ssh` process.
+        let mut local_opts = opts.clone();
+        local_opts.clear_env = false;
+        local_opts.env.clear();
+
+        Generic.exec_with(host, &args, &local_opts)
+    }
+}
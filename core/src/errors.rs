@@ -10,6 +10,7 @@ use futures::Future;
 use regex;
 use serde_json;
 use std::{error, io};
+use std::time::Duration;
 
 error_chain! {
     foreign_links {
@@ -19,9 +20,15 @@ error_chain! {
     }
 
     errors {
-        Command(out: String) {
+        Command {
+            stdout: String,
+            stderr: String,
+            code: Option<i32>,
+        } {
             description("Command returned non-zero exit code"),
-            display("Command returned non-zero exit code with output: {}", out),
+            display("Command returned non-zero exit code ({}) with output: {}",
+                code.map_or_else(|| "none".to_owned(), |c| c.to_string()),
+                if stderr.is_empty() { stdout } else { stderr }),
         }
 
         InvalidTelemetryKey {
@@ -32,11 +39,31 @@ error_chain! {
             display("Provided key '{}' not found in {} output", key, cmd),
         }
 
+        InvalidName(n: String) {
+            description("Invalid name"),
+            display("Invalid name '{}'. Names may only contain letters, numbers and safe punctuation", n),
+        }
+
+        ReadOnly {
+            description("Refusing to change a read only host"),
+            display("Refusing to send a request that could change a read only host"),
+        }
+
         MutRef(h: &'static str) {
             description("Unable to obtain mutable reference"),
             display("Unable to obtain mutable reference to {}", h),
         }
 
+        NotFound(p: String) {
+            description("File not found"),
+            display("File not found: {}", p),
+        }
+
+        AmbiguousProvider(p: Vec<&'static str>) {
+            description("Several providers are available"),
+            display("Several package providers are available ({}); choose one with `Host::set_package()` or `Package::using()`", p.join(", ")),
+        }
+
         ProviderUnavailable(p: &'static str) {
             description("No providers available"),
             display("No providers available for {}", p),
@@ -50,11 +77,57 @@ error_chain! {
             display("Could not run {}::{}() on host", endpoint, func),
         }
 
+        Resolve {
+            host: String,
+        } {
+            description("Could not resolve host address"),
+            display("Could not resolve host address '{}'", host),
+        }
+
+        Spawn {
+            cmd: String,
+        } {
+            description("Could not spawn command"),
+            display("Could not spawn command `{}`", cmd),
+        }
+
         Remote(e: String) {
             description("Error running command on remote host"),
             display("Error running command on remote host: {}", e),
         }
 
+        VersionMismatch {
+            local: u32,
+            remote: u32,
+        } {
+            description("Protocol version mismatch"),
+            display("Protocol version mismatch: this client speaks v{}, but the agent speaks v{}", local, remote),
+        }
+
+        WaitTimeout(w: String) {
+            description("Timed out waiting"),
+            display("Timed out waiting for {}", w),
+        }
+
+        TelemetryTimeout(d: Duration, last_command: Option<String>) {
+            description("Telemetry load timed out"),
+            display("Telemetry did not load within {}s, which may be caused by a hung system command such as `df` on a dead network mount{}",
+                d.as_secs(), match *last_command {
+                    Some(ref c) => format!(". The last command it started was `{}`", c),
+                    None => String::new(),
+                }),
+        }
+
+        TelemetryNotLoaded {
+            description("Telemetry has not been loaded"),
+            display("Telemetry has not been loaded for this host. See `Local::load_telemetry()`"),
+        }
+
+        RequestTimeout(d: Duration) {
+            description("Request timed out"),
+            display("Request did not complete within {}ms", d.as_secs() * 1000 + u64::from(d.subsec_nanos() / 1_000_000)),
+        }
+
         SystemCommand(c: &'static str) {
             description("Error running system command"),
             display("Error running system command '{}'", c),
@@ -0,0 +1,618 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Endpoint for managing files.
+//!
+//! A file is represented by the `File` struct.
+
+use bytes::Bytes;
+use command::hooks;
+use errors::*;
+use futures::{future, stream, Async, Future, Stream};
+use futures::future::FutureResult;
+use futures::sink::Sink;
+use futures::sync::mpsc;
+use host::Host;
+use host::local::Local;
+use message::{FromMessage, IntoMessage, InMessage};
+use regex::Regex;
+use request::{Executable, Request};
+use serde_json as json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::result;
+use tokio_core::reactor::Handle;
+use tokio_proto::streaming::{Body, Message};
+use users::{get_current_uid, get_user_by_name, get_user_by_uid};
+
+// Size of each chunk read from disk
+const CHUNK_SIZE: usize = 8192;
+// Prefix of the body chunk that reports an error while streaming a file.
+// File chunks are hex encoded, so they can never start with it.
+const ERROR_CHUNK: &'static [u8] = b"Error:";
+
+/// Represents a file on a host.
+///
+///## Example
+///
+/// Download a file from a host.
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::prelude::*;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = Local::new(&handle).wait().unwrap();
+///
+///let file = File::new(&host, "/etc/hosts");
+///core.run(file.download("hosts")).unwrap();
+///# }
+///```
+pub struct File<H: Host> {
+    host: H,
+    path: String,
+}
+
+/// Attributes to enforce on a file, alongside its contents.
+///
+/// Attributes that are `None` are left as they are.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FileOptions {
+    /// Permission bits, e.g. `0o644`
+    pub mode: Option<u32>,
+    /// Name of the user that should own the file
+    pub owner: Option<String>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct FileDownload {
+    path: String,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct FileEnsureLine {
+    path: String,
+    line: String,
+    pattern: Option<String>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct FileMatches {
+    path: String,
+    hash: String,
+    options: FileOptions,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage)]
+pub struct FileWrite {
+    path: String,
+    options: FileOptions,
+    // The file's contents, which are streamed as the message body in hex
+    // encoded chunks, as the protocol is newline delimited
+    #[serde(skip)]
+    content: Option<Body<Bytes, io::Error>>,
+}
+
+// The contents of a file, streamed in chunks.
+#[doc(hidden)]
+pub struct FileStream {
+    stream: Box<Stream<Item = Vec<u8>, Error = Error>>,
+}
+
+impl<H: Host + 'static> File<H> {
+    /// Create a new `File` for the given path on the host.
+    pub fn new(host: &H, path: &str) -> File<H> {
+        File {
+            host: host.clone(),
+            path: path.into(),
+        }
+    }
+
+    /// Download the file from the host to `local_path`.
+    ///
+    /// The file is written to a temporary file alongside `local_path`, then
+    /// renamed into place once it has been received in full, so
+    /// `local_path` is never left half written. If the file does not exist
+    /// on the host, an error is returned and `local_path` is left untouched.
+    pub fn download<P: AsRef<Path>>(&self, local_path: P) -> Box<Future<Item = (), Error = Error>> {
+        let local_path = local_path.as_ref().to_owned();
+        let tmp_path = temp_path(&local_path);
+        let tmp_path1 = tmp_path.clone();
+        let tmp_path2 = tmp_path.clone();
+
+        Box::new(self.host.request(FileDownload { path: self.path.clone() })
+            .chain_err(|| ErrorKind::Request { endpoint: "File", func: "download" })
+            .and_then(move |contents| {
+                let fh = match fs::File::create(&tmp_path)
+                    .chain_err(|| format!("Could not create file {}", tmp_path.display()))
+                {
+                    Ok(fh) => fh,
+                    Err(e) => return Box::new(future::err(e)) as Box<Future<Item = _, Error = _>>,
+                };
+
+                Box::new(contents.stream.fold(fh, |mut fh, chunk| {
+                    fh.write_all(&chunk)
+                        .chain_err(|| "Could not write file contents")
+                        .map(|_| fh)
+                }))
+            })
+            .and_then(move |_| fs::rename(&tmp_path1, &local_path)
+                .chain_err(|| format!("Could not move file into place at {}", local_path.display())))
+            .map_err(move |e| {
+                let _ = fs::remove_file(&tmp_path2);
+                e
+            }))
+    }
+
+    /// Make sure the file's contents are exactly `content`, and that it has
+    /// the given `options`.
+    ///
+    /// The host compares a hash of the file with a hash of `content`, so the
+    /// file is only sent to the host if it needs to be written.
+    ///
+    ///## Idempotence
+    ///
+    /// This function is idempotent. It resolves to `true` if the file was
+    /// written, or `false` if it was already up to date.
+    pub fn ensure_content(&self, content: &[u8], options: &FileOptions) -> Box<Future<Item = bool, Error = Error>> {
+        let host = self.host.clone();
+        let path = self.path.clone();
+        let write = FileWrite {
+            path: self.path.clone(),
+            options: options.clone(),
+            content: Some(content_body(content)),
+        };
+
+        Box::new(self.host.request(FileMatches { path, hash: sha256_hex(content), options: options.clone() })
+            .chain_err(|| ErrorKind::Request { endpoint: "File", func: "ensure_content" })
+            .and_then(move |matches| if matches {
+                Box::new(future::ok(false)) as Box<Future<Item = _, Error = _>>
+            } else {
+                Box::new(host.request(write)
+                    .chain_err(|| ErrorKind::Request { endpoint: "File", func: "ensure_content" })
+                    .map(|_| true))
+            }))
+    }
+
+    /// Make sure the file contains `line`, e.g. "vm.swappiness = 10".
+    ///
+    /// If `pattern` is given, the last line that matches the regex is
+    /// replaced with `line`. Otherwise, or if no line matches, `line` is
+    /// appended to the file. The file is created if it doesn't exist, and
+    /// an existing file keeps its mode and owner.
+    ///
+    /// The file is read and written by the host, so it is never transferred
+    /// in full.
+    ///
+    ///## Idempotence
+    ///
+    /// This function is idempotent. It resolves to `true` if the file was
+    /// changed, or `false` if it already contained `line`.
+    pub fn ensure_line(&self, line: &str, pattern: Option<&str>) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(self.host.request(FileEnsureLine {
+                path: self.path.clone(),
+                line: line.into(),
+                pattern: pattern.map(|p| p.into()),
+            })
+            .chain_err(|| ErrorKind::Request { endpoint: "File", func: "ensure_line" }))
+    }
+}
+
+impl Executable for FileDownload {
+    type Response = FileStream;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, _: &Local) -> Self::Future {
+        let mut fh = match fs::File::open(&self.path) {
+            Ok(fh) => fh,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return future::err(ErrorKind::NotFound(self.path).into());
+            },
+            Err(e) => return future::err(Error::with_chain(e, "Could not open file")),
+        };
+
+        let stream = stream::poll_fn(move || {
+            let mut buf = vec![0; CHUNK_SIZE];
+            match fh.read(&mut buf).chain_err(|| "Could not read file")? {
+                0 => Ok(Async::Ready(None)),
+                n => {
+                    buf.truncate(n);
+                    Ok(Async::Ready(Some(buf)))
+                },
+            }
+        });
+
+        future::ok(FileStream { stream: Box::new(stream) })
+    }
+}
+
+impl Executable for FileEnsureLine {
+    type Response = bool;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, _: &Local) -> Self::Future {
+        future::result(ensure_line(Path::new(&self.path), &self.line, self.pattern.as_ref().map(|p| p.as_str())))
+    }
+}
+
+impl Executable for FileMatches {
+    type Response = bool;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, _: &Local) -> Self::Future {
+        future::result(file_matches(&self.path, &self.hash, &self.options))
+    }
+}
+
+impl Executable for FileWrite {
+    type Response = ();
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn set_body(&mut self, body: Body<Bytes, io::Error>) -> Option<Body<Bytes, io::Error>> {
+        self.content = Some(body);
+        None
+    }
+
+    fn exec(self, _: &Local) -> Self::Future {
+        match self.content {
+            Some(content) => write_file_stream(PathBuf::from(self.path), self.options, content),
+            None => Box::new(future::err("File contents missing from request".into())),
+        }
+    }
+}
+
+impl IntoMessage for FileWrite {
+    fn into_msg(mut self, handle: &Handle) -> Result<InMessage> {
+        let content = self.content.take();
+        let msg = Request::FileWrite(self).into_msg(handle)?;
+
+        Ok(match content {
+            Some(body) => Message::WithBody(msg.into_inner(), body),
+            None => msg,
+        })
+    }
+}
+
+impl FromMessage for FileStream {
+    fn from_msg(mut msg: InMessage) -> Result<Self> {
+        let body = msg.take_body().ok_or("File download reply missing body stream")?;
+        let stream = body.then(|r| r.chain_err(|| "Could not receive file contents"))
+            .and_then(|chunk| if chunk.starts_with(ERROR_CHUNK) {
+                let e = String::from_utf8_lossy(&chunk[ERROR_CHUNK.len()..]).into_owned();
+                Err(ErrorKind::Remote(e).into())
+            } else {
+                hex_decode(&chunk)
+            });
+
+        Ok(FileStream { stream: Box::new(stream) })
+    }
+}
+
+impl IntoMessage for FileStream {
+    fn into_msg(self, handle: &Handle) -> Result<InMessage> {
+        let (tx, body) = Body::pair();
+
+        // Body chunks are newline delimited, so binary data must be encoded.
+        // If the file can't be read, the error is sent as the last chunk so
+        // that the client can abort the download.
+        let mut failed = false;
+        let stream = self.stream.then(|r| Ok::<_, Error>(r))
+            .take_while(move |r| {
+                let more = !failed;
+                failed = r.is_err();
+                Ok(more)
+            })
+            .map(|r| Ok(Bytes::from(match r {
+                Ok(chunk) => hex_encode(&chunk),
+                Err(e) => {
+                    let cause: Vec<_> = e.iter().map(|e| e.to_string()).collect();
+                    let mut chunk = ERROR_CHUNK.to_vec();
+                    chunk.extend(cause.join(": ").replace('\n', " ").into_bytes());
+                    chunk
+                },
+            })))
+            .forward(tx.sink_map_err(|e| Error::with_chain(e, "Could not forward file contents to Body")))
+            .map(|_| ())
+            .map_err(|e| warn!("Could not send file contents: {}", e));
+
+        handle.spawn(stream);
+
+        let value: result::Result<_, ()> = Ok(());
+        Ok(Message::WithBody(json::to_value(value).unwrap(), body))
+    }
+}
+
+// Add `line` to the file at `path`, or replace the last line that matches
+// `pattern` with it. Returns whether the file was changed.
+fn ensure_line(path: &Path, line: &str, pattern: Option<&str>) -> Result<bool> {
+    if line.contains('\n') {
+        return Err("Line must not contain a newline".into());
+    }
+    let regex = match pattern {
+        Some(p) => Some(Regex::new(p).chain_err(|| format!("Invalid line pattern '{}'", p))?),
+        None => None,
+    };
+
+    let mut content = String::new();
+    let mut options = FileOptions::default();
+    match fs::File::open(path) {
+        Ok(mut fh) => {
+            fh.read_to_string(&mut content).chain_err(|| "Could not read file")?;
+
+            // Keep the file's attributes, as it is replaced when written
+            let meta = fh.metadata().chain_err(|| "Could not read file metadata")?;
+            options.mode = Some(meta.permissions().mode() & 0o7777);
+            if meta.uid() != get_current_uid() {
+                options.owner = get_user_by_uid(meta.uid()).map(|u| u.name().to_owned());
+            }
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(Error::with_chain(e, "Could not open file")),
+    }
+
+    let mut lines: Vec<&str> = content.lines().collect();
+    if lines.iter().any(|l| *l == line) {
+        return Ok(false);
+    }
+
+    let matched = regex.and_then(|r| lines.iter().rposition(|l| r.is_match(l)));
+    match matched {
+        Some(pos) => lines[pos] = line,
+        None => lines.push(line),
+    }
+
+    let mut new_content = lines.join("\n");
+    new_content.push('\n');
+    write_file(path, new_content.as_bytes(), &options)?;
+    Ok(true)
+}
+
+// Check whether the file at `path` has the content hash `hash` and the
+// attributes in `options`. A missing file never matches.
+fn file_matches(path: &str, hash: &str, options: &FileOptions) -> Result<bool> {
+    let mut fh = match fs::File::open(path) {
+        Ok(fh) => fh,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(Error::with_chain(e, "Could not open file")),
+    };
+
+    let meta = fh.metadata().chain_err(|| "Could not read file metadata")?;
+    if let Some(mode) = options.mode {
+        if meta.permissions().mode() & 0o7777 != mode {
+            return Ok(false);
+        }
+    }
+    if let Some(ref owner) = options.owner {
+        match get_user_by_uid(meta.uid()) {
+            Some(ref user) if user.name() == owner => (),
+            _ => return Ok(false),
+        }
+    }
+
+    let mut hasher = Sha256::default();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match fh.read(&mut buf).chain_err(|| "Could not read file")? {
+            0 => break,
+            n => hasher.input(&buf[..n]),
+        }
+    }
+
+    Ok(String::from_utf8(hex_encode(&hasher.result())).unwrap() == hash)
+}
+
+// Write `content` to a temporary file alongside `path`, apply `options` to
+// it, then rename it into place.
+fn write_file(path: &Path, content: &[u8], options: &FileOptions) -> Result<()> {
+    let tmp_path = temp_path(path);
+
+    let result = fs::File::create(&tmp_path)
+        .and_then(|mut fh| fh.write_all(content))
+        .chain_err(|| format!("Could not write file {}", tmp_path.display()))
+        .and_then(|_| move_into_place(&tmp_path, path, options));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+// Like `write_file()`, but the contents are streamed in hex encoded chunks.
+fn write_file_stream(path: PathBuf, options: FileOptions, content: Body<Bytes, io::Error>) -> Box<Future<Item = (), Error = Error>> {
+    let tmp_path = temp_path(&path);
+    let tmp_path1 = tmp_path.clone();
+    let tmp_path2 = tmp_path.clone();
+
+    let fh = match fs::File::create(&tmp_path)
+        .chain_err(|| format!("Could not write file {}", tmp_path.display()))
+    {
+        Ok(fh) => fh,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(content.then(|r| r.chain_err(|| "Could not receive file contents"))
+        .fold(fh, |mut fh, chunk| {
+            hex_decode(&chunk)
+                .and_then(|chunk| fh.write_all(&chunk).chain_err(|| "Could not write file contents"))
+                .map(|_| fh)
+        })
+        .and_then(move |_| move_into_place(&tmp_path1, &path, &options))
+        .map_err(move |e| {
+            let _ = fs::remove_file(&tmp_path2);
+            e
+        }))
+}
+
+// Apply `options` to the fully written temporary file at `tmp_path`, then
+// rename it to `path`.
+fn move_into_place(tmp_path: &Path, path: &Path, options: &FileOptions) -> Result<()> {
+    if let Some(mode) = options.mode {
+        fs::set_permissions(tmp_path, fs::Permissions::from_mode(mode))
+            .chain_err(|| "Could not set file mode")?;
+    }
+    if let Some(ref owner) = options.owner {
+        chown(tmp_path, owner)?;
+    }
+
+    fs::rename(tmp_path, path)
+        .chain_err(|| format!("Could not move file into place at {}", path.display()))
+}
+
+// Build a message body that streams `content` in hex encoded chunks.
+fn content_body(content: &[u8]) -> Body<Bytes, io::Error> {
+    let chunks: Vec<_> = content.chunks(CHUNK_SIZE).collect();
+
+    // The channel has room for every chunk, so it can be filled up front
+    let (mut tx, rx) = mpsc::channel(chunks.len());
+    for chunk in chunks {
+        tx.try_send(Ok(Bytes::from(hex_encode(chunk)))).expect("Body channel has room for every chunk");
+    }
+
+    Body::from(rx)
+}
+
+fn chown(path: &Path, owner: &str) -> Result<()> {
+    // Resolve the name ourselves, so that it is never interpreted as a
+    // `chown` option.
+    let user = get_user_by_name(owner).ok_or_else(|| format!("Unknown user '{}'", owner))?;
+
+    let uid = user.uid().to_string();
+    let path = path.to_str().ok_or("Path is not valid UTF-8")?;
+    let status = hooks::command("chown", &[&uid, path])
+        .status()
+        .chain_err(|| ErrorKind::SystemCommand("chown"))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::SystemCommand("chown").into())
+    }
+}
+
+// Build a path for a temporary file in the same directory as `path`, so that
+// it can be renamed into place atomically.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.download", name))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(data);
+    String::from_utf8(hex_encode(&hasher.result())).unwrap()
+}
+
+fn hex_encode(data: &[u8]) -> Vec<u8> {
+    const DIGITS: &'static [u8] = b"0123456789abcdef";
+
+    let mut out = Vec::with_capacity(data.len() * 2);
+    for b in data {
+        out.push(DIGITS[(b >> 4) as usize]);
+        out.push(DIGITS[(b & 0xf) as usize]);
+    }
+    out
+}
+
+fn hex_decode(data: &[u8]) -> Result<Vec<u8>> {
+    fn nibble(c: u8) -> Result<u8> {
+        match c {
+            c if c >= b'0' && c <= b'9' => Ok(c - b'0'),
+            c if c >= b'a' && c <= b'f' => Ok(c - b'a' + 10),
+            _ => Err("Invalid file chunk encoding".into()),
+        }
+    }
+
+    if data.len() % 2 != 0 {
+        return Err("Invalid file chunk encoding".into());
+    }
+
+    data.chunks(2)
+        .map(|pair| Ok(nibble(pair[0])? << 4 | nibble(pair[1])?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::*;
+    use futures::{stream, Stream};
+    use message::{FromMessage, IntoMessage};
+    use tokio_core::reactor::Core;
+    use message::InMessage;
+    use request::Request;
+    use std::{env, fs, process};
+    use super::{content_body, write_file_stream, FileOptions, FileStream, FileWrite};
+
+    #[test]
+    fn test_file_stream_forwards_read_errors() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let chunks = vec![Ok(b"first chunk".to_vec()), Err("Could not read file".into())];
+        let file = FileStream { stream: Box::new(stream::iter_result(chunks)) };
+        let msg = file.into_msg(&handle).unwrap();
+        let file = FileStream::from_msg(msg).unwrap();
+
+        let (chunk, rest) = core.run(file.stream.into_future()).map_err(|(e, _)| e).unwrap();
+        assert_eq!(chunk.unwrap(), b"first chunk");
+        match core.run(rest.into_future()) {
+            Err((Error(ErrorKind::Remote(e), _), _)) => assert_eq!(e, "Could not read file"),
+            _ => panic!("Read error was not forwarded"),
+        }
+    }
+
+    #[test]
+    fn test_file_write_streams_content() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let path = env::temp_dir().join(format!("intecture-file-write-{}", process::id()));
+        // Large enough to be sent in several chunks
+        let content: Vec<u8> = (0..20000).map(|i| (i % 256) as u8).collect();
+        let write = FileWrite {
+            path: path.to_string_lossy().into_owned(),
+            options: FileOptions::default(),
+            content: Some(content_body(&content)),
+        };
+
+        // Send the request through a message, as a remote host would
+        let msg: InMessage = write.into_msg(&handle).unwrap();
+        let write = match Request::from_msg_with_handle(msg, &handle).unwrap() {
+            Request::FileWrite(w) => w,
+            _ => panic!("Request was not decoded as FileWrite"),
+        };
+        core.run(write_file_stream(path.clone(), write.options, write.content.unwrap())).unwrap();
+
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, content);
+    }
+}
@@ -6,73 +6,204 @@
 
 //! A connection to the local machine.
 
-use command::CommandProvider;
+use command::{self, Child, CommandProvider};
 use errors::*;
 use futures::{future, Future};
-use message::IntoMessage;
-use package::PackageProvider;
-use request::Executable;
-use service::ServiceProvider;
-use std::thread::sleep;
-use std::time::Duration;
-use std::sync::Arc;
-use super::{Host, Providers};
+use futures::future::FutureResult;
+use message::{FromMessage, IntoMessage, InMessage};
+use package::{self, PackageProvider};
+use request::{Executable, Request};
+use service::{self, ServiceProvider};
+use std::rc::Rc;
+use super::{check_read_only, get_mut, refuses, with_timeout, Host, HostId, HostOptions, HostTarget, Providers};
 use telemetry::{self, Telemetry};
 use tokio_core::reactor::Handle;
+use tokio_service::Service;
 
 /// A `Host` type that talks directly to the local machine.
 #[derive(Clone)]
 pub struct Local {
-    inner: Arc<Inner>,
+    inner: Rc<Inner>,
     handle: Handle,
 }
 
 struct Inner {
+    options: HostOptions,
     providers: Option<Providers>,
     telemetry: Option<Telemetry>,
 }
 
 impl Local {
     /// Create a new `Host` targeting the local machine.
+    ///
+    /// Telemetry is loaded on a separate thread while the command and
+    /// package providers are probed, as both shell out to several system
+    /// commands.
     pub fn new(handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
         let mut host = Local {
-            inner: Arc::new(Inner {
+            inner: Rc::new(Inner {
+                options: HostOptions::default(),
                 providers: None,
                 telemetry: None,
             }),
             handle: handle.clone(),
         };
 
-        Box::new(telemetry::Telemetry::load(&host)
+        let load = telemetry::load_local_async(handle);
+
+        // Only the `Service` provider depends on telemetry
+        let providers = command::factory()
+            .and_then(|command| Ok((command, package::factory()?)));
+
+        Box::new(load
             .chain_err(|| "Could not load telemetry for host")
-            .and_then(|t| {
+            .and_then(move |t| {
+                let (command, package) = providers?;
+                let service = service::factory(&t)?;
                 {
-                    let inner = Arc::get_mut(&mut host.inner).unwrap();
-                    inner.providers = match super::get_providers(&t) {
-                        Ok(p) => Some(p),
-                        Err(e) => return future::err(e),
-                    };
+                    let inner = Rc::get_mut(&mut host.inner).unwrap();
+                    inner.providers = Some(Providers {
+                        command: command,
+                        package: package,
+                        service: service,
+                    });
                     inner.telemetry = Some(t);
                 }
-                future::ok(host)
+                Ok(host)
             }))
     }
+
+    /// Create a new `Host` targeting the local machine, without loading
+    /// telemetry.
+    ///
+    /// Loading telemetry probes the machine with several system commands,
+    /// and `new()` fails if any of them do. Use this constructor if you only
+    /// need to run `Command`s, or if telemetry probing is broken on this
+    /// machine. Telemetry can be loaded later with
+    /// [`load_telemetry()`](#method.load_telemetry).
+    ///
+    /// Until telemetry is loaded, `telemetry()` will panic and
+    /// [`try_telemetry()`](#method.try_telemetry) will return an error.
+    /// `Service` operations, and other requests that depend on telemetry,
+    /// will return an error too, as the `Service` provider is chosen based
+    /// on telemetry.
+    pub fn new_without_telemetry(handle: &Handle) -> Result<Self> {
+        Ok(Local {
+            inner: Rc::new(Inner {
+                options: HostOptions::default(),
+                providers: Some(Providers {
+                    command: command::factory()?,
+                    package: package::factory()?,
+                    service: Box::new(NoTelemetry),
+                }),
+                telemetry: None,
+            }),
+            handle: handle.clone(),
+        })
+    }
+
+    /// Load telemetry for a host created by
+    /// [`new_without_telemetry()`](#method.new_without_telemetry), and
+    /// choose its `Service` provider.
+    pub fn load_telemetry(mut self) -> Box<Future<Item = Self, Error = Error>> {
+        Box::new(telemetry::Telemetry::load(&self)
+            .chain_err(|| "Could not load telemetry for host")
+            .and_then(move |t| {
+                let service = service::factory(&t)?;
+                {
+                    let inner = get_mut(&mut self.inner, "Local")?;
+                    inner.providers.as_mut().unwrap().service = service;
+                    inner.telemetry = Some(t);
+                }
+                Ok(self)
+            }))
+    }
+
+    /// Get `Telemetry` for this host, or an error if it hasn't been loaded
+    /// yet.
+    ///
+    /// Unlike [`telemetry()`](trait.Host.html#tymethod.telemetry), this
+    /// doesn't panic for hosts created with
+    /// [`new_without_telemetry()`](#method.new_without_telemetry).
+    pub fn try_telemetry(&self) -> Result<&Telemetry> {
+        self.inner.telemetry.as_ref().ok_or_else(|| ErrorKind::TelemetryNotLoaded.into())
+    }
+}
+
+// Placeholder `Service` provider for hosts that haven't loaded telemetry.
+struct NoTelemetry;
+
+impl NoTelemetry {
+    fn error() -> Error {
+        ErrorKind::TelemetryNotLoaded.into()
+    }
+}
+
+impl ServiceProvider for NoTelemetry {
+    fn available(_: &Telemetry) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn running(&self, _: &Local, _: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(future::err(Self::error()))
+    }
+
+    fn action(&self, _: &Local, _: &str, _: &str) -> FutureResult<Child, Error> {
+        future::err(Self::error())
+    }
+
+    fn enabled(&self, _: &Local, _: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(future::err(Self::error()))
+    }
+
+    fn enable(&self, _: &Local, _: &str) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(future::err(Self::error()))
+    }
+
+    fn disable(&self, _: &Local, _: &str) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(future::err(Self::error()))
+    }
+
+    fn logs(&self, _: &Local, _: &str, _: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        Box::new(future::err(Self::error()))
+    }
 }
 
 impl Host for Local {
     fn telemetry(&self) -> &Telemetry {
-        self.inner.telemetry.as_ref().unwrap()
+        self.inner.telemetry.as_ref()
+            .expect("Telemetry has not been loaded for this host. See `Local::load_telemetry()`")
     }
 
     fn handle(&self) -> &Handle {
         &self.handle
     }
 
+    fn id(&self) -> HostId {
+        HostId {
+            target: HostTarget::Local,
+            machine_id: self.inner.telemetry.as_ref().and_then(|t| t.machine_id.clone()),
+        }
+    }
+
     #[doc(hidden)]
     fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
-        where R: Executable + IntoMessage + 'static
+        where R: Executable + FromMessage + IntoMessage + 'static
     {
-        Box::new(request.exec(self).and_then(|r| future::ok(r)))
+        if refuses(self, request.mutates(), request.command_line()) {
+            return Box::new(future::err(ErrorKind::ReadOnly.into()));
+        }
+
+        with_timeout(request.exec(self), self.inner.options.default_timeout, &self.handle, |_| ())
+    }
+
+    #[doc(hidden)]
+    fn update_telemetry<F: FnOnce(&mut Telemetry)>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Local")?;
+        if let Some(ref mut t) = inner.telemetry {
+            f(t);
+        }
+        Ok(())
     }
 
     fn command(&self) -> &Box<CommandProvider> {
@@ -80,18 +211,9 @@ impl Host for Local {
     }
 
     fn set_command<P: CommandProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().command = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
-        }
-
-        Err(ErrorKind::MutRef("Local").into())
+        let inner = get_mut(&mut self.inner, "Local")?;
+        inner.providers.as_mut().unwrap().command = Box::new(provider);
+        Ok(())
     }
 
     fn package(&self) -> &Box<PackageProvider> {
@@ -99,18 +221,9 @@ impl Host for Local {
     }
 
     fn set_package<P: PackageProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().package = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
-        }
-
-        Err(ErrorKind::MutRef("Local").into())
+        let inner = get_mut(&mut self.inner, "Local")?;
+        inner.providers.as_mut().unwrap().package = Box::new(provider);
+        Ok(())
     }
 
     fn service(&self) -> &Box<ServiceProvider> {
@@ -118,17 +231,34 @@ impl Host for Local {
     }
 
     fn set_service<P: ServiceProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().service = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
+        let inner = get_mut(&mut self.inner, "Local")?;
+        inner.providers.as_mut().unwrap().service = Box::new(provider);
+        Ok(())
+    }
+
+    fn options(&self) -> &HostOptions {
+        &self.inner.options
+    }
+
+    fn options_mut(&mut self) -> Result<&mut HostOptions> {
+        Ok(&mut get_mut(&mut self.inner, "Local")?.options)
+    }
+}
+
+impl Service for Local {
+    type Request = InMessage;
+    type Response = InMessage;
+    type Error = Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, mut req: Self::Request) -> Self::Future {
+        if let Err(e) = check_read_only(self, &mut req) {
+            return Box::new(future::err(e));
         }
 
-        Err(ErrorKind::MutRef("Local").into())
+        match Request::from_msg_with_handle(req, &self.handle) {
+            Ok(request) => request.exec(self),
+            Err(e) => Box::new(future::err(e)),
+        }
     }
 }
@@ -7,29 +7,172 @@
 //! Manages the connection between the API and a server.
 
 pub mod local;
+pub mod recording;
 pub mod remote;
 
 use command;
 use errors::*;
-use futures::Future;
-use message::IntoMessage;
+use hostname::get_hostname;
+use futures::{future, stream, Future, Stream};
+use futures::future::{Either, FutureResult};
+use futures::sync::oneshot;
+use message::{drain_body, FromMessage, IntoMessage, InMessage};
 use package;
-use request::Executable;
+use request::{Executable, Request};
+use serde_json as json;
 use service;
-use telemetry;
-use tokio_core::reactor::Handle;
+use std::{env, fs, process, thread};
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use telemetry::{self, OsFamily};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_process::CommandExt;
+use tokio_proto::streaming::Message;
+use self::local::Local;
+use validate;
 
 /// Trait for local and remote host types.
 pub trait Host: Clone {
     /// Get `Telemetry` for this host.
+    ///
+    /// This panics for a `Local` host created with
+    /// [`Local::new_without_telemetry()`](local/struct.Local.html#method.new_without_telemetry)
+    /// until its telemetry has been loaded. Use
+    /// [`Local::try_telemetry()`](local/struct.Local.html#method.try_telemetry)
+    /// for an error instead.
     fn telemetry(&self) -> &telemetry::Telemetry;
 
     /// Get `Handle` to Tokio reactor.
     fn handle(&self) -> &Handle;
 
+    /// Get an identity for this host that can be used as a `HashMap` key,
+    /// e.g. to cache results per host.
+    ///
+    /// Clones of a host share its identity, as do separate connections to
+    /// the same address. See [`HostId`](struct.HostId.html).
+    fn id(&self) -> HostId;
+
+    /// Get a `Stream` of fresh `Telemetry` snapshots, one every `interval`.
+    ///
+    /// Unlike [`telemetry()`](#tymethod.telemetry), which is loaded once when
+    /// the host is created, each item of this stream reloads telemetry from
+    /// the host. This is useful for watching volatile data such as memory
+    /// or filesystem usage change over time. The first snapshot is yielded
+    /// after one `interval` has elapsed.
+    fn telemetry_stream(&self, interval: Duration) -> Box<Stream<Item = telemetry::Telemetry, Error = Error>>
+        where Self: 'static
+    {
+        let host = self.clone();
+
+        match Interval::new(interval, self.handle()) {
+            Ok(i) => Box::new(i
+                .map_err(|e| Error::with_chain(e, ErrorKind::Msg("Telemetry interval failed".into())))
+                .and_then(move |_| telemetry::Telemetry::load(&host))),
+            Err(e) => Box::new(stream::once(Err(Error::with_chain(e, ErrorKind::Msg("Could not create telemetry interval".into()))))),
+        }
+    }
+
+    /// Check that the host is reachable and responding to requests.
+    ///
+    /// This is much cheaper than loading `Telemetry`, so it is useful as a
+    /// liveness check before issuing real work. The response also carries
+    /// the agent's version, which can be compared with
+    /// [`VERSION`](../constant.VERSION.html) to detect version skew.
+    fn ping(&self) -> Box<Future<Item = PingResponse, Error = Error>> {
+        Box::new(self.request(Ping)
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "ping" }))
+    }
+
+    /// Set the host's hostname.
+    ///
+    /// The hostname is set persistently, so it survives a reboot, using
+    /// `hostnamectl` on systemd hosts, `scutil` on macOS, `sysrc` on BSD, or
+    /// `hostname` and /etc/hostname on other Linux hosts. Once it has been
+    /// set, the hostname in this host's cached telemetry is updated too.
+    ///
+    /// Returns `ErrorKind::InvalidName` if `name` is not a valid hostname.
+    ///
+    ///## Idempotence
+    ///
+    /// This function is idempotent. It resolves to the host and `true` if
+    /// the hostname was changed, or `false` if it was already set to `name`.
+    fn set_hostname(mut self, name: &str) -> Box<Future<Item = (Self, bool), Error = Error>>
+        where Self: 'static
+    {
+        if let Err(e) = validate::hostname(name) {
+            return Box::new(future::err(e));
+        }
+
+        let name = name.to_owned();
+        Box::new(self.request(SetHostname { name: name.clone() })
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "set_hostname" })
+            .and_then(move |changed| {
+                if changed {
+                    self.update_telemetry(move |t| t.hostname = name)?;
+                }
+                Ok((self, changed))
+            }))
+    }
+
+    /// Check whether the host can open a TCP connection to `port` on
+    /// `target`, a hostname or IP address, within `timeout`.
+    ///
+    /// The connection is made from the host itself, so this tests the
+    /// host's view of the network, e.g. whether a web server can reach its
+    /// database. The connection is closed as soon as it is established.
+    ///
+    /// Resolves to `false` if the connection fails or times out, including
+    /// if resolving `target` takes longer than `timeout`. Returns
+    /// `ErrorKind::Resolve` if the host can't resolve `target`.
+    fn check_port(&self, target: &str, port: u16, timeout: Duration) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(self.request(PortCheck { target: target.into(), port: port, timeout: timeout })
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "check_port" }))
+    }
+
+    /// Find `program` on the host's `PATH`.
+    ///
+    /// Resolves to the full path of the first matching executable, or `None`
+    /// if there isn't one. This is useful for checking that a tool is
+    /// installed before running a `Command` that depends on it. If
+    /// `program` contains a path separator, it is checked as is.
+    fn which(&self, program: &str) -> Box<Future<Item = Option<PathBuf>, Error = Error>> {
+        Box::new(self.request(Which { program: program.into() })
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "which" }))
+    }
+
+    /// Reboot the host, optionally after `delay`.
+    ///
+    /// This runs `shutdown -r` on the host. Because the host is about to go
+    /// away, the future resolves as soon as the shutdown command has started,
+    /// without waiting for it to finish. The delay is rounded up to whole
+    /// minutes. Windows hosts are not supported yet.
+    fn reboot(&self, delay: Option<Duration>) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(self.request(Reboot { delay: delay.map(|d| d.as_secs()) })
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "reboot" }))
+    }
+
+    /// Power off the host, optionally after `delay`.
+    ///
+    /// This runs `shutdown -h` on the host. As with
+    /// [`reboot()`](#method.reboot), the future resolves as soon as the
+    /// shutdown command has started.
+    fn shutdown(&self, delay: Option<Duration>) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(self.request(Shutdown { delay: delay.map(|d| d.as_secs()) })
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "shutdown" }))
+    }
+
     #[doc(hidden)]
     fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
-        where R: Executable + IntoMessage + 'static;
+        where R: Executable + FromMessage + IntoMessage + 'static;
+
+    // Modify this host's cached telemetry, e.g. after changing the host.
+    #[doc(hidden)]
+    fn update_telemetry<F: FnOnce(&mut telemetry::Telemetry)>(&mut self, f: F) -> Result<()>;
 
     /// Get a reference to the appropriate `Command` provider for this host.
     fn command(&self) -> &Box<command::CommandProvider>;
@@ -48,6 +191,285 @@ pub trait Host: Clone {
 
     /// Override the default `Service` provider for this host.
     fn set_service<P: service::ServiceProvider + 'static>(&mut self, P) -> Result<()>;
+
+    // The options below, which every host type stores the same way.
+    #[doc(hidden)]
+    fn options(&self) -> &HostOptions;
+
+    // Fails if the host has been cloned. See `get_mut()`.
+    #[doc(hidden)]
+    fn options_mut(&mut self) -> Result<&mut HostOptions>;
+
+    /// Fail any request to this host that doesn't complete within `timeout`
+    /// with `ErrorKind::RequestTimeout`.
+    ///
+    /// This is a safety net, so that a hung provider can't stall everything
+    /// waiting on it. Note that for a `Command`, the request completes once
+    /// the command has started, so its output stream is not subject to the
+    /// timeout.
+    fn set_default_timeout(&mut self, timeout: Duration) -> Result<()> {
+        self.options_mut()?.default_timeout = Some(timeout);
+        Ok(())
+    }
+
+    /// Get the labels attached to this host with
+    /// [`set_label()`](#tymethod.set_label).
+    fn labels(&self) -> &HashMap<String, String> {
+        &self.options().labels
+    }
+
+    /// Attach a label to this host, e.g. `("role", "web")`, replacing any
+    /// existing label with the same key.
+    ///
+    /// Labels are metadata for organising hosts, and are never sent to the
+    /// host itself.
+    fn set_label(&mut self, key: &str, value: &str) -> Result<()> {
+        self.options_mut()?.labels.insert(key.into(), value.into());
+        Ok(())
+    }
+
+    /// Get the proxy that package managers use on this host, if one was set
+    /// with [`set_proxy()`](#tymethod.set_proxy).
+    fn proxy(&self) -> Option<&str> {
+        self.options().proxy.as_ref().map(|p| p.as_str())
+    }
+
+    /// Send package manager traffic through the HTTP proxy at `url`, e.g.
+    /// "http://proxy.example.com:3128".
+    ///
+    /// The proxy is passed to the package manager in the `http_proxy` and
+    /// `https_proxy` environment variables, which Apt, Yum, Homebrew and
+    /// most other package managers respect.
+    fn set_proxy(&mut self, url: &str) -> Result<()> {
+        self.options_mut()?.proxy = Some(url.into());
+        Ok(())
+    }
+
+    /// Whether this host refuses requests that could change it. See
+    /// [`set_read_only()`](#tymethod.set_read_only).
+    fn read_only(&self) -> bool {
+        self.options().read_only
+    }
+
+    /// Refuse every request that could change this host, e.g. installing a
+    /// package, enabling a service, writing a file or running a command.
+    ///
+    /// These requests fail with `ErrorKind::ReadOnly` before they are sent,
+    /// so a read only host is safe to use for auditing and compliance
+    /// checks. Queries like `Package::installed()` are unaffected, as are
+    /// commands allowed with [`allow_command()`](#tymethod.allow_command).
+    fn set_read_only(&mut self, read_only: bool) -> Result<()> {
+        self.options_mut()?.read_only = read_only;
+        Ok(())
+    }
+
+    /// Get the commands that this host runs even when it is read only. See
+    /// [`allow_command()`](#tymethod.allow_command).
+    fn allowed_commands(&self) -> &[String] {
+        &self.options().allowed_commands
+    }
+
+    /// Allow a read only host to run the command `cmd`, e.g. "uname -a".
+    ///
+    /// A `Command` only matches if it is exactly the same as `cmd`. For
+    /// commands with a custom shell or separate arguments, `cmd` is matched
+    /// against the shell and arguments joined by spaces, e.g.
+    /// "/bin/bash -c uname -a". Commands that set environment variables are
+    /// never allowed.
+    fn allow_command(&mut self, cmd: &str) -> Result<()> {
+        self.options_mut()?.allowed_commands.push(cmd.into());
+        Ok(())
+    }
+}
+
+/// The identity of a host. See [`Host::id()`](trait.Host.html#method.id).
+///
+/// Two `HostId`s are equal if they have the same target and machine ID.
+/// Note that a `Local` host and a remote host connected to the same machine
+/// through its agent have different targets, so they are not equal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HostId {
+    /// How the host is reached
+    pub target: HostTarget,
+    /// The host's `Telemetry::machine_id`, if available. For a `Local` host,
+    /// this is `None` until its telemetry has been loaded.
+    pub machine_id: Option<String>,
+}
+
+/// How a host is reached. See [`HostId`](struct.HostId.html).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HostTarget {
+    /// The machine this process is running on
+    Local,
+    /// An agent listening on a TCP socket
+    Socket(SocketAddr),
+    /// An agent listening on a Unix socket
+    Unix(PathBuf),
+}
+
+/// The response to [`Host::ping()`](trait.Host.html#method.ping).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PingResponse {
+    /// Version of the API that the agent was built with. For a `Local` host,
+    /// this is the version of this crate.
+    pub agent_version: String,
+    /// Time on the host when the ping was answered, in seconds since the
+    /// Unix epoch.
+    pub time: u64,
+}
+
+/// Upper bounds, in milliseconds, of the latency buckets in
+/// [`RequestMetrics::latency`](struct.RequestMetrics.html#structfield.latency).
+pub const LATENCY_BUCKETS_MS: [u64; 6] = [10, 50, 100, 500, 1000, 5000];
+
+/// Request metrics recorded by an agent. See
+/// [`Plain::metrics()`](remote/struct.Plain.html#method.metrics).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AgentMetrics {
+    /// Seconds since the agent started.
+    pub uptime: u64,
+    /// Metrics for each type of request the agent has served, keyed by the
+    /// request's name, e.g. "CommandExec".
+    pub requests: HashMap<String, RequestMetrics>,
+    /// Number of requests that were too malformed to tell their type.
+    #[serde(default)]
+    pub malformed: u64,
+}
+
+/// Metrics for one type of request.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RequestMetrics {
+    /// Number of requests served.
+    pub count: u64,
+    /// Number of requests that failed, were refused or were cancelled,
+    /// including requests that couldn't be decoded.
+    pub errors: u64,
+    /// Total time spent serving requests, in milliseconds. A request that
+    /// streams a response body is timed until the body ends.
+    pub total_ms: u64,
+    /// A histogram of request latency. Each item counts the requests that
+    /// took no longer than the bucket with the same index in
+    /// [`LATENCY_BUCKETS_MS`](constant.LATENCY_BUCKETS_MS.html), but longer
+    /// than the bucket before. The last item counts the requests that took
+    /// longer than every bucket.
+    pub latency: Vec<u64>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct MetricsLoad;
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct Ping;
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct SetHostname {
+    #[serde(deserialize_with = "validate::de_hostname")]
+    name: String,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PortCheck {
+    target: String,
+    port: u16,
+    timeout: Duration,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct Which {
+    program: String,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct Reboot {
+    delay: Option<u64>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct Shutdown {
+    delay: Option<u64>,
+}
+
+// Race `future` against `timeout`, if there is one. If the timeout wins, the
+// unfinished future is passed to `on_timeout` so it can be cleaned up.
+fn with_timeout<F, C>(future: F, timeout: Option<Duration>, handle: &Handle, on_timeout: C) -> Box<Future<Item = F::Item, Error = Error>>
+    where F: Future<Error = Error> + 'static,
+          C: FnOnce(F) + 'static
+{
+    let duration = match timeout {
+        Some(d) => d,
+        None => return Box::new(future),
+    };
+    let timeout = match Timeout::new(duration, handle) {
+        Ok(t) => t,
+        Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create timeout"))),
+    };
+
+    Box::new(future.select2(timeout).then(move |r| match r {
+        Ok(Either::A((item, _))) => Ok(item),
+        Err(Either::A((e, _))) => Err(e),
+        Ok(Either::B((_, future))) => {
+            on_timeout(future);
+            Err(ErrorKind::RequestTimeout(duration).into())
+        },
+        Err(Either::B((e, _))) => Err(Error::with_chain(e, "Request timeout failed")),
+    }))
+}
+
+// Whether a read only host must refuse a request. See `Executable::mutates()`
+// and `Executable::command_line()`.
+fn refuses<H: Host>(host: &H, mutates: bool, command_line: Option<String>) -> bool {
+    host.read_only() && mutates
+        && !command_line.map_or(false, |c| host.allowed_commands().contains(&c))
+}
+
+// Refuse a serialized request if the host is read only and the request
+// could change it. This is for requests that have already been serialized,
+// e.g. those sent through `Service::call()`. The body of a refused request
+// is drained, as it will never be read.
+fn check_read_only<H: Host>(host: &H, msg: &mut InMessage) -> Result<()> {
+    if !host.read_only() {
+        return Ok(());
+    }
+
+    let result = Request::from_header(msg.get_ref()).and_then(|request| {
+        if refuses(host, request.mutates(), request.command_line()) {
+            Err(ErrorKind::ReadOnly.into())
+        } else {
+            Ok(())
+        }
+    });
+
+    if result.is_err() {
+        if let Some(body) = msg.take_body() {
+            drain_body(body, host.handle());
+        }
+    }
+    result
+}
+
+// Get mutable access to the state shared by clones of a host. This fails if
+// any other clones of the host exist, so a host has to be configured before
+// it is cloned.
+fn get_mut<'a, T>(inner: &'a mut Rc<T>, host: &'static str) -> Result<&'a mut T> {
+    Rc::get_mut(inner).ok_or_else(|| ErrorKind::MutRef(host).into())
+}
+
+// Client side settings for a host, which aren't sent to the host itself.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default)]
+pub struct HostOptions {
+    default_timeout: Option<Duration>,
+    labels: HashMap<String, String>,
+    proxy: Option<String>,
+    read_only: bool,
+    allowed_commands: Vec<String>,
 }
 
 struct Providers {
@@ -63,3 +485,301 @@ fn get_providers(telemetry: &telemetry::Telemetry) -> Result<Providers> {
         service: service::factory(telemetry)?,
     })
 }
+
+impl Executable for Ping {
+    type Response = PingResponse;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, _: &Local) -> Self::Future {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        future::ok(PingResponse {
+            agent_version: ::VERSION.into(),
+            time: time,
+        })
+    }
+}
+
+impl Executable for MetricsLoad {
+    type Response = AgentMetrics;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    // Metrics are answered by the agent itself, so this is only reached
+    // for hosts that don't record any.
+    fn exec(self, _: &Local) -> Self::Future {
+        future::err("Request metrics are only recorded by the agent".into())
+    }
+}
+
+impl Executable for SetHostname {
+    type Response = bool;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        match get_hostname() {
+            Some(ref current) if *current == self.name => return Box::new(future::ok(false)),
+            _ => (),
+        }
+
+        let name = self.name;
+        let telemetry = match host.try_telemetry() {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let result = if telemetry.init_system == "systemd" {
+            run(host, &["hostnamectl", "set-hostname", &name])
+        } else {
+            match telemetry.os.family {
+                OsFamily::Darwin => run(host, &["scutil", "--set", "HostName", &name]),
+                OsFamily::Bsd => {
+                    let persist = format!("hostname={}", name);
+                    let host = host.clone();
+                    Box::new(run(&host, &["hostname", &name])
+                        .and_then(move |_| run(&host, &["sysrc", &persist])))
+                },
+                OsFamily::Linux(_) => Box::new(run(host, &["hostname", &name])
+                    .and_then(move |_| fs::File::create("/etc/hostname")
+                        .and_then(|mut fh| writeln!(fh, "{}", name))
+                        .chain_err(|| "Could not write /etc/hostname"))),
+            }
+        };
+
+        Box::new(result.map(|_| true))
+    }
+}
+
+impl Executable for PortCheck {
+    type Response = bool;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let timeout = match Timeout::new(self.timeout, host.handle()) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create timeout"))),
+        };
+
+        // The system resolver blocks, so resolve on another thread rather
+        // than stalling the reactor
+        let (tx, rx) = oneshot::channel();
+        let (target, port) = (self.target, self.port);
+        thread::spawn(move || {
+            // The receiver only goes away if the check timed out
+            let _ = tx.send(resolve(&target, port));
+        });
+
+        let handle = host.handle().clone();
+        let check = rx
+            .then(|r| match r {
+                Ok(addr) => addr,
+                Err(_) => Err("Resolver thread exited without a result".into()),
+            })
+            .and_then(move |addr| TcpStream::connect(&addr, &handle).then(|r| Ok(r.is_ok())));
+
+        Box::new(check
+            .select2(timeout)
+            .then(|r| match r {
+                Ok(Either::A((connected, _))) => Ok(connected),
+                Err(Either::A((e, _))) => Err(e),
+                // Resolving the target or connecting to it timed out
+                Ok(Either::B(_)) => Ok(false),
+                Err(Either::B((e, _))) => Err(Error::with_chain(e, "Port check timeout failed")),
+            }))
+    }
+}
+
+// Resolve `target` to the first of its socket addresses.
+fn resolve(target: &str, port: u16) -> Result<SocketAddr> {
+    match (target, port).to_socket_addrs() {
+        Ok(mut addrs) => addrs.next().ok_or_else(|| ErrorKind::Resolve { host: target.into() }.into()),
+        Err(e) => Err(Error::with_chain(e, ErrorKind::Resolve { host: target.into() })),
+    }
+}
+
+impl Executable for Which {
+    type Response = Option<PathBuf>;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, _: &Local) -> Self::Future {
+        future::ok(which(&self.program))
+    }
+}
+
+impl Executable for Reboot {
+    type Response = ();
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        future::result(power_off(host, true, self.delay))
+    }
+}
+
+impl Executable for Shutdown {
+    type Response = ();
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        future::result(power_off(host, false, self.delay))
+    }
+}
+
+// Start `shutdown` without waiting for it, as the host (and with it this
+// process) may be gone before it exits.
+fn power_off(host: &Local, reboot: bool, delay: Option<u64>) -> Result<()> {
+    // `shutdown` only understands minutes
+    let when = match delay.unwrap_or(0) {
+        0 => "now".to_owned(),
+        d => format!("+{}", (d + 59) / 60),
+    };
+    let mut command = command::hooks::command("shutdown", &[if reboot { "-r" } else { "-h" }, &when]);
+
+    let child = command.stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn_async(host.handle())
+        .chain_err(|| ErrorKind::Spawn { cmd: "shutdown".into() })?;
+
+    // Reap the process in the background once it exits
+    host.handle().spawn(child.map(|_| ()).map_err(|_| ()));
+    Ok(())
+}
+
+// Find the first executable named `program` on this process' `PATH`.
+#[doc(hidden)]
+pub fn which(program: &str) -> Option<PathBuf> {
+    if program.is_empty() {
+        return None;
+    }
+
+    if Path::new(program).components().count() > 1 {
+        let path = PathBuf::from(program);
+        return if is_executable(&path) { Some(path) } else { None };
+    }
+
+    // On Windows, "git" may be "git.exe" or "git.bat", as listed in PATHEXT
+    let exts: Vec<String> = if cfg!(windows) {
+        env::var("PATHEXT").unwrap_or(".COM;.EXE;.BAT;.CMD".into())
+            .split(';')
+            .map(|e| e.to_owned())
+            .chain(Some(String::new()))
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .flat_map(|dir| exts.iter().map(move |ext| dir.join(format!("{}{}", program, ext))))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Run a command to completion, failing if it exits unsuccessfully.
+fn run(host: &Local, cmd: &[&str]) -> Box<Future<Item = (), Error = Error>> {
+    let program = cmd[0].to_owned();
+    let err_program = program.clone();
+
+    Box::new(command::hooks::command(cmd[0], &cmd[1..])
+        .output_async(host.handle())
+        .chain_err(move || format!("Could not run `{}`", err_program))
+        .and_then(move |output| if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!("Error running `{}`: {}", program, String::from_utf8_lossy(&output.stderr)).into())
+        }))
+}
+
+impl RequestMetrics {
+    // Record a request that took `duration`.
+    #[doc(hidden)]
+    pub fn record(&mut self, success: bool, duration: Duration) {
+        let ms = duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000);
+
+        if self.latency.len() != LATENCY_BUCKETS_MS.len() + 1 {
+            self.latency = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        }
+        let bucket = LATENCY_BUCKETS_MS.iter()
+            .position(|b| ms <= *b)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency[bucket] += 1;
+
+        self.count += 1;
+        self.total_ms += ms;
+        if !success {
+            self.errors += 1;
+        }
+    }
+}
+
+impl FromMessage for AgentMetrics {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        json::from_value(msg.into_inner()).chain_err(|| "Could not deserialize AgentMetrics")
+    }
+}
+
+impl IntoMessage for AgentMetrics {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        let value = json::to_value(self).chain_err(|| "Could not convert type into Message")?;
+        Ok(Message::WithoutBody(value))
+    }
+}
+
+impl FromMessage for PingResponse {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        json::from_value(msg.into_inner()).chain_err(|| "Could not deserialize PingResponse")
+    }
+}
+
+impl IntoMessage for PingResponse {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        let value = json::to_value(self).chain_err(|| "Could not convert type into Message")?;
+        Ok(Message::WithoutBody(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use errors::*;
+    use super::resolve;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("127.0.0.1", 22).unwrap(), "127.0.0.1:22".parse().unwrap());
+
+        match resolve("not a hostname", 22) {
+            Err(Error(ErrorKind::Resolve { ref host }, _)) => assert_eq!(host, "not a hostname"),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
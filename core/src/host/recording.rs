@@ -0,0 +1,343 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! A `Host` wrapper that records the requests sent through it.
+
+use command::CommandProvider;
+use errors::*;
+use futures::{future, Future};
+use message::{drain_body, FromMessage, IntoMessage};
+use package::PackageProvider;
+use request::Executable;
+use serde_json::Value;
+use service::ServiceProvider;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::{refuses, Host, HostId, HostOptions};
+use telemetry::Telemetry;
+use tokio_core::reactor::Handle;
+use tokio_proto::streaming::Message;
+
+/// A `Host` type that records every request made through it, for use in
+/// tests.
+///
+/// Each request is serialized exactly as it would be sent to a remote host,
+/// e.g. `{"PackageInstall": {"name": "nginx", ...}}`, and recorded before it
+/// is passed on to the inner host. Requests can also be answered with a
+/// canned response instead, so that tests can check what an endpoint sends
+/// without changing the host.
+///
+/// Clones of a `RecordingHost` share their recorded requests and canned
+/// responses.
+///
+///## Example
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///#[macro_use] extern crate serde_json;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::host::recording::RecordingHost;
+///use intecture_api::prelude::*;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = RecordingHost::new(Local::new(&handle).wait().unwrap());
+///// Pretend nginx is already installed
+///host.respond("PackageInstalled", json!("1.10.3"));
+///
+///let nginx = Package::new(&host, "nginx").unwrap();
+///core.run(nginx.install()).unwrap();
+///
+///assert_eq!(host.requests()[0]["PackageInstalled"]["name"], "nginx");
+///# }
+///```
+#[derive(Clone)]
+pub struct RecordingHost<H> {
+    inner: H,
+    recorder: Rc<RefCell<Recorder>>,
+}
+
+#[derive(Default)]
+struct Recorder {
+    requests: Vec<Value>,
+    responses: HashMap<String, Value>,
+}
+
+impl<H: Host + 'static> RecordingHost<H> {
+    /// Create a new `RecordingHost` that passes requests on to `inner`.
+    pub fn new(inner: H) -> RecordingHost<H> {
+        RecordingHost {
+            inner: inner,
+            recorder: Rc::new(RefCell::new(Recorder::default())),
+        }
+    }
+
+    /// Get the inner host.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Get the requests made so far, oldest first.
+    pub fn requests(&self) -> Vec<Value> {
+        self.recorder.borrow().requests.clone()
+    }
+
+    /// Forget the requests made so far.
+    pub fn clear(&self) {
+        self.recorder.borrow_mut().requests.clear();
+    }
+
+    /// Answer every request of type `request`, e.g. "PackageInstall", with
+    /// `response` instead of passing it on to the inner host.
+    ///
+    /// `response` is decoded as the request's response, so it must have the
+    /// same form as a remote host's response, e.g. `json!(true)` for
+    /// "ServiceRunning".
+    pub fn respond(&self, request: &str, response: Value) {
+        self.recorder.borrow_mut().responses.insert(request.into(), response);
+    }
+}
+
+impl<H: Host + 'static> Host for RecordingHost<H> {
+    fn telemetry(&self) -> &Telemetry {
+        self.inner.telemetry()
+    }
+
+    fn handle(&self) -> &Handle {
+        self.inner.handle()
+    }
+
+    fn id(&self) -> HostId {
+        self.inner.id()
+    }
+
+    #[doc(hidden)]
+    fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
+        where R: Executable + FromMessage + IntoMessage + 'static
+    {
+        if refuses(self, request.mutates(), request.command_line()) {
+            return Box::new(future::err(ErrorKind::ReadOnly.into()));
+        }
+
+        // Only the header is recorded. The body, if any, is handed back to
+        // the request once it has been decoded again.
+        let (value, body) = match request.into_msg(self.inner.handle()) {
+            Ok(mut msg) => {
+                let body = msg.take_body();
+                (msg.into_inner(), body)
+            },
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        self.recorder.borrow_mut().requests.push(value.clone());
+
+        // A serialized request is an object with a single key, the type of
+        // request, whose value is the request itself.
+        let (name, request) = match value {
+            Value::Object(map) => match map.into_iter().next() {
+                Some(pair) => pair,
+                None => return Box::new(future::err("Request was serialized without a type".into())),
+            },
+            _ => return Box::new(future::err("Request was not serialized as an object".into())),
+        };
+
+        let canned = self.recorder.borrow().responses.get(&name).cloned();
+        if let Some(response) = canned {
+            if let Some(b) = body {
+                drain_body(b, self.inner.handle());
+            }
+            return Box::new(future::result(R::Response::from_msg(Message::WithoutBody(response))
+                .chain_err(|| format!("Could not decode canned response to {}", name))));
+        }
+
+        match R::from_msg(Message::WithoutBody(request)) {
+            Ok(mut r) => {
+                if let Some(b) = body.and_then(|b| r.set_body(b)) {
+                    drain_body(b, self.inner.handle());
+                }
+                self.inner.request(r)
+            },
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+
+    #[doc(hidden)]
+    fn update_telemetry<F: FnOnce(&mut Telemetry)>(&mut self, f: F) -> Result<()> {
+        self.inner.update_telemetry(f)
+    }
+
+    fn command(&self) -> &Box<CommandProvider> {
+        self.inner.command()
+    }
+
+    fn set_command<P: CommandProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        self.inner.set_command(provider)
+    }
+
+    fn package(&self) -> &Box<PackageProvider> {
+        self.inner.package()
+    }
+
+    fn set_package<P: PackageProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        self.inner.set_package(provider)
+    }
+
+    fn service(&self) -> &Box<ServiceProvider> {
+        self.inner.service()
+    }
+
+    fn set_service<P: ServiceProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        self.inner.set_service(provider)
+    }
+
+    fn options(&self) -> &HostOptions {
+        self.inner.options()
+    }
+
+    fn options_mut(&mut self) -> Result<&mut HostOptions> {
+        self.inner.options_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use command::CommandProvider;
+    use errors::*;
+    use futures::{future, Future};
+    use host::{Host, HostId, HostOptions, HostTarget, Reboot};
+    use message::{FromMessage, IntoMessage};
+    use package::PackageProvider;
+    use request::Executable;
+    use serde_json::Value;
+    use service::ServiceProvider;
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use super::RecordingHost;
+    use telemetry::Telemetry;
+    use tokio_core::reactor::{Core, Handle};
+    use tokio_proto::streaming::Message;
+
+    // A host that answers every request with `null`, keeping the requests
+    // that reach it.
+    #[derive(Clone)]
+    struct Stub {
+        handle: Handle,
+        options: HostOptions,
+        received: Rc<RefCell<Vec<Value>>>,
+    }
+
+    impl Stub {
+        fn new(core: &Core) -> Stub {
+            Stub {
+                handle: core.handle(),
+                options: HostOptions::default(),
+                received: Rc::new(RefCell::new(Vec::new())),
+            }
+        }
+    }
+
+    impl Host for Stub {
+        fn telemetry(&self) -> &Telemetry { unimplemented!() }
+        fn handle(&self) -> &Handle { &self.handle }
+
+        fn id(&self) -> HostId {
+            HostId { target: HostTarget::Local, machine_id: None }
+        }
+
+        fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
+            where R: Executable + FromMessage + IntoMessage + 'static
+        {
+            match request.into_msg(&self.handle) {
+                Ok(msg) => self.received.borrow_mut().push(msg.into_inner()),
+                Err(e) => return Box::new(future::err(e)),
+            }
+            Box::new(future::result(R::Response::from_msg(Message::WithoutBody(Value::Null))))
+        }
+
+        fn update_telemetry<F: FnOnce(&mut Telemetry)>(&mut self, _: F) -> Result<()> { unimplemented!() }
+        fn command(&self) -> &Box<CommandProvider> { unimplemented!() }
+        fn set_command<P: CommandProvider + 'static>(&mut self, _: P) -> Result<()> { unimplemented!() }
+        fn package(&self) -> &Box<PackageProvider> { unimplemented!() }
+        fn set_package<P: PackageProvider + 'static>(&mut self, _: P) -> Result<()> { unimplemented!() }
+        fn service(&self) -> &Box<ServiceProvider> { unimplemented!() }
+        fn set_service<P: ServiceProvider + 'static>(&mut self, _: P) -> Result<()> { unimplemented!() }
+        fn options(&self) -> &HostOptions { &self.options }
+        fn options_mut(&mut self) -> Result<&mut HostOptions> { Ok(&mut self.options) }
+    }
+
+    #[test]
+    fn test_records_and_passes_on_requests() {
+        let core = Core::new().unwrap();
+        let stub = Stub::new(&core);
+        let host = RecordingHost::new(stub.clone());
+
+        assert_eq!(host.which("ls").wait().unwrap(), None);
+
+        let requests = host.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["Which"]["program"], "ls");
+        // The inner host receives the same request
+        assert_eq!(*stub.received.borrow(), requests);
+    }
+
+    #[test]
+    fn test_canned_response() {
+        let core = Core::new().unwrap();
+        let stub = Stub::new(&core);
+        let host = RecordingHost::new(stub.clone());
+        host.respond("Which", Value::String("/bin/ls".into()));
+
+        assert_eq!(host.which("ls").wait().unwrap(), Some(PathBuf::from("/bin/ls")));
+        assert_eq!(host.requests()[0]["Which"]["program"], "ls");
+        assert!(stub.received.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_bad_canned_response() {
+        let core = Core::new().unwrap();
+        let host = RecordingHost::new(Stub::new(&core));
+        host.respond("PortCheck", Value::String("yes".into()));
+
+        assert!(host.check_port("localhost", 22, Duration::from_secs(1)).wait().is_err());
+    }
+
+    #[test]
+    fn test_clones_share_requests() {
+        let core = Core::new().unwrap();
+        let host = RecordingHost::new(Stub::new(&core));
+        let clone = host.clone();
+
+        clone.which("ls").wait().unwrap();
+        assert_eq!(host.requests().len(), 1);
+
+        host.clear();
+        assert!(clone.requests().is_empty());
+    }
+
+    #[test]
+    fn test_refused_requests_are_not_recorded() {
+        let core = Core::new().unwrap();
+        let mut stub = Stub::new(&core);
+        stub.options.read_only = true;
+        let host = RecordingHost::new(stub.clone());
+
+        match host.request(Reboot { delay: None }).wait() {
+            Err(Error(ErrorKind::ReadOnly, _)) => (),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        assert!(host.requests().is_empty());
+        assert!(stub.received.borrow().is_empty());
+    }
+}
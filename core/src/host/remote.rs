@@ -9,27 +9,39 @@
 use bytes::{Bytes, BytesMut};
 use command::CommandProvider;
 use errors::*;
-use futures::{future, Future};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use futures::{future, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc;
 use message::{InMessage, FromMessage, IntoMessage};
 use package::PackageProvider;
-use request::Executable;
+use redact::redact;
+use request::{Envelope, Executable, Hello};
 use serde_json;
 use service::ServiceProvider;
 use std::{io, result};
-use std::net::SocketAddr;
-use std::thread::sleep;
-use std::time::Duration;
-use std::sync::Arc;
-use super::{Host, Providers};
+use std::io::{Read, Write};
+use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(unix)] use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use super::{check_read_only, get_mut, refuses, with_timeout, AgentMetrics, Host, HostId, HostOptions, HostTarget, MetricsLoad, Providers};
 use telemetry::{self, Telemetry};
+use tokio_core::net::TcpStream;
 use tokio_core::reactor::Handle;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::{Encoder, Decoder, Framed};
-use tokio_proto::streaming::Message;
-use tokio_proto::streaming::pipeline::{ClientProto, Frame, ServerProto};
-use tokio_proto::TcpClient;
+use tokio_proto::streaming::{Body, Message};
+use tokio_proto::streaming::pipeline::{ClientProto, Frame, ServerProto, Transport};
+use tokio_proto::BindClient;
 use tokio_proto::util::client_proxy::ClientProxy;
 use tokio_service::Service;
+#[cfg(unix)] use tokio_uds::UnixStream;
 
 /// A `Host` type that uses an unencrypted socket.
 ///
@@ -37,44 +49,442 @@ use tokio_service::Service;
 /// attacks, and should only be used on secure private networks.
 #[derive(Clone)]
 pub struct Plain {
-    inner: Arc<Inner>,
+    inner: Rc<Inner>,
     handle: Handle,
 }
 
 struct Inner {
     inner: ClientProxy<InMessage, InMessage, io::Error>,
+    target: HostTarget,
+    agent_version: String,
+    options: HostOptions,
+    next_id: Cell<u64>,
+    hooks: Hooks,
     providers: Option<Providers>,
     telemetry: Option<Telemetry>,
 }
 
+// Callbacks that receive the raw JSON headers sent to and received from the
+// remote host.
+#[derive(Clone, Default)]
+struct Hooks {
+    request: Option<Rc<Fn(&serde_json::Value)>>,
+    response: Option<Rc<Fn(&serde_json::Value)>>,
+}
+
+/// A request in flight to a remote host, which can be cancelled.
+///
+/// This is a `Future` that resolves to the request's response. Calling
+/// `cancel()` asks the remote host to abort the request. Note that only
+/// the work done _before_ the response is sent can be aborted, so a
+/// request that has already begun streaming its output (e.g.
+/// `Command::exec`) will not be affected.
+#[doc(hidden)]
+pub struct RequestHandle<T> {
+    id: u64,
+    proxy: ClientProxy<InMessage, InMessage, io::Error>,
+    hooks: Hooks,
+    inner: Box<Future<Item = T, Error = Error>>,
+}
+
+/// A `Host` type that spreads requests across a pool of unencrypted
+/// connections to the same host.
+///
+/// Each `Plain` host sends its requests through a single pipeline, so
+/// concurrent requests are answered one after the other. `Pool` opens up to
+/// `PoolConfig::max` connections and round-robins requests across them,
+/// preferring idle connections. New connections are only opened when every
+/// existing connection is busy, and never beyond the configured maximum.
+///
+/// >**Warning!** Like `Plain`, the pool's connections are unencrypted and
+/// should only be used on secure private networks.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Rc<PoolInner>,
+    handle: Handle,
+}
+
+/// Sizing options for a `Pool`.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    /// Number of connections to open up front. At least one connection is
+    /// always opened.
+    pub min: usize,
+    /// Maximum number of connections the pool will ever open.
+    pub max: usize,
+    /// Options for each of the pool's sockets.
+    pub socket: SocketConfig,
+}
+
+/// Options for the TCP socket that connects to a host.
+///
+/// The defaults disable Nagle's algorithm and leave the buffer sizes to the
+/// OS. Requests are small and sent one at a time, so without `nodelay` each
+/// one can sit in the send buffer waiting for the previous reply's ACK.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketConfig {
+    /// Set `TCP_NODELAY`, so that small frames are sent immediately.
+    pub nodelay: bool,
+    /// The size of the socket's send buffer (`SO_SNDBUF`), in bytes.
+    pub send_buffer_size: Option<usize>,
+    /// The size of the socket's receive buffer (`SO_RCVBUF`), in bytes.
+    pub recv_buffer_size: Option<usize>,
+    /// Offer to gzip message bodies, e.g. file uploads and command output.
+    /// Compression is only used if the agent agrees to it in the handshake.
+    pub gzip: bool,
+    /// How message headers are serialized.
+    pub format: JsonFormat,
+}
+
+struct PoolInner {
+    addr: SocketAddr,
+    config: PoolConfig,
+    conns: RefCell<Vec<Connection>>,
+    connecting: Cell<usize>,
+    options: HostOptions,
+    next: Cell<usize>,
+    hooks: Hooks,
+    providers: Option<Providers>,
+    telemetry: Option<Telemetry>,
+}
+
+#[derive(Clone)]
+struct Connection {
+    proxy: ClientProxy<InMessage, InMessage, io::Error>,
+    next_id: Rc<Cell<u64>>,
+    in_flight: Rc<Cell<usize>>,
+}
+
+/// The largest frame that `JsonLineCodec` will buffer by default, in bytes.
+#[doc(hidden)]
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
 #[doc(hidden)]
 pub struct JsonLineCodec {
     decoding_head: bool,
+    format: JsonFormat,
+    max_frame_size: usize,
+    gzip: GzipSwitch,
+    // Body chunks decompressed from a batch that haven't been returned yet
+    chunks: VecDeque<Bytes>,
 }
+
+/// Frames a connection with `JsonLineCodec`. Once gzip has been negotiated,
+/// body chunks that are sent together are held until the transport is
+/// flushed, then compressed as one batch.
 #[doc(hidden)]
-pub struct JsonLineProto;
+pub struct JsonLineTransport<T> {
+    inner: Framed<T, JsonLineCodec>,
+    gzip: GzipSwitch,
+    batch: Vec<Bytes>,
+    batch_len: usize,
+}
+
+/// Whether a connection compresses its body chunks.
+///
+/// Each connection gets its own switch, shared by its codec and by the code
+/// that performs the handshake, which turns it on once both sides have
+/// offered gzip in their `Hello`.
+#[doc(hidden)]
+#[derive(Clone, Debug, Default)]
+pub struct GzipSwitch(Rc<Cell<bool>>);
+
+// Batches smaller than this aren't worth compressing
+const GZIP_MIN_BATCH: usize = 512;
+// Batches are sent once they reach this size, even if more chunks are ready
+const GZIP_MAX_BATCH: usize = 256 * 1024;
+// Once gzip is negotiated, each body line starts with one of these
+const CHUNK_RAW: u8 = b'r';
+const CHUNK_GZIP: u8 = b'z';
+// Compressed chunks are escaped, as body chunks are newline delimited
+const ESCAPE: u8 = 0x1b;
+
+/// Controls how message headers are serialized on a connection.
+///
+/// The default is compact, unsorted output, which is what existing
+/// deployments expect. Set this for a client in
+/// [`SocketConfig`](struct.SocketConfig.html), and for an agent with the
+/// `json` table in its config file.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct JsonFormat {
+    /// Sort object keys, so that identical messages always produce identical
+    /// frames.
+    pub sort_keys: bool,
+    /// Pretty print headers in debug logs. Frames on the wire are always
+    /// compact, as the protocol is newline delimited.
+    pub pretty_log: bool,
+}
+
+#[doc(hidden)]
+#[derive(Clone, Debug, Default)]
+pub struct JsonLineProto {
+    pub format: JsonFormat,
+    /// Turned on by the handshake if both sides agree to gzip. Give each
+    /// connection its own switch.
+    pub gzip: GzipSwitch,
+    /// The largest frame to buffer before giving up on the connection, so
+    /// that a peer that never sends a newline can't exhaust our memory.
+    /// This also limits how large a compressed batch of body chunks may be
+    /// once it is decompressed. Defaults to `DEFAULT_MAX_FRAME_SIZE` if
+    /// `None`.
+    pub max_frame_size: Option<usize>,
+}
 
 impl Plain {
     /// Create a new Host connected to the given address.
+    ///
+    /// The address is a `host:port` string, where `host` is a hostname, an
+    /// IPv4 address or a bracketed IPv6 address, e.g. `"[::1]:7101"`. If a
+    /// hostname resolves to several addresses, each is tried in turn until
+    /// one accepts the connection.
+    ///
+    /// Once connected, the client and agent exchange versions. If they speak
+    /// different versions of the protocol, this fails with
+    /// `ErrorKind::VersionMismatch`.
     pub fn connect(addr: &str, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
-        let addr: SocketAddr = match addr.parse().chain_err(|| "Invalid host address") {
-            Ok(addr) => addr,
+        Plain::connect_with(addr, SocketConfig::default(), handle)
+    }
+
+    /// Create a new Host connected to the given address, using the given
+    /// socket options.
+    ///
+    /// See [`connect()`](#method.connect) for details of how the address is
+    /// resolved.
+    pub fn connect_with(addr: &str, socket: SocketConfig, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        let addrs = match resolve(addr) {
+            Ok(addrs) => addrs,
             Err(e) => return Box::new(future::err(e)),
         };
         let handle = handle.clone();
 
-        info!("Connecting to host {}", addr);
+        Box::new(connect_any(addrs, socket, &handle)
+            .and_then(move |(addr, client_service, agent_version)| {
+                Plain::from_proxy(client_service, agent_version, HostTarget::Socket(addr), &handle)
+            }))
+    }
+
+    /// Create a new Host connected to the Unix socket at the given path.
+    ///
+    /// Use this to talk to an agent that is listening on a Unix socket. This
+    /// is also how a [`Payload`](../../payload/struct.Payload.html) connects
+    /// back to the host that is running it. See
+    /// [`payload::connect()`](../../payload/fn.connect.html).
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        Plain::connect_unix_with(path, SocketConfig::default(), handle)
+    }
+
+    /// Create a new Host connected to the Unix socket at the given path,
+    /// using the given socket options.
+    ///
+    /// Only the `gzip` and `format` options apply, as the rest are TCP
+    /// settings.
+    #[cfg(unix)]
+    pub fn connect_unix_with<P: AsRef<Path>>(path: P, socket: SocketConfig, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        let path = path.as_ref();
+        info!("Connecting to socket {}", path.display());
+
+        let stream = match UnixStream::connect(path, handle) {
+            Ok(s) => s,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not connect to host"))),
+        };
+
+        let target = HostTarget::Unix(path.to_owned());
+        let handle = handle.clone();
+        Box::new(open_proxy(stream, socket, &handle)
+            .and_then(move |(client_service, agent_version)| {
+                Plain::from_proxy(client_service, agent_version, target, &handle)
+            }))
+    }
+
+    // Finish connecting to a host once the handshake is done.
+    fn from_proxy(proxy: ClientProxy<InMessage, InMessage, io::Error>, agent_version: String, target: HostTarget, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        let mut host = Plain {
+            inner: Rc::new(
+                Inner {
+                    inner: proxy,
+                    target: target,
+                    agent_version: agent_version,
+                    options: HostOptions::default(),
+                    next_id: Cell::new(0),
+                    hooks: Hooks::default(),
+                    providers: None,
+                    telemetry: None,
+                }),
+            handle: handle.clone(),
+        };
+
+        Box::new(telemetry::Telemetry::load(&host)
+            .chain_err(|| "Could not load telemetry for host")
+            .and_then(|t| {
+                {
+                    let inner = Rc::get_mut(&mut host.inner).unwrap();
+                    inner.providers = match super::get_providers(&t) {
+                        Ok(p) => Some(p),
+                        Err(e) => return future::err(e),
+                    };
+                    inner.telemetry = Some(t);
+                }
+                future::ok(host)
+            }))
+    }
+
+    /// Get the version of the agent this host is connected to.
+    pub fn agent_version(&self) -> &str {
+        &self.inner.agent_version
+    }
+
+    /// Get the request metrics recorded by the agent since it started.
+    ///
+    /// The metrics cover the requests from every client of the agent, not
+    /// only this connection.
+    pub fn metrics(&self) -> Box<Future<Item = AgentMetrics, Error = Error>> {
+        Box::new(self.request(MetricsLoad)
+            .chain_err(|| ErrorKind::Request { endpoint: "Host", func: "metrics" }))
+    }
+
+    /// Send a request to the host, returning a handle that can be used to
+    /// cancel it.
+    #[doc(hidden)]
+    pub fn request_cancellable<R>(&self, request: R) -> RequestHandle<R::Response>
+        where R: Executable + FromMessage + IntoMessage + 'static
+    {
+        let id = self.next_id();
+        let inner: Box<Future<Item = _, Error = _>> = if refuses(self, request.mutates(), request.command_line()) {
+            Box::new(future::err(ErrorKind::ReadOnly.into()))
+        } else {
+            match request.into_msg(&self.handle) {
+                Ok(msg) => Box::new(call_proxy(&self.inner.inner, &self.inner.hooks, id, msg)
+                    .and_then(|msg| {
+                        match R::Response::from_msg(msg) {
+                            Ok(t) => future::ok(t),
+                            Err(e) => future::err(e)
+                        }
+                    })),
+                Err(e) => Box::new(future::err(e)),
+            }
+        };
+
+        RequestHandle {
+            id: id,
+            proxy: self.inner.inner.clone(),
+            hooks: self.inner.hooks.clone(),
+            inner: inner,
+        }
+    }
+
+    /// Register a callback that receives the raw JSON header of each
+    /// request before it is sent to the host.
+    ///
+    /// This is useful for capturing or recording exactly what is sent over
+    /// the wire, without enabling crate-wide debug logging.
+    pub fn on_request<F: Fn(&serde_json::Value) + 'static>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        inner.hooks.request = Some(Rc::new(f));
+        Ok(())
+    }
+
+    /// Register a callback that receives the raw JSON header of each
+    /// response received from the host.
+    pub fn on_response<F: Fn(&serde_json::Value) + 'static>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        inner.hooks.response = Some(Rc::new(f));
+        Ok(())
+    }
+
+    /// Close the connection to the host.
+    ///
+    /// This sends a close frame to the host and waits for it to be answered.
+    /// Responses are pipelined, so the answer only arrives once every request
+    /// sent before it has been answered, and any output streams from those
+    /// requests have been consumed. The connection is then shut down.
+    ///
+    /// Clones of a `Plain` host share its connection, so this will fail if
+    /// any other clones of this host still exist.
+    pub fn close(mut self) -> Box<Future<Item = (), Error = Error>> {
+        if let Err(e) = get_mut(&mut self.inner, "Plain") {
+            return Box::new(future::err(e));
+        }
+
+        let msg = match serde_json::to_value(Envelope::Close)
+            .chain_err(|| "Could not serialize close request")
+        {
+            Ok(v) => Message::WithoutBody(v),
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        Box::new(decode_response(&self.inner.inner, &self.inner.hooks, msg)
+            .map(move |_| {
+                // Dropping the last `ClientProxy` shuts down the transport
+                drop(self);
+                info!("Disconnected from host");
+            }))
+    }
+
+    fn next_id(&self) -> u64 {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id.wrapping_add(1));
+        id
+    }
+}
+
+impl<T> RequestHandle<T> {
+    /// Ask the remote host to abort this request.
+    pub fn cancel(&self) -> Box<Future<Item = (), Error = Error>> {
+        cancel(&self.proxy, &self.hooks, self.id)
+    }
+}
+
+impl<T> Future for RequestHandle<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl Pool {
+    /// Create a new pool of connections to the given address.
+    ///
+    /// The address is resolved in the same way as
+    /// [`Plain::connect()`](struct.Plain.html#method.connect). Once a
+    /// reachable address has been found, every connection in the pool uses
+    /// it.
+    pub fn connect(addr: &str, config: PoolConfig, handle: &Handle) -> Box<Future<Item = Self, Error = Error>> {
+        if config.max == 0 || config.min > config.max {
+            return Box::new(future::err("Invalid pool size".into()));
+        }
+
+        let addrs = match resolve(addr) {
+            Ok(addrs) => addrs,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let handle = handle.clone();
 
-        Box::new(TcpClient::new(JsonLineProto)
-            .connect(&addr, &handle)
-            .chain_err(|| "Could not connect to host")
-            .and_then(move |client_service| {
-                info!("Connected!");
+        Box::new(connect_any(addrs, config.socket, &handle)
+            .and_then(move |(addr, first, _)| {
+                let conns: Vec<_> = (1..config.min.max(1))
+                    .map(|_| connect_proxy(&addr, config.socket, &handle).map(|(proxy, _)| proxy))
+                    .collect();
 
-                let mut host = Plain {
-                    inner: Arc::new(
-                        Inner {
-                            inner: client_service,
+                future::join_all(conns).map(move |mut proxies| {
+                    proxies.insert(0, first);
+                    (addr, proxies, handle)
+                })
+            })
+            .and_then(move |(addr, proxies, handle)| {
+                let mut host = Pool {
+                    inner: Rc::new(
+                        PoolInner {
+                            addr: addr,
+                            config: config,
+                            conns: RefCell::new(proxies.into_iter().map(Connection::new).collect()),
+                            connecting: Cell::new(0),
+                            options: HostOptions::default(),
+                            next: Cell::new(0),
+                            hooks: Hooks::default(),
                             providers: None,
                             telemetry: None,
                         }),
@@ -85,7 +495,7 @@ impl Plain {
                     .chain_err(|| "Could not load telemetry for host")
                     .and_then(|t| {
                         {
-                            let inner = Arc::get_mut(&mut host.inner).unwrap();
+                            let inner = Rc::get_mut(&mut host.inner).unwrap();
                             inner.providers = match super::get_providers(&t) {
                                 Ok(p) => Some(p),
                                 Err(e) => return future::err(e),
@@ -96,6 +506,162 @@ impl Plain {
                     }))
             }))
     }
+
+    /// Register a callback that receives the raw JSON header of each
+    /// request before it is sent to the host, whichever connection it is
+    /// sent through.
+    ///
+    /// See [`Plain::on_request()`](struct.Plain.html#method.on_request).
+    pub fn on_request<F: Fn(&serde_json::Value) + 'static>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        inner.hooks.request = Some(Rc::new(f));
+        Ok(())
+    }
+
+    /// Register a callback that receives the raw JSON header of each
+    /// response received from the host.
+    pub fn on_response<F: Fn(&serde_json::Value) + 'static>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        inner.hooks.response = Some(Rc::new(f));
+        Ok(())
+    }
+
+    /// Get the number of connections currently open in the pool.
+    pub fn size(&self) -> usize {
+        self.inner.conns.borrow().len()
+    }
+
+    // Send a request through the next free connection, opening a new one if
+    // the pool has room to grow. Once the request has been sent, the
+    // returned slot holds the connection and ID it was sent with, so that
+    // it can be cancelled.
+    fn dispatch(&self, req: InMessage) -> (Rc<RefCell<Option<(Connection, u64)>>>, Box<Future<Item = InMessage, Error = Error>>) {
+        let sent = Rc::new(RefCell::new(None));
+
+        if let Some(conn) = self.checkout() {
+            let (id, response) = conn.call(req, &self.inner.hooks, &self.handle);
+            *sent.borrow_mut() = Some((conn, id));
+            return (sent, response);
+        }
+
+        let inner = self.inner.clone();
+        let hooks = self.inner.hooks.clone();
+        let handle = self.handle.clone();
+        let slot = sent.clone();
+        inner.connecting.set(inner.connecting.get() + 1);
+
+        let response = connect_proxy(&self.inner.addr, self.inner.config.socket, &self.handle)
+            .then(move |result| {
+                inner.connecting.set(inner.connecting.get() - 1);
+                let conn = Connection::new(result?.0);
+                inner.conns.borrow_mut().push(conn.clone());
+                Ok(conn)
+            })
+            .and_then(move |conn| {
+                let (id, response) = conn.call(req, &hooks, &handle);
+                *slot.borrow_mut() = Some((conn, id));
+                response
+            });
+        (sent, Box::new(response))
+    }
+
+    // Find the next connection to send a request through. Returns `None` if
+    // every connection is busy and the pool has room to grow.
+    fn checkout(&self) -> Option<Connection> {
+        let conns = self.inner.conns.borrow();
+        let start = self.inner.next.get();
+        let len = conns.len();
+
+        for i in 0..len {
+            let conn = &conns[(start + i) % len];
+            if conn.in_flight.get() == 0 {
+                self.inner.next.set((start + i + 1) % len);
+                return Some(conn.clone());
+            }
+        }
+
+        if len + self.inner.connecting.get() < self.inner.config.max || len == 0 {
+            None
+        } else {
+            self.inner.next.set((start + 1) % len);
+            Some(conns[start % len].clone())
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            min: 1,
+            max: 4,
+            socket: SocketConfig::default(),
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> SocketConfig {
+        SocketConfig {
+            nodelay: true,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            gzip: false,
+            format: JsonFormat::default(),
+        }
+    }
+}
+
+impl SocketConfig {
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(size) = self.send_buffer_size {
+            stream.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            stream.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+impl Connection {
+    fn new(proxy: ClientProxy<InMessage, InMessage, io::Error>) -> Connection {
+        Connection {
+            proxy: proxy,
+            next_id: Rc::new(Cell::new(0)),
+            in_flight: Rc::new(Cell::new(0)),
+        }
+    }
+
+    // Send a request, returning the ID it was sent with.
+    fn call(&self, req: InMessage, hooks: &Hooks, handle: &Handle) -> (u64, Box<Future<Item = InMessage, Error = Error>>) {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+
+        let in_flight = self.in_flight.clone();
+        in_flight.set(in_flight.get() + 1);
+
+        let handle = handle.clone();
+        (id, Box::new(call_proxy(&self.proxy, hooks, id, req)
+            .then(move |r| match r {
+                // The connection is busy until the whole body has been read,
+                // so forward it and only count the request as done once the
+                // body ends.
+                Ok(Message::WithBody(header, body)) => {
+                    let (tx, forwarded) = Body::pair();
+                    handle.spawn(tx.send_all(body.then(Ok::<_, mpsc::SendError<_>>))
+                        .then(move |_| {
+                            in_flight.set(in_flight.get() - 1);
+                            Ok(())
+                        }));
+                    Ok(Message::WithBody(header, forwarded))
+                },
+                r => {
+                    in_flight.set(in_flight.get() - 1);
+                    r
+                },
+            })))
+    }
 }
 
 impl Host for Plain {
@@ -107,21 +673,142 @@ impl Host for Plain {
         &self.handle
     }
 
+    fn id(&self) -> HostId {
+        HostId {
+            target: self.inner.target.clone(),
+            machine_id: self.telemetry().machine_id.clone(),
+        }
+    }
+
     #[doc(hidden)]
     fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
-        where R: Executable + IntoMessage + 'static
+        where R: Executable + FromMessage + IntoMessage + 'static
     {
+        let handle = self.handle.clone();
+        with_timeout(self.request_cancellable(request), self.inner.options.default_timeout, &self.handle, move |request| {
+            // Ask the host to stop working on the request we've given up on
+            handle.spawn(request.cancel().map_err(|_| ()));
+        })
+    }
+
+    #[doc(hidden)]
+    fn update_telemetry<F: FnOnce(&mut Telemetry)>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        if let Some(ref mut t) = inner.telemetry {
+            f(t);
+        }
+        Ok(())
+    }
+
+    fn command(&self) -> &Box<CommandProvider> {
+        &self.inner.providers.as_ref().unwrap().command
+    }
+
+    fn set_command<P: CommandProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        inner.providers.as_mut().unwrap().command = Box::new(provider);
+        Ok(())
+    }
+
+    fn package(&self) -> &Box<PackageProvider> {
+        &self.inner.providers.as_ref().unwrap().package
+    }
+
+    fn set_package<P: PackageProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        inner.providers.as_mut().unwrap().package = Box::new(provider);
+        Ok(())
+    }
+
+    fn service(&self) -> &Box<ServiceProvider> {
+        &self.inner.providers.as_ref().unwrap().service
+    }
+
+    fn set_service<P: ServiceProvider + 'static>(&mut self, provider: P) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Plain")?;
+        inner.providers.as_mut().unwrap().service = Box::new(provider);
+        Ok(())
+    }
+
+    fn options(&self) -> &HostOptions {
+        &self.inner.options
+    }
+
+    fn options_mut(&mut self) -> Result<&mut HostOptions> {
+        Ok(&mut get_mut(&mut self.inner, "Plain")?.options)
+    }
+}
+
+impl Service for Plain {
+    type Request = InMessage;
+    type Response = InMessage;
+    type Error = Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, mut req: Self::Request) -> Self::Future {
+        if let Err(e) = check_read_only(self, &mut req) {
+            return Box::new(future::err(e));
+        }
+
+        call_proxy(&self.inner.inner, &self.inner.hooks, self.next_id(), req)
+    }
+}
+
+impl Host for Pool {
+    fn telemetry(&self) -> &Telemetry {
+        self.inner.telemetry.as_ref().unwrap()
+    }
+
+    fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    fn id(&self) -> HostId {
+        HostId {
+            target: HostTarget::Socket(self.inner.addr),
+            machine_id: self.telemetry().machine_id.clone(),
+        }
+    }
+
+    #[doc(hidden)]
+    fn request<R>(&self, request: R) -> Box<Future<Item = R::Response, Error = Error>>
+        where R: Executable + FromMessage + IntoMessage + 'static
+    {
+        if refuses(self, request.mutates(), request.command_line()) {
+            return Box::new(future::err(ErrorKind::ReadOnly.into()));
+        }
+
         let msg = match request.into_msg(&self.handle) {
             Ok(m) => m,
             Err(e) => return Box::new(future::err(e)),
         };
-        Box::new(self.call(msg)
+        let (sent, response) = self.dispatch(msg);
+        let response = response
             .and_then(|msg| {
                 match R::Response::from_msg(msg) {
                     Ok(t) => future::ok(t),
                     Err(e) => future::err(e)
                 }
-            }))
+            });
+        let hooks = self.inner.hooks.clone();
+        let handle = self.handle.clone();
+        with_timeout(response, self.inner.options.default_timeout, &self.handle, move |_| {
+            // Ask the host to stop working on the request we've given up on,
+            // using the connection it was sent through. If we timed out
+            // before it was sent, there's nothing to cancel.
+            if let Some((conn, id)) = sent.borrow_mut().take() {
+                handle.spawn(cancel(&conn.proxy, &hooks, id).map_err(|_| ()));
+            }
+        })
+    }
+
+    #[doc(hidden)]
+    fn update_telemetry<F: FnOnce(&mut Telemetry)>(&mut self, f: F) -> Result<()> {
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        if let Some(ref mut t) = inner.telemetry {
+            f(t);
+        }
+        Ok(())
     }
 
     fn command(&self) -> &Box<CommandProvider> {
@@ -129,18 +816,9 @@ impl Host for Plain {
     }
 
     fn set_command<P: CommandProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().command = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
-        }
-
-        Err(ErrorKind::MutRef("Local").into())
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        inner.providers.as_mut().unwrap().command = Box::new(provider);
+        Ok(())
     }
 
     fn package(&self) -> &Box<PackageProvider> {
@@ -148,18 +826,9 @@ impl Host for Plain {
     }
 
     fn set_package<P: PackageProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().package = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
-        }
-
-        Err(ErrorKind::MutRef("Local").into())
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        inner.providers.as_mut().unwrap().package = Box::new(provider);
+        Ok(())
     }
 
     fn service(&self) -> &Box<ServiceProvider> {
@@ -167,56 +836,221 @@ impl Host for Plain {
     }
 
     fn set_service<P: ServiceProvider + 'static>(&mut self, provider: P) -> Result<()> {
-        // @todo Is this a good thing to do, or should we introduce a Mutex?
-        for _ in 0..5 {
-            match Arc::get_mut(&mut self.inner) {
-                Some(inner) => {
-                    inner.providers.as_mut().unwrap().service = Box::new(provider);
-                    return Ok(());
-                },
-                None => sleep(Duration::from_millis(1)),
-            }
-        }
+        let inner = get_mut(&mut self.inner, "Pool")?;
+        inner.providers.as_mut().unwrap().service = Box::new(provider);
+        Ok(())
+    }
+
+    fn options(&self) -> &HostOptions {
+        &self.inner.options
+    }
 
-        Err(ErrorKind::MutRef("Local").into())
+    fn options_mut(&mut self) -> Result<&mut HostOptions> {
+        Ok(&mut get_mut(&mut self.inner, "Pool")?.options)
     }
 }
 
-impl Service for Plain {
+impl Service for Pool {
     type Request = InMessage;
     type Response = InMessage;
     type Error = Error;
     type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
 
-    fn call(&self, req: Self::Request) -> Self::Future {
-        debug!("Sending JSON request: {}", req.get_ref());
+    fn call(&self, mut req: Self::Request) -> Self::Future {
+        if let Err(e) = check_read_only(self, &mut req) {
+            return Box::new(future::err(e));
+        }
 
-        Box::new(self.inner.inner.call(req)
-            .chain_err(|| "Error while running provider on host")
-            .and_then(|mut msg| {
-                let body = msg.take_body();
-                let header = msg.into_inner();
+        self.dispatch(req).1
+    }
+}
 
-                debug!("Received JSON response: {}", header);
+// Resolve a `host:port` string into the list of addresses to try.
+fn resolve(addr: &str) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<_> = addr.to_socket_addrs()
+        .chain_err(|| ErrorKind::Resolve { host: addr.into() })?
+        .collect();
 
-                let result: result::Result<serde_json::Value, String> = match serde_json::from_value(header)
-                    .chain_err(|| "Could not decode response from host")
-                {
-                    Ok(r) => r,
-                    Err(e) => return Box::new(future::err(e)),
-                };
+    if addrs.is_empty() {
+        Err(ErrorKind::Resolve { host: addr.into() }.into())
+    } else {
+        Ok(addrs)
+    }
+}
 
-                let msg = match result {
-                    Ok(m) => m,
-                    Err(e) => return Box::new(future::err(ErrorKind::Remote(e).into())),
-                };
+// Connect to the first address that accepts the connection. `addrs` must not
+// be empty.
+fn connect_any(mut addrs: Vec<SocketAddr>, socket: SocketConfig, handle: &Handle) -> Box<Future<Item = (SocketAddr, ClientProxy<InMessage, InMessage, io::Error>, String), Error = Error>> {
+    let addr = addrs.remove(0);
+    let handle = handle.clone();
 
-                Box::new(future::ok(match body {
-                    Some(b) => Message::WithBody(msg, b),
-                    None => Message::WithoutBody(msg),
-                }))
-            }))
+    Box::new(connect_proxy(&addr, socket, &handle)
+        .map(move |(proxy, agent_version)| (addr, proxy, agent_version))
+        .or_else(move |e| if addrs.is_empty() {
+            Box::new(future::err(e))
+        } else {
+            warn!("{}; trying next address", e);
+            connect_any(addrs, socket, &handle)
+        }))
+}
+
+// Connect to the agent at `addr`, returning the connection and the agent's
+// version.
+fn connect_proxy(addr: &SocketAddr, socket: SocketConfig, handle: &Handle) -> Box<Future<Item = (ClientProxy<InMessage, InMessage, io::Error>, String), Error = Error>> {
+    info!("Connecting to host {}", addr);
+    let handle = handle.clone();
+
+    Box::new(TcpStream::connect(addr, &handle)
+        .and_then(move |stream| socket.apply(&stream).map(|_| stream))
+        .chain_err(|| "Could not connect to host")
+        .and_then(move |stream| {
+            info!("Connected!");
+            open_proxy(stream, socket, &handle)
+        }))
+}
+
+// Bind the protocol to a connected stream and perform the handshake.
+fn open_proxy<T>(stream: T, socket: SocketConfig, handle: &Handle) -> Box<Future<Item = (ClientProxy<InMessage, InMessage, io::Error>, String), Error = Error>>
+    where T: AsyncRead + AsyncWrite + 'static
+{
+    let gzip = GzipSwitch::default();
+    let proxy = JsonLineProto {
+        format: socket.format,
+        gzip: gzip.clone(),
+        ..JsonLineProto::default()
+    }.bind_client(handle, stream);
+
+    Box::new(handshake(&proxy, socket.gzip, gzip)
+        .map(move |agent_version| (proxy, agent_version)))
+}
+
+// Exchange versions with the agent, returning the agent's version. If we
+// offer gzip and the agent agrees, `switch` is turned on.
+fn handshake(proxy: &ClientProxy<InMessage, InMessage, io::Error>, gzip: bool, switch: GzipSwitch) -> Box<Future<Item = String, Error = Error>> {
+    let msg = match serde_json::to_value(Envelope::Hello(Hello {
+            version: ::VERSION.into(),
+            protocol: ::PROTOCOL_VERSION,
+            gzip: gzip,
+        }))
+        .chain_err(|| "Could not serialize handshake")
+    {
+        Ok(v) => Message::WithoutBody(v),
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(decode_response(proxy, &Hooks::default(), msg)
+        .then(move |result| {
+            let hello: Hello = match result {
+                Ok(msg) => serde_json::from_value(msg.into_inner())
+                    .chain_err(|| "Could not decode handshake from host")?,
+                // Agents that predate the handshake reject it as a malformed
+                // request.
+                Err(Error(ErrorKind::Remote(_), _)) => return Err(ErrorKind::VersionMismatch {
+                    local: ::PROTOCOL_VERSION,
+                    remote: 0,
+                }.into()),
+                Err(e) => return Err(e),
+            };
+
+            if hello.protocol == ::PROTOCOL_VERSION {
+                info!("Connected to agent version {}", hello.version);
+                switch.negotiate(gzip, hello.gzip);
+                Ok(hello.version)
+            } else {
+                Err(ErrorKind::VersionMismatch {
+                    local: ::PROTOCOL_VERSION,
+                    remote: hello.protocol,
+                }.into())
+            }
+        }))
+}
+
+fn call_proxy(proxy: &ClientProxy<InMessage, InMessage, io::Error>, hooks: &Hooks, id: u64, req: InMessage) -> Box<Future<Item = InMessage, Error = Error>> {
+    let (request, body) = match req {
+        Message::WithBody(v, b) => (v, Some(b)),
+        Message::WithoutBody(v) => (v, None),
+    };
+
+    let cid = correlation_id();
+    debug!("Sending request {} with id {}", cid, id);
+
+    let header = match serde_json::to_value(Envelope::Request { id, cid: cid.clone(), request })
+        .chain_err(|| "Could not serialize request")
+    {
+        Ok(v) => v,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(decode_response(proxy, hooks, match body {
+            Some(b) => Message::WithBody(header, b),
+            None => Message::WithoutBody(header),
+        })
+        .map_err(move |e| e.chain_err(|| format!("Request {} failed", cid))))
+}
+
+// Generate an id to identify a request in both the client's and the agent's
+// logs. Unlike the envelope id, this is unique across connections.
+fn correlation_id() -> String {
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    format!("{:x}-{:x}-{:x}", process::id(), now, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// Ask the host to abort the request with the given ID on this connection.
+fn cancel(proxy: &ClientProxy<InMessage, InMessage, io::Error>, hooks: &Hooks, id: u64) -> Box<Future<Item = (), Error = Error>> {
+    let msg = match serde_json::to_value(Envelope::Cancel { id: id })
+        .chain_err(|| "Could not serialize cancel request")
+    {
+        Ok(v) => Message::WithoutBody(v),
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(decode_response(proxy, hooks, msg).map(|_| ()))
+}
+
+fn decode_response(proxy: &ClientProxy<InMessage, InMessage, io::Error>, hooks: &Hooks, req: InMessage) -> Box<Future<Item = InMessage, Error = Error>> {
+    debug!("Sending JSON request: {}", redact(req.get_ref()));
+
+    if let Some(ref f) = hooks.request {
+        f(req.get_ref());
     }
+
+    let response_hook = hooks.response.clone();
+
+    Box::new(proxy.call(req)
+        .chain_err(|| "Error while running provider on host")
+        .and_then(move |mut msg| {
+            let body = msg.take_body();
+            let header = msg.into_inner();
+
+            debug!("Received JSON response: {}", redact(&header));
+
+            if let Some(ref f) = response_hook {
+                f(&header);
+            }
+
+            let result: result::Result<serde_json::Value, String> = match serde_json::from_value(header)
+                .chain_err(|| "Could not decode response from host")
+            {
+                Ok(r) => r,
+                Err(e) => return Box::new(future::err(e)),
+            };
+
+            let msg = match result {
+                Ok(m) => m,
+                Err(e) => return Box::new(future::err(ErrorKind::Remote(e).into())),
+            };
+
+            Box::new(future::ok(match body {
+                Some(b) => Message::WithBody(msg, b),
+                None => Message::WithoutBody(msg),
+            }))
+        }))
 }
 
 impl Decoder for JsonLineCodec {
@@ -224,16 +1058,20 @@ impl Decoder for JsonLineCodec {
     type Error = io::Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> io::Result<Option<Self::Item>> {
+        if let Some(chunk) = self.chunks.pop_front() {
+            return Ok(Some(Frame::Body { chunk: Some(chunk) }));
+        }
+
         let line = match buf.iter().position(|b| *b == b'\n') {
-            Some(n) => buf.split_to(n),
+            Some(n) if n <= self.max_frame_size => buf.split_to(n),
+            Some(_) => return Err(frame_too_large(self.max_frame_size)),
+            None if buf.len() > self.max_frame_size => return Err(frame_too_large(self.max_frame_size)),
             None => return Ok(None),
         };
 
         buf.split_to(1);
 
         if self.decoding_head {
-            debug!("Decoding header: {:?}", line);
-
             // The last byte in this frame is a bool that indicates
             // whether we have a body stream following or not.
             // This byte must exist, or our codec is buggered and
@@ -247,22 +1085,25 @@ impl Decoder for JsonLineCodec {
                 self.decoding_head = false;
             }
 
-            let frame = Frame::Message {
-                message: serde_json::from_slice(&line).map_err(|e| {
-                    io::Error::new(io::ErrorKind::Other, e)
-                })?,
-                body: *has_body == 1,
-            };
+            let message = serde_json::from_slice(&line).map_err(|e| {
+                io::Error::new(io::ErrorKind::Other, e)
+            })?;
 
-            debug!("Decoded header: {:?}", frame);
+            debug!("Decoded header: {}", redact(&message));
 
-            Ok(Some(frame))
+            Ok(Some(Frame::Message {
+                message: message,
+                body: *has_body == 1,
+            }))
         } else {
             debug!("Decoding body chunk: {:?}", line);
 
             let frame = if line.is_empty() {
                 self.decoding_head = true;
                 Frame::Body { chunk: None }
+            } else if self.gzip.enabled() {
+                self.chunks.extend(decode_chunks(&line, self.max_frame_size)?);
+                Frame::Body { chunk: self.chunks.pop_front() }
             } else {
                 Frame::Body { chunk: Some(line.freeze()) }
             };
@@ -274,6 +1115,92 @@ impl Decoder for JsonLineCodec {
     }
 }
 
+impl GzipSwitch {
+    /// Turn compression on if both we and the peer offered it, returning
+    /// whether it is on.
+    pub fn negotiate(&self, ours: bool, theirs: bool) -> bool {
+        self.0.set(ours && theirs);
+        ours && theirs
+    }
+
+    fn enabled(&self) -> bool {
+        self.0.get()
+    }
+}
+
+// Encode a batch of body chunks, joined by newlines, as one or more lines.
+// Small batches are sent as a raw line per chunk, as they aren't worth
+// compressing.
+fn encode_batch(batch: &[u8], buf: &mut BytesMut) -> io::Result<()> {
+    if batch.len() < GZIP_MIN_BATCH {
+        for chunk in batch.split(|b| *b == b'\n') {
+            buf.extend(&[CHUNK_RAW]);
+            buf.extend(chunk);
+            buf.extend(b"\n");
+        }
+        return Ok(());
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(batch)?;
+    let compressed = encoder.finish()?;
+
+    let mut out = Vec::with_capacity(compressed.len() + 2);
+    out.push(CHUNK_GZIP);
+    for b in compressed {
+        match b {
+            b'\n' => out.extend_from_slice(&[ESCAPE, b'n']),
+            ESCAPE => out.extend_from_slice(&[ESCAPE, ESCAPE]),
+            b => out.push(b),
+        }
+    }
+    out.push(b'\n');
+    buf.extend(&out);
+    Ok(())
+}
+
+// Decode a body line into the chunks it holds. A compressed batch may not
+// decompress to more than `max` bytes, so that a small gzip bomb can't get
+// around the frame size limit.
+fn decode_chunks(line: &[u8], max: usize) -> io::Result<Vec<Bytes>> {
+    let (kind, data) = line.split_first().ok_or_else(invalid_chunk)?;
+    match *kind {
+        CHUNK_RAW => Ok(vec![Bytes::from(data)]),
+        CHUNK_GZIP => {
+            let mut compressed = Vec::with_capacity(data.len());
+            let mut bytes = data.iter();
+            while let Some(&b) = bytes.next() {
+                compressed.push(if b == ESCAPE {
+                    match bytes.next() {
+                        Some(&b'n') => b'\n',
+                        Some(&ESCAPE) => ESCAPE,
+                        _ => return Err(invalid_chunk()),
+                    }
+                } else {
+                    b
+                });
+            }
+
+            let mut batch = Vec::new();
+            GzDecoder::new(&compressed[..]).take(max as u64 + 1).read_to_end(&mut batch)?;
+            if batch.len() > max {
+                return Err(frame_too_large(max));
+            }
+            Ok(batch.split(|b| *b == b'\n').map(Bytes::from).collect())
+        },
+        _ => Err(invalid_chunk()),
+    }
+}
+
+fn invalid_chunk() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid compressed body chunk")
+}
+
+// Returning an error from the codec closes the connection.
+fn frame_too_large(max: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Frame exceeds the maximum size of {} bytes", max))
+}
+
 impl Encoder for JsonLineCodec {
     type Item = Frame<serde_json::Value, Bytes, io::Error>;
     type Error = io::Error;
@@ -281,7 +1208,18 @@ impl Encoder for JsonLineCodec {
     fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> io::Result<()> {
         match msg {
             Frame::Message { message, body } => {
-                debug!("Encoding header: {:?}, {:?}", message, body);
+                let message = if self.format.sort_keys {
+                    sort_keys(message)
+                } else {
+                    message
+                };
+
+                if self.format.pretty_log {
+                    debug!("Encoding header: {}, {:?}",
+                        serde_json::to_string_pretty(&redact(&message)).unwrap_or_default(), body);
+                } else {
+                    debug!("Encoding header: {}, {:?}", redact(&message), body);
+                }
 
                 let json = serde_json::to_vec(&message)
                     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
@@ -292,8 +1230,11 @@ impl Encoder for JsonLineCodec {
             Frame::Body { chunk } => {
                 debug!("Encoding chunk: {:?}", chunk);
 
-                if let Some(chunk) = chunk {
-                    buf.extend(&chunk);
+                match chunk {
+                    // Batches write their own newlines
+                    Some(ref batch) if self.gzip.enabled() => return encode_batch(batch, buf),
+                    Some(chunk) => buf.extend(&chunk),
+                    None => (),
                 }
             }
             Frame::Error { error } => {
@@ -308,21 +1249,131 @@ impl Encoder for JsonLineCodec {
     }
 }
 
+// Recursively sort the keys of every object in `value`.
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(entries.into_iter()
+                .map(|(k, v)| (k, sort_keys(v)))
+                .collect())
+        },
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_keys).collect())
+        },
+        v => v,
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> JsonLineTransport<T> {
+    // Pass the batched body chunks to the codec as a single chunk. Body
+    // chunks never contain newlines, as the protocol is newline delimited,
+    // so the codec can split the batch up again.
+    fn send_batch(&mut self) -> Poll<(), io::Error> {
+        if self.batch.is_empty() {
+            return Ok(Async::Ready(()));
+        }
+
+        let mut joined = BytesMut::with_capacity(self.batch_len);
+        for (i, chunk) in self.batch.iter().enumerate() {
+            if i > 0 {
+                joined.extend(b"\n");
+            }
+            joined.extend(chunk);
+        }
+
+        match self.inner.start_send(Frame::Body { chunk: Some(joined.freeze()) })? {
+            AsyncSink::Ready => {
+                self.batch.clear();
+                self.batch_len = 0;
+                Ok(Async::Ready(()))
+            },
+            AsyncSink::NotReady(_) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Stream for JsonLineTransport<T> {
+    type Item = Frame<serde_json::Value, Bytes, io::Error>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        self.inner.poll()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Sink for JsonLineTransport<T> {
+    type SinkItem = Frame<serde_json::Value, Bytes, io::Error>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, frame: Self::SinkItem) -> StartSend<Self::SinkItem, io::Error> {
+        // The pipeline sends every chunk that is ready before flushing, so
+        // everything sent between flushes is compressed together.
+        if self.gzip.enabled() {
+            if let Frame::Body { chunk: Some(chunk) } = frame {
+                if self.batch_len >= GZIP_MAX_BATCH && !self.send_batch()?.is_ready() {
+                    return Ok(AsyncSink::NotReady(Frame::Body { chunk: Some(chunk) }));
+                }
+                self.batch_len += chunk.len() + 1;
+                self.batch.push(chunk);
+                return Ok(AsyncSink::Ready);
+            }
+        }
+
+        if !self.send_batch()?.is_ready() {
+            return Ok(AsyncSink::NotReady(frame));
+        }
+        self.inner.start_send(frame)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        if !self.send_batch()?.is_ready() {
+            return Ok(Async::NotReady);
+        }
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        if !self.send_batch()?.is_ready() {
+            return Ok(Async::NotReady);
+        }
+        self.inner.close()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + 'static> Transport for JsonLineTransport<T> {}
+
+impl JsonLineProto {
+    fn transport<T: AsyncRead + AsyncWrite>(&self, io: T) -> JsonLineTransport<T> {
+        let codec = JsonLineCodec {
+            decoding_head: true,
+            format: self.format,
+            max_frame_size: self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE),
+            gzip: self.gzip.clone(),
+            chunks: VecDeque::new(),
+        };
+
+        JsonLineTransport {
+            inner: io.framed(codec),
+            gzip: self.gzip.clone(),
+            batch: Vec::new(),
+            batch_len: 0,
+        }
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite + 'static> ClientProto<T> for JsonLineProto {
     type Request = serde_json::Value;
     type RequestBody = Bytes;
     type Response = serde_json::Value;
     type ResponseBody = Bytes;
     type Error = io::Error;
-    type Transport = Framed<T, JsonLineCodec>;
+    type Transport = JsonLineTransport<T>;
     type BindTransport = result::Result<Self::Transport, Self::Error>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        let codec = JsonLineCodec {
-            decoding_head: true,
-        };
-
-        Ok(io.framed(codec))
+        Ok(self.transport(io))
     }
 }
 
@@ -332,14 +1383,84 @@ impl<T: AsyncRead + AsyncWrite + 'static> ServerProto<T> for JsonLineProto {
     type Response = serde_json::Value;
     type ResponseBody = Bytes;
     type Error = io::Error;
-    type Transport = Framed<T, JsonLineCodec>;
+    type Transport = JsonLineTransport<T>;
     type BindTransport = result::Result<Self::Transport, Self::Error>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        let codec = JsonLineCodec {
-            decoding_head: true,
-        };
+        Ok(self.transport(io))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use super::{GzipSwitch, JsonLineCodec, CHUNK_GZIP, CHUNK_RAW};
+    use tokio_io::codec::{Decoder, Encoder};
+    use tokio_proto::streaming::pipeline::Frame;
+
+    fn codec(gzip: bool) -> JsonLineCodec {
+        let switch = GzipSwitch::default();
+        switch.negotiate(gzip, true);
+        JsonLineCodec {
+            decoding_head: false,
+            format: Default::default(),
+            max_frame_size: super::DEFAULT_MAX_FRAME_SIZE,
+            gzip: switch,
+            chunks: Default::default(),
+        }
+    }
+
+    // Encode `batch` as one body chunk, then decode every chunk it holds
+    fn round_trip(batch: &[u8]) -> (BytesMut, Vec<Bytes>) {
+        let mut buf = BytesMut::new();
+        codec(true).encode(Frame::Body { chunk: Some(Bytes::from(batch)) }, &mut buf).unwrap();
+        let wire = buf.clone();
+
+        let mut decoder = codec(true);
+        let mut chunks = Vec::new();
+        while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+            match frame {
+                Frame::Body { chunk: Some(c) } => chunks.push(c),
+                f => panic!("Unexpected frame {:?}", f),
+            }
+        }
+        (wire, chunks)
+    }
+
+    #[test]
+    fn test_small_batch_is_sent_raw() {
+        let (wire, chunks) = round_trip(b"one\ntwo");
+        assert_eq!(&wire[..], b"rone\nrtwo\n");
+        assert_eq!(chunks, vec![Bytes::from("one"), Bytes::from("two")]);
+    }
+
+    #[test]
+    fn test_large_batch_is_compressed() {
+        let lines: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        let (wire, chunks) = round_trip(lines.join("\n").as_bytes());
+
+        assert_eq!(wire[0], CHUNK_GZIP);
+        assert_eq!(wire.iter().filter(|b| **b == b'\n').count(), 1);
+        assert_eq!(chunks, lines.into_iter().map(Bytes::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_decompressed_batch_is_limited() {
+        let mut buf = BytesMut::new();
+        codec(true).encode(Frame::Body { chunk: Some(Bytes::from(vec![b'a'; 64 * 1024])) }, &mut buf).unwrap();
+        assert_eq!(buf[0], CHUNK_GZIP);
+        assert!(buf.len() < 1024);
+
+        let mut decoder = codec(true);
+        decoder.max_frame_size = 32 * 1024;
+        assert!(decoder.decode(&mut buf).is_err());
+    }
 
-        Ok(io.framed(codec))
+    #[test]
+    fn test_no_gzip_sends_chunk_as_is() {
+        let mut buf = BytesMut::new();
+        codec(false).encode(Frame::Body { chunk: Some(Bytes::from("one")) }, &mut buf).unwrap();
+        assert_eq!(&buf[..], b"one\n");
+        assert!(buf[0] != CHUNK_RAW);
     }
 }
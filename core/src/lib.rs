@@ -126,30 +126,37 @@
 extern crate bytes;
 extern crate erased_serde;
 #[macro_use] extern crate error_chain;
+extern crate flate2;
 extern crate futures;
 extern crate hostname;
 #[macro_use] extern crate intecture_core_derive;
 extern crate ipnetwork;
+#[cfg(unix)] extern crate libc;
+#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
 extern crate pnet;
 extern crate regex;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate tokio_process;
 extern crate tokio_proto;
 extern crate tokio_service;
+#[cfg(unix)] extern crate tokio_uds;
 extern crate users;
 
 pub mod command;
 pub mod errors;
+pub mod file;
 pub mod host;
 mod message;
 pub mod prelude {
     //! The API prelude.
     pub use command::{self, Command};
+    pub use file::{self, File};
     pub use host::Host;
     pub use host::remote::{self, Plain};
     pub use host::local::{self, Local};
@@ -158,12 +165,28 @@ pub mod prelude {
     pub use telemetry::{self, Cpu, FsMount, LinuxDistro, Os, OsFamily, OsPlatform, Telemetry};
 }
 pub mod package;
+#[cfg(unix)] pub mod payload;
+pub mod redact;
+mod registry;
 mod request;
+pub mod resource;
 pub mod service;
 mod target;
 pub mod telemetry;
+pub mod util;
+mod validate;
 
+pub use registry::BUILTIN_PRIORITY;
 #[doc(hidden)]
-pub use message::{FromMessage, InMessage};
+pub use message::{drain_body, error_to_msg, ok_to_msg, FromMessage, InMessage};
 #[doc(hidden)]
-pub use request::Request;
+pub use request::{Envelope, Hello, Request};
+
+/// The version of this crate.
+pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+/// The version of the protocol spoken between clients and agents.
+///
+/// This is only incremented when a change breaks compatibility, so a client
+/// and agent can only talk to each other if their protocol versions match.
+pub const PROTOCOL_VERSION: u32 = 5;
@@ -5,14 +5,48 @@
 // modified, or distributed except according to those terms.
 
 use bytes::Bytes;
+use error_chain::ChainedError;
 use errors::*;
+use futures::{Future, Stream};
 use serde_json::Value;
-use std::io;
+use std::{io, result};
+use std::path::PathBuf;
 use tokio_core::reactor::Handle;
 use tokio_proto::streaming::{Body, Message};
 
 pub type InMessage = Message<Value, Body<Bytes, io::Error>>;
 
+/// Consume and discard a message body in the background.
+///
+/// If a body is dropped without being read, the peer can be left waiting to
+/// stream the rest of it, which can wedge the connection.
+pub fn drain_body(body: Body<Bytes, io::Error>, handle: &Handle) {
+    handle.spawn(body.for_each(|_| Ok(())).map_err(|_| ()));
+}
+
+/// Wrap a successful response in the result that is sent to clients.
+#[doc(hidden)]
+pub fn ok_to_msg(mut msg: InMessage) -> InMessage {
+    let value = msg.get_mut().take();
+    let response: result::Result<Value, String> = Ok(value);
+    // If we can't serialize this, we can't serialize anything, so
+    // panicking is appropriate.
+    *msg.get_mut() = ::serde_json::to_value(response)
+        .expect("Cannot serialize ResponseResult::Ok. This is bad...");
+    msg
+}
+
+/// Build the result that is sent to clients when a request fails.
+#[doc(hidden)]
+pub fn error_to_msg<E: ChainedError>(e: E) -> InMessage {
+    let response: result::Result<(), String> = Err(format!("{}", e.display_chain()));
+    // If we can't serialize this, we can't serialize anything, so
+    // panicking is appropriate.
+    let value = ::serde_json::to_value(response)
+        .expect("Cannot serialize ResponseResult::Err. This is bad...");
+    Message::WithoutBody(value)
+}
+
 // @todo This trait might disappear when TryFrom is stabilised.
 // https://github.com/rust-lang/rust/issues/33417
 pub trait FromMessage {
@@ -54,3 +88,43 @@ impl IntoMessage for () {
         Ok(Message::WithoutBody(Value::Null))
     }
 }
+
+impl FromMessage for Option<String> {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        match msg.into_inner() {
+            Value::String(s) => Ok(Some(s)),
+            Value::Null => Ok(None),
+            _ => Err("Non-string message received".into())
+        }
+    }
+}
+
+impl IntoMessage for Option<String> {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        Ok(Message::WithoutBody(self.map_or(Value::Null, Value::String)))
+    }
+}
+
+impl FromMessage for Option<PathBuf> {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        Ok(::serde_json::from_value(msg.into_inner()).chain_err(|| "Non-path message received")?)
+    }
+}
+
+impl IntoMessage for Option<PathBuf> {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        Ok(Message::WithoutBody(::serde_json::to_value(self).chain_err(|| "Could not serialize path")?))
+    }
+}
+
+impl FromMessage for Vec<String> {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        Ok(::serde_json::from_value(msg.into_inner()).chain_err(|| "Non-string array message received")?)
+    }
+}
+
+impl IntoMessage for Vec<String> {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        Ok(Message::WithoutBody(Value::Array(self.into_iter().map(Value::String).collect())))
+    }
+}
@@ -11,13 +11,20 @@
 
 mod providers;
 
-use command::Child;
+use command::{Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
+use host::local::Local;
+use request::Executable;
+use validate;
 #[doc(hidden)]
-pub use self::providers::{factory, PackageProvider, Apt, Dnf, Homebrew, Nix, Pkg, Yum};
+pub use self::providers::{
+    brew_path, factory, provider, PackageProvider, Apt, Cargo, Dnf, Flatpak,
+    Homebrew, Nix, Npm, Pkg, Portage, Snap, Yum
+};
+pub use self::providers::{register_package_provider, set_strict, Operation, PackageProviderFactory, Provider};
 
 /// Represents a system package to be managed for a host.
 ///
@@ -41,7 +48,7 @@ pub use self::providers::{factory, PackageProvider, Apt, Dnf, Homebrew, Nix, Pkg
 ///
 ///let host = Local::new(&handle).wait().unwrap();
 ///
-///let nginx = Package::new(&host, "nginx");
+///let nginx = Package::new(&host, "nginx").unwrap();
 ///let result = nginx.install().and_then(|status| {
 ///    match status {
 ///        // We're performing the install
@@ -49,7 +56,7 @@ pub use self::providers::{factory, PackageProvider, Apt, Dnf, Homebrew, Nix, Pkg
 ///            .map(|_| println!("Installed"))
 ///            .map_err(|e| {
 ///                match *e.kind() {
-///                    ErrorKind::Command(ref output) => println!("Failed with output: {}", output),
+///                    ErrorKind::Command { ref stdout, .. } => println!("Failed with output: {}", stdout),
 ///                    _ => unreachable!(),
 ///                }
 ///                e
@@ -69,47 +76,207 @@ pub use self::providers::{factory, PackageProvider, Apt, Dnf, Homebrew, Nix, Pkg
 pub struct Package<H: Host> {
     host: H,
     name: String,
+    provider: Option<Provider>,
+    source: Option<String>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "bool"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct PackageInstalled {
+    #[serde(deserialize_with = "validate::de_package_name")]
     name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "Child"]
-#[future = "FutureResult<Self::Response, Error>"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct PackageInstall {
+    #[serde(deserialize_with = "validate::de_package_name")]
     name: String,
+    #[serde(default)]
+    proxy: Option<String>,
+    #[serde(default)]
+    provider: Option<Provider>,
+    #[serde(default)]
+    source: Option<String>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "Child"]
-#[future = "FutureResult<Self::Response, Error>"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageInstallFile {
+    #[serde(deserialize_with = "validate::de_package_file")]
+    path: String,
+    #[serde(default)]
+    proxy: Option<String>,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageInstallMany {
+    #[serde(deserialize_with = "validate::de_package_names")]
+    names: Vec<String>,
+    #[serde(default)]
+    proxy: Option<String>,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageRefresh {
+    #[serde(default)]
+    proxy: Option<String>,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct PackageUninstall {
+    #[serde(deserialize_with = "validate::de_package_name")]
     name: String,
+    #[serde(default)]
+    proxy: Option<String>,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageRequiresRoot {
+    op: Operation,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageSearch {
+    #[serde(deserialize_with = "validate::de_package_name")]
+    name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct PackageCandidate {
+    #[serde(deserialize_with = "validate::de_package_name")]
+    name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 impl<H: Host + 'static> Package<H> {
     /// Create a new `Package` with the default [`Provider`](enum.Provider.html).
-    pub fn new(host: &H, name: &str) -> Package<H> {
-        Package {
+    ///
+    /// Returns `ErrorKind::InvalidName` if `name` contains characters that
+    /// are unsafe to pass to a shell command.
+    pub fn new(host: &H, name: &str) -> Result<Package<H>> {
+        validate::package_name(name)?;
+
+        Ok(Package {
             host: host.clone(),
             name: name.into(),
-        }
+            provider: None,
+            source: None,
+        })
+    }
+
+    /// Manage this package with the given provider, rather than the host's
+    /// default `Package` provider.
+    ///
+    /// This is useful on hosts with several package managers, e.g. Apt and
+    /// Snap, as it doesn't change the provider for any other `Package`.
+    /// Operations return `ErrorKind::ProviderUnavailable` if the provider
+    /// isn't available on the host.
+    pub fn using(mut self, provider: Provider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Install the package from a specific source, rather than the
+    /// provider's default.
+    ///
+    /// What a source is depends on the provider:
+    ///
+    /// - Apt: a target release, e.g. "stretch-backports"
+    /// - Dnf and Yum: a repo to enable, e.g. "epel"
+    /// - Flatpak: a remote, e.g. "flathub"
+    /// - Nix: a channel, e.g. "nixpkgs", so the package is installed by
+    ///   its attribute path
+    /// - Snap: a channel, e.g. "latest/edge"
+    ///
+    /// Other providers ignore the source. Returns `ErrorKind::InvalidName`
+    /// if `source` contains characters that are unsafe to pass to a shell
+    /// command.
+    pub fn source(mut self, source: &str) -> Result<Self> {
+        validate::package_name(source)?;
+        self.source = Some(source.into());
+        Ok(self)
+    }
+
+    /// Check whether an operation on the package requires root privileges.
+    ///
+    /// Combined with [`User::is_root()`](../telemetry/struct.User.html#method.is_root),
+    /// this lets you fail fast before attempting an install that will be
+    /// denied. Queries, such as [`installed()`](#method.installed), never
+    /// require root.
+    pub fn requires_root(&self, op: Operation) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(self.host.request(PackageRequiresRoot { op, provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "requires_root" }))
+    }
+
+    /// Check whether installing the package requires root privileges.
+    pub fn install_requires_root(&self) -> Box<Future<Item = bool, Error = Error>> {
+        self.requires_root(Operation::Install)
+    }
+
+    /// Check whether uninstalling the package requires root privileges.
+    pub fn uninstall_requires_root(&self) -> Box<Future<Item = bool, Error = Error>> {
+        self.requires_root(Operation::Uninstall)
     }
 
     /// Check if the package is installed.
     pub fn installed(&self) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(self.host.request(PackageInstalled { name: self.name.clone() })
-            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "installed" }))
+        Box::new(self.installed_version().map(|v| v.is_some()))
+    }
+
+    /// Get the installed version of the package, or `None` if it isn't
+    /// installed.
+    ///
+    /// Versions are reported by the Apt, Dnf, Homebrew and Yum providers.
+    /// Other providers, and Homebrew casks, return an empty version for a
+    /// package that is installed.
+    pub fn installed_version(&self) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(self.host.request(PackageInstalled { name: self.name.clone(), provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "installed_version" }))
+    }
+
+    /// Get the version of the package that would be installed from the
+    /// host's package index, or `None` if the package can't be found.
+    ///
+    /// If this differs from [`installed_version()`](#method.installed_version),
+    /// an upgrade is pending. Note that the index may be stale, so you may
+    /// want to [`Packages::refresh_index()`](struct.Packages.html#method.refresh_index)
+    /// first.
+    pub fn candidate_version(&self) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(self.host.request(PackageCandidate { name: self.name.clone(), provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "candidate_version" }))
+    }
+
+    /// Check whether the package exists in the host's configured
+    /// repositories, whether or not it is installed.
+    ///
+    /// Use this before installing a package to tell a misspelled name
+    /// apart from a failed install. It is supported by the Apt, Dnf,
+    /// Homebrew and Yum providers. Other providers return an error.
+    pub fn exists_in_repo(&self) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(self.host.request(PackageSearch { name: self.name.clone(), provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "exists_in_repo" }))
     }
 
     /// Install the package.
@@ -130,15 +297,18 @@ impl<H: Host + 'static> Package<H> {
     {
         let host = self.host.clone();
         let name = self.name.clone();
+        let provider = self.provider;
+        let source = self.source.clone();
 
         Box::new(self.installed()
             .and_then(move |installed| {
                 if installed {
                     Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
                 } else {
-                    Box::new(host.request(PackageInstall { name })
+                    let proxy = host.proxy().map(|p| p.to_owned());
+                    Box::new(host.request(PackageInstall { name, proxy, provider, source })
                         .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "install" })
-                        .map(|msg| Some(Child::from(msg))))
+                        .map(Some))
                 }
             }))
     }
@@ -161,16 +331,320 @@ impl<H: Host + 'static> Package<H> {
     {
         let host = self.host.clone();
         let name = self.name.clone();
+        let provider = self.provider;
 
         Box::new(self.installed()
             .and_then(move |installed| {
                 if installed {
-                    Box::new(host.request(PackageUninstall { name })
+                    let proxy = host.proxy().map(|p| p.to_owned());
+                    Box::new(host.request(PackageUninstall { name, proxy, provider })
                         .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "uninstall" })
-                        .map(|msg| Some(Child::from(msg))))
+                        .map(Some))
                 } else {
                     Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
                 }
             }))
     }
 }
+
+/// The packages on a host as a whole, for operations that aren't about a
+/// single [`Package`](struct.Package.html).
+///
+///# Example
+///
+/// Refresh the package index, then install several packages at once.
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::package::Packages;
+///use intecture_api::prelude::*;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = Local::new(&handle).wait().unwrap();
+///
+///let packages = Packages::new(&host);
+///let result = packages.refresh_index()
+///    .and_then(|child| child.result().unwrap())
+///    .and_then(|_| packages.install_many(&["nginx", "php-fpm"]));
+///
+///core.run(result).unwrap();
+///# }
+///```
+pub struct Packages<H: Host> {
+    host: H,
+    provider: Option<Provider>,
+}
+
+impl<H: Host + 'static> Packages<H> {
+    /// Manage the host's packages with the default [`Provider`](enum.Provider.html).
+    pub fn new(host: &H) -> Packages<H> {
+        Packages {
+            host: host.clone(),
+            provider: None,
+        }
+    }
+
+    /// Manage the host's packages with the given provider, rather than the
+    /// host's default `Package` provider, as with
+    /// [`Package::using()`](struct.Package.html#method.using).
+    pub fn using(mut self, provider: Provider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Install several packages in a single transaction.
+    ///
+    /// All of the packages that are not already installed are passed to a
+    /// single invocation of the package manager, which is faster than
+    /// installing them one by one and lets the package manager resolve
+    /// their dependencies together.
+    ///
+    ///## Idempotence
+    ///
+    /// This function is idempotent. It returns `Option::None` if every
+    /// package is already installed, otherwise `Option::Some` with a handle
+    /// to the installation of the missing packages. See
+    /// [`Package::install()`](struct.Package.html#method.install) for
+    /// details.
+    pub fn install_many(&self, names: &[&str]) -> Box<Future<Item = Option<Child>, Error = Error>> {
+        let provider = self.provider;
+        let packages = match names.iter()
+            .map(|name| Package::new(&self.host, name).map(|p| Package { provider, ..p }))
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(p) => p,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let host = self.host.clone();
+        let checks: Vec<_> = packages.iter().map(|p| p.installed()).collect();
+
+        Box::new(future::join_all(checks)
+            .and_then(move |installed| {
+                let names: Vec<_> = packages.into_iter()
+                    .zip(installed)
+                    .filter(|&(_, installed)| !installed)
+                    .map(|(p, _)| p.name)
+                    .collect();
+
+                if names.is_empty() {
+                    Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
+                } else {
+                    let proxy = host.proxy().map(|p| p.to_owned());
+                    Box::new(host.request(PackageInstallMany { names, proxy, provider })
+                        .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "install_many" })
+                        .map(Some))
+                }
+            }))
+    }
+
+    /// Install a package from a file that is already on the host, e.g. a
+    /// `.deb` or `.rpm` built locally and uploaded with the
+    /// [`File`](../file/struct.File.html) endpoint.
+    ///
+    /// `path` is a path on the host and should be absolute. The package's
+    /// dependencies are installed from the repositories where the provider
+    /// supports it. This is supported by the Apt, Dnf, Pkg and Yum
+    /// providers. Other providers return an error.
+    ///
+    /// Unlike [`Package::install()`](struct.Package.html#method.install),
+    /// this isn't idempotent, as the package name isn't known until the
+    /// file is read. The returned `Child` holds a handle to the live output
+    /// and the result of the installation. See
+    /// [`Command` docs](../command/struct.Command.html) for detailed usage.
+    pub fn install_file(&self, path: &str) -> Box<Future<Item = Child, Error = Error>> {
+        if let Err(e) = validate::package_file(path) {
+            return Box::new(future::err(e));
+        }
+
+        let proxy = self.host.proxy().map(|p| p.to_owned());
+        Box::new(self.host.request(PackageInstallFile { path: path.into(), proxy, provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "install_file" }))
+    }
+
+    /// Refresh the host's package index, e.g. `apt-get update`.
+    ///
+    /// Package managers install from a local copy of their repositories'
+    /// index, which may be out of date. Refresh it before installing a
+    /// package that has been published recently. Providers that have no
+    /// local index, such as Cargo and npm, succeed without doing anything.
+    ///
+    /// The returned `Child` holds a handle to the live output and the result
+    /// of the refresh. See [`Command` docs](../command/struct.Command.html)
+    /// for detailed usage.
+    pub fn refresh_index(&self) -> Box<Future<Item = Child, Error = Error>> {
+        let proxy = self.host.proxy().map(|p| p.to_owned());
+        Box::new(self.host.request(PackageRefresh { proxy, provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Package", func: "refresh_index" }))
+    }
+}
+
+impl Executable for PackageInstall {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (name, opts) = (self.name, proxy_opts(self.proxy));
+        let source = self.source;
+        if let Some(ref s) = source {
+            if let Err(e) = validate::package_name(s) {
+                return future::err(e);
+            }
+        }
+
+        match with_provider(host, self.provider, |p| match source {
+            Some(ref s) => p.install_from(host, &name, s, &opts),
+            None => p.install(host, &name, &opts),
+        }) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for PackageInstallFile {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (path, opts) = (self.path, proxy_opts(self.proxy));
+        match with_provider(host, self.provider, |p| p.install_file(host, &path, &opts)) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for PackageInstallMany {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (names, opts) = (self.names, proxy_opts(self.proxy));
+        match with_provider(host, self.provider, |p| p.install_many(host, &names, &opts)) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for PackageRefresh {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let opts = proxy_opts(self.proxy);
+        match with_provider(host, self.provider, |p| p.refresh(host, &opts)) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for PackageUninstall {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (name, opts) = (self.name, proxy_opts(self.proxy));
+        match with_provider(host, self.provider, |p| p.uninstall(host, &name, &opts)) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for PackageInstalled {
+    type Response = Option<String>;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.installed_version(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for PackageSearch {
+    type Response = bool;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.exists_in_repo(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for PackageCandidate {
+    type Response = Option<String>;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.candidate_version(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for PackageRequiresRoot {
+    type Response = bool;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let op = self.op;
+        future::result(with_provider(host, self.provider, |p| p.requires_root(op)))
+    }
+}
+
+// Call `f` with the provider chosen for an operation, or the host's default
+// provider if there isn't one.
+fn with_provider<F, R>(host: &Local, provider: Option<Provider>, f: F) -> Result<R>
+    where F: FnOnce(&PackageProvider) -> R
+{
+    match provider {
+        Some(p) => Ok(f(&*providers::provider(p)?)),
+        None => Ok(f(&**host.package())),
+    }
+}
+
+// Options that point the package manager at a proxy. Both the lower and
+// upper case variables are set, as tools disagree on which they read.
+fn proxy_opts(proxy: Option<String>) -> ExecOptions {
+    let mut opts = ExecOptions::default();
+    if let Some(proxy) = proxy {
+        for key in &["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+            opts.env.push(((*key).into(), proxy.clone()));
+        }
+    }
+    opts
+}
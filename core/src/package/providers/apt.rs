@@ -4,15 +4,13 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{self, Child};
-use error_chain::ChainedError;
+use command::{hooks, Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use regex::Regex;
-use std::process;
+use regex::{self, Regex};
 use super::PackageProvider;
 use tokio_process::CommandExt;
 
@@ -20,8 +18,7 @@ pub struct Apt;
 
 impl PackageProvider for Apt {
     fn available() -> Result<bool> {
-        Ok(process::Command::new("/usr/bin/type")
-            .arg("apt-get")
+        Ok(hooks::command("/usr/bin/type", &["apt-get"])
             .status()
             .chain_err(|| "Could not determine provider availability")?
             .success())
@@ -30,13 +27,12 @@ impl PackageProvider for Apt {
     fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("dpkg")
-            .args(&["--get-selections"])
+        Box::new(hooks::command("dpkg", &["--get-selections"])
             .output_async(&host.handle())
             .chain_err(|| "Could not get installed packages")
             .and_then(move |output| {
                 if output.status.success() {
-                    let re = match Regex::new(&format!("(?m){}\\s+install$", name)) {
+                    let re = match Regex::new(&format!("(?m)^{}\\s+install$", regex::escape(&name))) {
                         Ok(r) => r,
                         Err(e) => return future::err(ErrorKind::Regex(e).into()),
                     };
@@ -49,19 +45,111 @@ impl PackageProvider for Apt {
             }))
     }
 
-    fn install(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["apt-get", "-y", "install", name])
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["apt-get", "-y", "install", name], opts)
     }
 
-    fn uninstall(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["apt-get", "-y", "remove", name])
+    fn install_from(&self, host: &Local, name: &str, source: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["apt-get", "-y", "-t", source, "install", name], opts)
+    }
+
+    fn install_file(&self, host: &Local, path: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        // Apt only treats the argument as a file if it contains a slash
+        let path = if path.contains('/') { path.to_owned() } else { format!("./{}", path) };
+        super::run(host, &["apt-get", "-y", "install", &path], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["apt-get", "-y", "install"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["apt-get", "update"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["apt-get", "-y", "remove", name], opts)
+    }
+
+    fn installed_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        // `dpkg-query` exits with 1 if it has never heard of the package, so
+        // check the output rather than the exit status.
+        Box::new(hooks::command("dpkg-query", &["--show", "--showformat", "${Status}\t${Version}\n", name])
+            .output_async(host.handle())
+            .chain_err(|| ErrorKind::SystemCommand("dpkg-query"))
+            .and_then(|output| {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if output.status.success() || stderr.contains("no packages found") {
+                    future::ok(selected_version(&String::from_utf8_lossy(&output.stdout)))
+                } else {
+                    future::err(format!("Error running `dpkg-query`: {}", stderr).into())
+                }
+            }))
+    }
+
+    fn candidate_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        policy(host, name, "Candidate")
+    }
+
+    fn exists_in_repo(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        super::succeeds(host, "apt-cache", &["show", name])
+    }
+}
+
+// Get the version of a package selected for installation from the output of
+// `dpkg-query`, formatted as "<want> <flag> <status>\t<version>". This is the
+// same selection that `dpkg --get-selections` reports.
+fn selected_version(output: &str) -> Option<String> {
+    output.lines()
+        .filter_map(|l| {
+            let mut fields = l.splitn(2, '\t');
+            match (fields.next(), fields.next()) {
+                (Some(status), Some(version)) if status.starts_with("install ") => Some(version.trim().to_owned()),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+// Get a version from the output of `apt-cache policy`, which includes lines
+// formatted as "  Installed: <version>" and "  Candidate: <version>". The
+// version is "(none)" if there isn't one, and unknown packages print nothing.
+// These labels are translated, so the output is forced into English.
+fn policy(host: &Local, name: &str, field: &'static str) -> Box<Future<Item = Option<String>, Error = Error>> {
+    Box::new(hooks::command("apt-cache", &["policy", name])
+        .env("LC_ALL", "C")
+        .output_async(host.handle())
+        .chain_err(|| ErrorKind::SystemCommand("apt-cache"))
+        .and_then(move |output| {
+            if output.status.success() {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let prefix = format!("{}:", field);
+                let version = stdout.lines()
+                    .map(|l| l.trim())
+                    .find(|l| l.starts_with(&prefix))
+                    .map(|l| l[prefix.len()..].trim())
+                    .and_then(|v| if v == "(none)" { None } else { Some(v.to_owned()) });
+                future::ok(version)
+            } else {
+                future::err(format!("Error running `apt-cache policy`: {}",
+                    String::from_utf8_lossy(&output.stderr)).into())
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::selected_version;
+
+    #[test]
+    fn test_selected_version() {
+        assert_eq!(selected_version("install ok installed\t1.10.3-1\n"), Some("1.10.3-1".into()));
+        assert_eq!(selected_version("deinstall ok config-files\t1.10.3-1\n"), None);
+        assert_eq!(selected_version("unknown ok not-installed\t\n"), None);
+        assert_eq!(selected_version(""), None);
+        // Multiarch packages are listed once per architecture
+        assert_eq!(selected_version("deinstall ok config-files\t1.0\ninstall ok installed\t1.1\n"), Some("1.1".into()));
     }
 }
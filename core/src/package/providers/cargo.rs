@@ -0,0 +1,127 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, ExecOptions};
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use super::{Operation, PackageProvider};
+use tokio_process::CommandExt;
+
+/// The Cargo `Package` provider for Rust crates.
+///
+/// This provider manages binary crates installed with `cargo install`, and
+/// is never selected automatically. To use it, set it explicitly with
+/// [`Host::set_package()`](../../host/trait.Host.html#tymethod.set_package).
+pub struct Cargo;
+
+impl PackageProvider for Cargo {
+    fn available() -> Result<bool> {
+        Ok(hooks::command("/usr/bin/type", &["cargo"])
+            .status()
+            .chain_err(|| "Could not determine provider availability")?
+            .success())
+    }
+
+    fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        let name = name.to_owned();
+
+        Box::new(hooks::command("cargo", &["install", "--list"])
+            .output_async(host.handle())
+            .chain_err(|| "Could not get installed packages")
+            .and_then(move |output| {
+                if output.status.success() {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    future::ok(parse_install_list(&stdout).contains(&&*name))
+                } else {
+                    future::err(format!("Error running `cargo install --list`: {}",
+                        String::from_utf8_lossy(&output.stderr)).into())
+                }
+            }))
+    }
+
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["cargo", "install", name], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["cargo", "install"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, _: &Local, _: &ExecOptions) -> FutureResult<Child, Error> {
+        // Cargo refreshes the crates.io index itself whenever it
+        // installs a crate.
+        future::ok(Child::detached())
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["cargo", "uninstall", name], opts)
+    }
+
+    // Crates are installed into the user's Cargo home
+    fn requires_root(&self, _: Operation) -> bool {
+        false
+    }
+}
+
+// `cargo install --list` groups the binaries installed by each crate under
+// an unindented "<crate> v<version>:" line, e.g.
+//
+//     ripgrep v0.7.1:
+//         rg
+//
+// We only care about crate names, so skip the indented binary lines.
+fn parse_install_list(output: &str) -> Vec<&str> {
+    output.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_install_list;
+
+    #[test]
+    fn test_parse_install_list_skips_binaries() {
+        let out = "\
+cargo-edit v0.2.0:
+    cargo-add
+    cargo-rm
+ripgrep v0.7.1:
+    rg
+";
+        assert_eq!(parse_install_list(out), vec!["cargo-edit", "ripgrep"]);
+    }
+
+    #[test]
+    fn test_parse_install_list_binary_named_like_crate() {
+        // `rg` is a binary here, not an installed crate
+        let out = "\
+ripgrep v0.7.1:
+    rg
+";
+        assert!(!parse_install_list(out).contains(&"rg"));
+    }
+
+    #[test]
+    fn test_parse_install_list_local_crate() {
+        let out = "\
+mytool v0.1.0 (/home/user/mytool):
+    mytool
+";
+        assert_eq!(parse_install_list(out), vec!["mytool"]);
+    }
+
+    #[test]
+    fn test_parse_install_list_empty() {
+        assert!(parse_install_list("").is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, ExecOptions};
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use regex::{self, Regex};
+use super::PackageProvider;
+use tokio_process::CommandExt;
+
+/// The Flatpak `Package` provider.
+///
+/// Flatpaks usually live alongside the system package manager, so this
+/// provider is never selected automatically. To use it, set it explicitly
+/// with [`Host::set_package()`](../../host/trait.Host.html#tymethod.set_package).
+///
+///## Package names
+///
+/// Flatpak identifies applications by their _application ID_ (e.g.
+/// "org.gimp.GIMP"), rather than the human readable name shown in app
+/// stores (e.g. "GIMP"). When using this provider, the package name must
+/// be an application ID or a full ref (e.g. "org.gimp.GIMP/x86_64/stable").
+pub struct Flatpak;
+
+impl PackageProvider for Flatpak {
+    fn available() -> Result<bool> {
+        Ok(hooks::command("/usr/bin/type", &["flatpak"])
+            .status()
+            .chain_err(|| "Could not determine provider availability")?
+            .success())
+    }
+
+    fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        let name = name.to_owned();
+
+        Box::new(hooks::command("flatpak", &["list"])
+            .output_async(host.handle())
+            .chain_err(|| "Could not get installed packages")
+            .and_then(move |output| {
+                if output.status.success() {
+                    let re = match Regex::new(&format!("(?m)(^|\\s){}(/|\\s|$)", regex::escape(&name))) {
+                        Ok(r) => r,
+                        Err(e) => return future::err(ErrorKind::Regex(e).into()),
+                    };
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    future::ok(re.is_match(&stdout))
+                } else {
+                    future::err(format!("Error running `flatpak list`: {}",
+                        String::from_utf8_lossy(&output.stderr)).into())
+                }
+            }))
+    }
+
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["flatpak", "install", "-y", name], opts)
+    }
+
+    fn install_from(&self, host: &Local, name: &str, source: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["flatpak", "install", "-y", source, name], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["flatpak", "install", "-y"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["flatpak", "update", "-y", "--appstream"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["flatpak", "uninstall", "-y", name], opts)
+    }
+}
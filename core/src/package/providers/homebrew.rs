@@ -4,44 +4,107 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{self, Child};
-use error_chain::ChainedError;
+use command::{hooks, Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use regex::Regex;
-use std::process;
-use super::PackageProvider;
+use regex::{self, Regex};
+use serde_json::{self as json, Value};
+use std::env;
+use std::path::PathBuf;
+use super::{Operation, PackageProvider};
 use tokio_process::CommandExt;
 
+// Where Homebrew installs `brew` on macOS (Intel and Apple silicon) and on
+// Linux. The agent often runs without these in its `PATH`.
+static BREW_PATHS: &'static [&'static str] = &[
+    "/usr/local/bin/brew",
+    "/opt/homebrew/bin/brew",
+    "/home/linuxbrew/.linuxbrew/bin/brew",
+];
+
+/// Homebrew package provider.
+///
+/// This works with Homebrew on macOS and on Linux. Casks are selected by
+/// prefixing the package name with `cask/`, e.g. `cask/firefox`, and are
+/// only supported on macOS. Formulae (or casks) from a third party tap use
+/// the fully qualified name, e.g. `user/repo/name`, and the tap is added
+/// before the package is installed.
 pub struct Homebrew;
 
+// A package name, broken down into its Homebrew components.
+struct Formula<'a> {
+    cask: bool,
+    tap: Option<&'a str>,
+    // The name to pass to `brew install`, including the tap if any
+    name: &'a str,
+    // The name as it appears in `brew list`
+    short_name: &'a str,
+}
+
+impl<'a> Formula<'a> {
+    fn parse(name: &'a str) -> Formula<'a> {
+        let (cask, name) = if name.starts_with("cask/") {
+            (true, &name[5..])
+        } else {
+            (false, name)
+        };
+
+        let (tap, short_name) = match name.rfind('/') {
+            Some(i) => (Some(&name[..i]), &name[i+1..]),
+            None => (None, name),
+        };
+
+        Formula { cask, tap, name, short_name }
+    }
+
+    // Casks are macOS applications, so Homebrew on Linux can't install them
+    fn check(&self) -> Result<()> {
+        if self.cask && !cfg!(target_os="macos") {
+            Err(format!("Cannot manage cask '{}', as casks are only supported on macOS", self.name).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn args(&self, brew: &'a str, action: &'a str) -> Vec<&'a str> {
+        let mut args = vec![brew, action];
+        if self.cask {
+            args.push("--cask");
+        }
+        args.push(self.name);
+        args
+    }
+}
+
 impl PackageProvider for Homebrew {
     fn available() -> Result<bool> {
-        Ok(process::Command::new("/usr/bin/type")
-            .arg("brew")
-            .status()
-            .chain_err(|| "Could not determine provider availability")?
-            .success())
+        Ok(brew_path().is_some())
     }
 
     fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        let name = name.to_owned();
+        let brew = match brew() {
+            Ok(b) => b,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let formula = Formula::parse(name);
+        if let Err(e) = formula.check() {
+            return Box::new(future::err(e));
+        }
+        let short_name = formula.short_name.to_owned();
 
-        Box::new(process::Command::new("brew")
-            .arg("list")
-            .output_async(host.handle())
+        let mut args = vec!["list"];
+        if formula.cask {
+            args.push("--cask");
+        }
+
+        Box::new(hooks::command(&brew, &args).output_async(host.handle())
             .chain_err(|| "Could not get installed packages")
             .and_then(move |output| {
                 if output.status.success() {
-                    let re = match Regex::new(&format!("(?m)(^|\\s+){}\\s+", name)) {
-                        Ok(r) => r,
-                        Err(e) => return future::err(ErrorKind::Regex(e).into()),
-                    };
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    future::ok(re.is_match(&stdout))
+                    future::result(is_listed(&String::from_utf8_lossy(&output.stdout), &short_name))
                 } else {
                     future::err(format!("Error running `brew list installed`: {}",
                         String::from_utf8_lossy(&output.stderr)).into())
@@ -49,19 +112,227 @@ impl PackageProvider for Homebrew {
             }))
     }
 
-    fn install(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let brew = match brew() {
+            Ok(b) => b,
+            Err(e) => return future::err(e),
+        };
+        let formula = Formula::parse(name);
+        if let Err(e) = formula.check() {
+            return future::err(e);
+        }
+        let args = formula.args(&brew, "install");
+
+        match formula.tap {
+            Some(tap) => {
+                let script = format!("{} tap {} && {}", brew, tap, args.join(" "));
+                super::run(host, &["/bin/sh", "-c", &script], opts)
+            },
+            None => super::run(host, &args, opts),
+        }
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let brew = match brew() {
+            Ok(b) => b,
+            Err(e) => return future::err(e),
         };
-        cmd.exec(host, &["brew", "install", name])
+        let formulae: Vec<_> = names.iter().map(|n| Formula::parse(n)).collect();
+        if let Some(Err(e)) = formulae.iter().map(|f| f.check()).find(|r| r.is_err()) {
+            return future::err(e);
+        }
+        let mut taps: Vec<_> = formulae.iter().filter_map(|f| f.tap).collect();
+        taps.sort();
+        taps.dedup();
+
+        // Formulae and casks can't be installed in a single invocation
+        let (casks, formulae): (Vec<_>, Vec<_>) = formulae.into_iter().partition(|f| f.cask);
+
+        let mut script: Vec<_> = taps.iter().map(|t| format!("{} tap {}", brew, t)).collect();
+        if !formulae.is_empty() {
+            let names: Vec<_> = formulae.iter().map(|f| f.name).collect();
+            script.push(format!("{} install {}", brew, names.join(" ")));
+        }
+        if !casks.is_empty() {
+            let names: Vec<_> = casks.iter().map(|f| f.name).collect();
+            script.push(format!("{} install --cask {}", brew, names.join(" ")));
+        }
+
+        super::run(host, &["/bin/sh", "-c", &script.join(" && ")], opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        match brew() {
+            Ok(brew) => super::run(host, &[&brew, "update"], opts),
+            Err(e) => future::err(e),
+        }
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let brew = match brew() {
+            Ok(b) => b,
+            Err(e) => return future::err(e),
+        };
+        let formula = Formula::parse(name);
+        match formula.check() {
+            Ok(_) => super::run(host, &formula.args(&brew, "uninstall"), opts),
+            Err(e) => future::err(e),
+        }
+    }
+
+    fn installed_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        // `brew info` doesn't describe casks, so they don't have a version
+        if Formula::parse(name).cask {
+            return Box::new(self.installed(host, name).map(|i| if i { Some(String::new()) } else { None }));
+        }
+
+        // The last installed version is the one that is linked
+        Box::new(info(host, name).map(|info| info.and_then(|i| {
+            i["installed"].as_array()
+                .and_then(|v| v.last())
+                .and_then(|v| v["version"].as_str())
+                .map(|v| v.to_owned())
+        })))
+    }
+
+    fn candidate_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(info(host, name).map(|info| info.and_then(|i| {
+            i["versions"]["stable"].as_str().map(|v| v.to_owned())
+        })))
     }
 
-    fn uninstall(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
+    fn exists_in_repo(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        let brew = match brew() {
+            Ok(b) => b,
+            Err(e) => return Box::new(future::err(e)),
         };
-        cmd.exec(host, &["brew", "uninstall", name])
+        let formula = Formula::parse(name);
+        if let Err(e) = formula.check() {
+            return Box::new(future::err(e));
+        }
+        let args = formula.args(&brew, "info");
+        super::succeeds(host, args[0], &args[1..])
+    }
+
+    // Homebrew refuses to run as root
+    fn requires_root(&self, _: Operation) -> bool {
+        false
+    }
+}
+
+/// Find the `brew` executable, looking in the `PATH` first and then in the
+/// locations Homebrew installs to on macOS and Linux.
+#[doc(hidden)]
+pub fn brew_path() -> Option<PathBuf> {
+    let in_path = env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths)
+            .map(|p| p.join("brew"))
+            .find(|p| p.is_file()));
+
+    in_path.or_else(|| {
+        let home = env::var_os("HOME").map(|h| PathBuf::from(h).join(".linuxbrew/bin/brew"));
+        BREW_PATHS.iter()
+            .map(PathBuf::from)
+            .chain(home)
+            .find(|p| p.is_file())
+    })
+}
+
+// Whether `name` appears in the output of `brew list`. Formulae and casks
+// are listed separately, so only one kind is searched at a time.
+fn is_listed(list: &str, name: &str) -> Result<bool> {
+    let re = Regex::new(&format!("(?m)(^|\\s+){}\\s+", regex::escape(name)))
+        .map_err(ErrorKind::Regex)?;
+    Ok(re.is_match(list))
+}
+
+fn brew() -> Result<String> {
+    brew_path()
+        .map(|p| p.to_string_lossy().into_owned())
+        .ok_or_else(|| ErrorKind::ProviderUnavailable("Package").into())
+}
+
+// Get the JSON description of a formula from `brew info`, or `None` if there
+// is no such formula.
+fn info(host: &Local, name: &str) -> Box<Future<Item = Option<Value>, Error = Error>> {
+    let brew = match brew() {
+        Ok(b) => b,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let formula = Formula::parse(name);
+    if formula.cask {
+        return Box::new(future::err(format!("Cannot query the version of cask '{}'", formula.name).into()));
+    }
+
+    Box::new(hooks::command(&brew, &["info", "--json=v1", formula.name])
+        .output_async(host.handle())
+        .chain_err(|| ErrorKind::SystemCommand("brew info"))
+        .and_then(|output| {
+            if output.status.success() {
+                json::from_slice::<Vec<Value>>(&output.stdout)
+                    .map(|mut formulae| formulae.pop())
+                    .chain_err(|| ErrorKind::SystemCommandOutput("brew info"))
+            } else if String::from_utf8_lossy(&output.stderr).contains("No available formula") {
+                Ok(None)
+            } else {
+                Err(format!("Error running `brew info`: {}", String::from_utf8_lossy(&output.stderr)).into())
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_listed, Formula};
+
+    #[test]
+    fn test_parse_formula() {
+        let f = Formula::parse("wget");
+        assert!(!f.cask);
+        assert_eq!(f.tap, None);
+        assert_eq!(f.short_name, "wget");
+        assert_eq!(f.args("brew", "install"), vec!["brew", "install", "wget"]);
+    }
+
+    #[test]
+    fn test_parse_cask() {
+        let f = Formula::parse("cask/firefox");
+        assert!(f.cask);
+        assert_eq!(f.tap, None);
+        assert_eq!(f.short_name, "firefox");
+        assert_eq!(f.args("brew", "install"), vec!["brew", "install", "--cask", "firefox"]);
+    }
+
+    #[test]
+    fn test_parse_tap() {
+        let f = Formula::parse("user/repo/tool");
+        assert!(!f.cask);
+        assert_eq!(f.tap, Some("user/repo"));
+        assert_eq!(f.short_name, "tool");
+        assert_eq!(f.args("brew", "install"), vec!["brew", "install", "user/repo/tool"]);
+
+        let f = Formula::parse("cask/user/repo/app");
+        assert!(f.cask);
+        assert_eq!(f.tap, Some("user/repo"));
+        assert_eq!(f.short_name, "app");
+    }
+
+    #[test]
+    fn test_formula_is_listed() {
+        let list = "git\nopenssl@1.1\nwget\n";
+        assert!(is_listed(list, "wget").unwrap());
+        assert!(is_listed(list, "openssl@1.1").unwrap());
+        assert!(!is_listed(list, "get").unwrap());
+        assert!(!is_listed(list, "openssl").unwrap());
+
+        // `brew list` prints columns when writing to a terminal
+        assert!(is_listed("git     wget\n", "wget").unwrap());
+    }
+
+    #[test]
+    fn test_cask_is_listed() {
+        let list = "firefox\ngoogle-chrome\n";
+        assert!(is_listed(list, "firefox").unwrap());
+        assert!(is_listed(list, "google-chrome").unwrap());
+        assert!(!is_listed(list, "chrome").unwrap());
     }
 }
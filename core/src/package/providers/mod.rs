@@ -7,51 +7,358 @@
 //! OS abstractions for `Package`.
 
 mod apt;
+mod cargo;
 mod dnf;
+mod flatpak;
 mod homebrew;
 mod nix;
+mod npm;
 mod pkg;
+mod portage;
+mod rpm;
+mod snap;
 mod yum;
 
-use command::Child;
+use command::{self, hooks, Child, ExecOptions};
+use error_chain::ChainedError;
 use errors::*;
-use futures::Future;
+use futures::{future, Future};
 use futures::future::FutureResult;
+use host::Host;
 use host::local::Local;
+use registry::{Registry, Rejections};
 pub use self::apt::Apt;
+pub use self::cargo::Cargo;
 pub use self::dnf::Dnf;
-pub use self::homebrew::Homebrew;
+pub use self::flatpak::Flatpak;
+pub use self::homebrew::{brew_path, Homebrew};
 pub use self::nix::Nix;
+pub use self::npm::Npm;
 pub use self::pkg::Pkg;
+pub use self::portage::Portage;
+pub use self::snap::Snap;
 pub use self::yum::Yum;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use tokio_process::CommandExt;
+
+/// An operation on a package, used to ask a provider whether the operation
+/// requires root privileges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    Install,
+    Uninstall,
+    /// Refresh the package index
+    Refresh,
+    /// Any operation that only queries the host, e.g. `installed()`
+    Query,
+}
+
+/// Specific implementation of `Package`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Provider {
+    Apt,
+    Cargo,
+    Dnf,
+    Flatpak,
+    Homebrew,
+    Nix,
+    Npm,
+    Pkg,
+    Portage,
+    Snap,
+    Yum,
+}
 
 pub trait PackageProvider {
     fn available() -> Result<bool> where Self: Sized;
     fn installed(&self, &Local, &str) -> Box<Future<Item = bool, Error = Error>>;
-    fn install(&self, &Local, &str) -> FutureResult<Child, Error>;
-    fn uninstall(&self, &Local, &str) -> FutureResult<Child, Error>;
+    fn install(&self, &Local, &str, &ExecOptions) -> FutureResult<Child, Error>;
+    fn install_many(&self, &Local, &[String], &ExecOptions) -> FutureResult<Child, Error>;
+    fn refresh(&self, &Local, &ExecOptions) -> FutureResult<Child, Error>;
+    fn uninstall(&self, &Local, &str, &ExecOptions) -> FutureResult<Child, Error>;
+
+    /// Install a package from a specific source, such as a repo or channel.
+    ///
+    /// Providers that don't support sources ignore it, which is what the
+    /// default implementation does.
+    fn install_from(&self, host: &Local, name: &str, _: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        self.install(host, name, opts)
+    }
+
+    /// Install a package from a file on the host, e.g. a `.deb` or `.rpm`.
+    fn install_file(&self, _: &Local, _: &str, _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err("This package provider cannot install packages from a file".into())
+    }
+
+    /// The installed version of a package, or `None` if it isn't installed.
+    ///
+    /// Providers that can't query versions report an empty version for an
+    /// installed package, which is what the default implementation does.
+    fn installed_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(self.installed(host, name).map(|i| if i { Some(String::new()) } else { None }))
+    }
+
+    /// The version of a package that would be installed from the package
+    /// index, or `None` if the package can't be found.
+    fn candidate_version(&self, _: &Local, _: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(future::err("This package provider cannot query package versions".into()))
+    }
+
+    /// Whether a package with this name can be found in the configured
+    /// repositories, whether or not it is installed.
+    fn exists_in_repo(&self, _: &Local, _: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(future::err("This package provider cannot search its repositories".into()))
+    }
+
+    /// Whether an operation requires root privileges. By default, only
+    /// queries don't.
+    fn requires_root(&self, op: Operation) -> bool {
+        op != Operation::Query
+    }
+}
+
+/// A function that creates a custom `PackageProvider`.
+///
+/// The function should return `None` if the provider is not available on the
+/// current host.
+pub type PackageProviderFactory = Fn() -> Result<Option<Box<PackageProvider>>> + Send + Sync;
+
+lazy_static! {
+    static ref REGISTRY: Registry<PackageProviderFactory> = Registry::new();
+    // Whether each built-in provider is available. Probing runs a system
+    // command, so each provider is only probed once.
+    static ref AVAILABLE: Mutex<HashMap<Provider, bool>> = Mutex::new(HashMap::new());
+}
+
+static STRICT: AtomicBool = ATOMIC_BOOL_INIT;
+
+// The built-in providers that `factory()` chooses from, in order of
+// preference. The others have to be chosen explicitly.
+static BUILTIN: [(&'static str, Provider); 7] = [
+    ("apt", Provider::Apt),
+    ("dnf", Provider::Dnf),
+    ("homebrew", Provider::Homebrew),
+    ("nix", Provider::Nix),
+    ("pkg", Provider::Pkg),
+    ("portage", Provider::Portage),
+    ("yum", Provider::Yum),
+];
+
+/// Register a custom `PackageProvider`.
+///
+/// With a `priority` greater than
+/// [`BUILTIN_PRIORITY`](../constant.BUILTIN_PRIORITY.html), the provider
+/// is preferred to the host's own package manager. Otherwise it is only
+/// used on hosts where none of the built-in providers are available.
+pub fn register_package_provider<F>(priority: i32, factory: F)
+    where F: Fn() -> Result<Option<Box<PackageProvider>>> + Send + Sync + 'static
+{
+    REGISTRY.register(priority, Arc::new(factory));
+}
+
+/// Refuse to guess when more than one built-in `PackageProvider` is
+/// available, e.g. on a host with both Dnf and Yum.
+///
+/// By default, `factory()` picks the first available built-in provider in a
+/// fixed order. In strict mode, the host's default provider instead fails
+/// every operation with `ErrorKind::AmbiguousProvider`, listing every
+/// available provider. To choose one, set the host's provider with
+/// `Host::set_package()`, choose a provider for each operation with
+/// [`Package::using()`](struct.Package.html#method.using), or register one
+/// with [`register_package_provider()`](fn.register_package_provider.html)
+/// and a priority above [`BUILTIN_PRIORITY`](../constant.BUILTIN_PRIORITY.html),
+/// so the built-in providers aren't considered.
+///
+/// Providers are chosen by the process that runs them. For a remote host,
+/// that is the agent, so strict mode must be enabled there.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::SeqCst);
 }
 
 #[doc(hidden)]
 pub fn factory() -> Result<Box<PackageProvider>> {
-    if Apt::available()? {
-        Ok(Box::new(Apt))
+    let mut rejections = Rejections::new();
+    let provider = REGISTRY.resolve(|| builtin(&mut rejections), |f| f())?;
+    provider.ok_or_else(|| rejections.into_error("Package"))
+}
+
+/// Create the given provider, or return `ErrorKind::ProviderUnavailable` if
+/// it isn't available on this host.
+#[doc(hidden)]
+pub fn provider(provider: Provider) -> Result<Box<PackageProvider>> {
+    if is_available(provider)? {
+        Ok(create(provider))
+    } else {
+        Err(ErrorKind::ProviderUnavailable("Package").into())
+    }
+}
+
+fn create(provider: Provider) -> Box<PackageProvider> {
+    match provider {
+        Provider::Apt => Box::new(Apt),
+        Provider::Cargo => Box::new(Cargo),
+        Provider::Dnf => Box::new(Dnf),
+        Provider::Flatpak => Box::new(Flatpak),
+        Provider::Homebrew => Box::new(Homebrew),
+        Provider::Nix => Box::new(Nix),
+        Provider::Npm => Box::new(Npm),
+        Provider::Pkg => Box::new(Pkg),
+        Provider::Portage => Box::new(Portage),
+        Provider::Snap => Box::new(Snap),
+        Provider::Yum => Box::new(Yum),
+    }
+}
+
+// Whether a built-in provider is available. Failed probes aren't cached, so
+// they are retried next time.
+fn is_available(provider: Provider) -> Result<bool> {
+    let probe: fn() -> Result<bool> = match provider {
+        Provider::Apt => Apt::available,
+        Provider::Cargo => Cargo::available,
+        Provider::Dnf => Dnf::available,
+        Provider::Flatpak => Flatpak::available,
+        Provider::Homebrew => Homebrew::available,
+        Provider::Nix => Nix::available,
+        Provider::Npm => Npm::available,
+        Provider::Pkg => Pkg::available,
+        Provider::Portage => Portage::available,
+        Provider::Snap => Snap::available,
+        Provider::Yum => Yum::available,
+    };
+
+    let cached = AVAILABLE.lock().unwrap_or_else(|e| e.into_inner()).get(&provider).cloned();
+    match cached {
+        Some(a) => Ok(a),
+        None => {
+            let a = probe()?;
+            AVAILABLE.lock().unwrap_or_else(|e| e.into_inner()).insert(provider, a);
+            Ok(a)
+        },
+    }
+}
+
+// Run a command with the command provider, which streams its output back to
+// the caller. This is how providers install, uninstall and refresh packages.
+fn run(host: &Local, args: &[&str], opts: &ExecOptions) -> FutureResult<Child, Error> {
+    match command::factory() {
+        Ok(cmd) => cmd.exec_with(host, args, opts),
+        Err(e) => future::err(format!("{}", e.display_chain()).into()),
     }
-    else if Dnf::available()? {
-        Ok(Box::new(Dnf))
+}
+
+// Run a command that only reports its result through its exit status, e.g.
+// `apt-cache show <name>`.
+fn succeeds(host: &Local, program: &str, args: &[&str]) -> Box<Future<Item = bool, Error = Error>> {
+    let program_owned = program.to_owned();
+    Box::new(hooks::command(program, args)
+        .output_async(host.handle())
+        .map_err(move |e| Error::with_chain(e, format!("Could not run `{}`", program_owned)))
+        .map(|output| output.status.success()))
+}
+
+fn builtin(rejections: &mut Rejections) -> Result<Option<Box<PackageProvider>>> {
+    let mut available = available_builtins(rejections, STRICT.load(Ordering::SeqCst));
+    match available.len() {
+        0 => Ok(None),
+        1 => Ok(Some(create(available.remove(0).1))),
+        _ => Ok(Some(Box::new(Ambiguous(available.into_iter().map(|(name, _)| name).collect())))),
     }
-    else if Homebrew::available()? {
-        Ok(Box::new(Homebrew))
+}
+
+// The available built-in providers. Unless we're strict, the first
+// available provider wins, so the rest aren't probed.
+fn available_builtins(rejections: &mut Rejections, strict: bool) -> Vec<(&'static str, Provider)> {
+    let mut available = Vec::new();
+    for &(name, provider) in &BUILTIN {
+        if rejections.check(name, is_available(provider)) {
+            available.push((name, provider));
+            if !strict {
+                break;
+            }
+        }
     }
-    else if Nix::available()? {
-        Ok(Box::new(Nix))
+    available
+}
+
+// Placeholder provider for a host with several built-in providers in strict
+// mode. It fails every operation until the host's provider is set, so that
+// operations that choose their own provider still work.
+struct Ambiguous(Vec<&'static str>);
+
+impl Ambiguous {
+    fn error(&self) -> Error {
+        ErrorKind::AmbiguousProvider(self.0.clone()).into()
     }
-    else if Pkg::available()? {
-        Ok(Box::new(Pkg))
+}
+
+impl PackageProvider for Ambiguous {
+    fn available() -> Result<bool> {
+        Ok(false)
     }
-    else if Yum::available()? {
-        Ok(Box::new(Yum))
-    } else {
-        Err(ErrorKind::ProviderUnavailable("Package").into())
+
+    fn installed(&self, _: &Local, _: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(future::err(self.error()))
+    }
+
+    fn install(&self, _: &Local, _: &str, _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err(self.error())
+    }
+
+    fn install_many(&self, _: &Local, _: &[String], _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err(self.error())
+    }
+
+    fn refresh(&self, _: &Local, _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err(self.error())
+    }
+
+    fn uninstall(&self, _: &Local, _: &str, _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err(self.error())
+    }
+
+    fn install_file(&self, _: &Local, _: &str, _: &ExecOptions) -> FutureResult<Child, Error> {
+        future::err(self.error())
+    }
+
+    fn candidate_version(&self, _: &Local, _: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(future::err(self.error()))
+    }
+
+    fn exists_in_repo(&self, _: &Local, _: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(future::err(self.error()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use registry::Rejections;
+    use super::*;
+
+    #[test]
+    fn test_available_builtins() {
+        // Pretend this is a Fedora host, without probing
+        {
+            let mut cache = AVAILABLE.lock().unwrap();
+            for &(_, p) in &BUILTIN {
+                cache.insert(p, p == Provider::Dnf || p == Provider::Yum);
+            }
+        }
+
+        let first = available_builtins(&mut Rejections::new(), false);
+        assert_eq!(first, vec![("dnf", Provider::Dnf)]);
+
+        let all = available_builtins(&mut Rejections::new(), true);
+        assert_eq!(all, vec![("dnf", Provider::Dnf), ("yum", Provider::Yum)]);
+    }
+
+    #[test]
+    fn test_ambiguous_error() {
+        let ambiguous = Ambiguous(vec!["dnf", "yum"]);
+        match *ambiguous.error().kind() {
+            ErrorKind::AmbiguousProvider(ref p) => assert_eq!(p, &["dnf", "yum"]),
+            ref e => panic!("Unexpected error: {}", e),
+        }
     }
 }
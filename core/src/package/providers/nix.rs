@@ -4,23 +4,20 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{self, Child};
-use error_chain::ChainedError;
+use command::{hooks, Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use std::process;
-use super::PackageProvider;
+use super::{Operation, PackageProvider};
 use tokio_process::CommandExt;
 
 pub struct Nix;
 
 impl PackageProvider for Nix {
     fn available() -> Result<bool> {
-        Ok(process::Command::new("/usr/bin/type")
-            .arg("nix-env")
+        Ok(hooks::command("/usr/bin/type", &["nix-env"])
             .status()
             .chain_err(|| "Could not determine provider availability")?
             .success())
@@ -29,8 +26,7 @@ impl PackageProvider for Nix {
     fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("nix-env")
-            .args(&["--install", "--dry-run", &name])
+        Box::new(hooks::command("nix-env", &["--install", "--dry-run", &name])
             .output_async(host.handle())
             .chain_err(|| "Could not check if package is installed")
             .and_then(move |output| {
@@ -44,19 +40,30 @@ impl PackageProvider for Nix {
             }))
     }
 
-    fn install(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["nix-env", "--install", name])
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["nix-env", "--install", name], opts)
     }
 
-    fn uninstall(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["nix-env", "--uninstall", name])
+    fn install_from(&self, host: &Local, name: &str, source: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["nix-env", "--install", "--attr", &format!("{}.{}", source, name)], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["nix-env", "--install"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["nix-channel", "--update"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["nix-env", "--uninstall", name], opts)
+    }
+
+    // Packages are installed into the user's profile
+    fn requires_root(&self, _: Operation) -> bool {
+        false
     }
 }
@@ -4,14 +4,12 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{self, Child};
-use error_chain::ChainedError;
+use command::{hooks, Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use std::process;
 use super::PackageProvider;
 use tokio_process::CommandExt;
 
@@ -19,8 +17,7 @@ pub struct Pkg;
 
 impl PackageProvider for Pkg {
     fn available() -> Result<bool> {
-        Ok(process::Command::new("/usr/bin/type")
-            .arg("pkg")
+        Ok(hooks::command("/usr/bin/type", &["pkg"])
             .status()
             .chain_err(|| "Could not determine provider availability")?
             .success())
@@ -29,8 +26,7 @@ impl PackageProvider for Pkg {
     fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("pkg")
-            .args(&["query", "\"%n\"", &name])
+        Box::new(hooks::command("pkg", &["query", "\"%n\"", &name])
             .output_async(host.handle())
             .chain_err(|| "Could not get installed packages")
             .and_then(move |output| {
@@ -38,19 +34,25 @@ impl PackageProvider for Pkg {
             }))
     }
 
-    fn install(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["pkg", "install", "-y", name])
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["pkg", "install", "-y", name], opts)
     }
 
-    fn uninstall(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["pkg", "delete", "-y", name])
+    fn install_file(&self, host: &Local, path: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["pkg", "add", path], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["pkg", "install", "-y"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["pkg", "update"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["pkg", "delete", "-y", name], opts)
     }
 }
@@ -0,0 +1,58 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, ExecOptions};
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use super::PackageProvider;
+use tokio_process::CommandExt;
+
+/// The Portage `Package` provider for Gentoo.
+///
+/// Note that Portage builds packages from source, so installs can take a
+/// long time. The `Child` returned by `install()` streams the output of
+/// `emerge` as it runs, allowing you to follow the build progress.
+pub struct Portage;
+
+impl PackageProvider for Portage {
+    fn available() -> Result<bool> {
+        Ok(hooks::command("/usr/bin/type", &["emerge"])
+            .status()
+            .chain_err(|| "Could not determine provider availability")?
+            .success())
+    }
+
+    fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(hooks::command("qlist", &["-I", "-e", name])
+            .output_async(host.handle())
+            .chain_err(|| "Could not get installed packages")
+            .and_then(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                future::ok(output.status.success() && !stdout.trim().is_empty())
+            }))
+    }
+
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["emerge", "--ask=n", name], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["emerge", "--ask=n"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["emerge", "--sync"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["emerge", "--ask=n", "--unmerge", name], opts)
+    }
+}
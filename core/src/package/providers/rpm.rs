@@ -0,0 +1,74 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Helpers shared by the RPM based providers, Yum and Dnf.
+
+use command::hooks;
+use errors::*;
+use futures::{future, Future};
+use host::Host;
+use host::local::Local;
+use regex::{self, Regex};
+use tokio_process::CommandExt;
+
+/// The installed version of a package, and the newest version available
+/// from the repositories.
+pub struct Versions {
+    pub installed: Option<String>,
+    pub available: Option<String>,
+}
+
+/// Get a package's versions from `<bin> list <name>`, which prints an
+/// "Installed Packages" section, followed by an "Available Packages" section
+/// that only lists versions newer than the installed one.
+pub fn versions(bin: &'static str, host: &Local, name: &str) -> Box<Future<Item = Versions, Error = Error>> {
+    let arch = match host.try_telemetry() {
+        Ok(t) => &t.os.arch,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let re = match Regex::new(&format!(r"^{}\.({}|noarch)\s+(\S+)",
+        regex::escape(name), regex::escape(arch)))
+    {
+        Ok(r) => r,
+        Err(e) => return Box::new(future::err(ErrorKind::Regex(e).into())),
+    };
+
+    Box::new(hooks::command(bin, &["list", name])
+        .output_async(host.handle())
+        .chain_err(move || ErrorKind::SystemCommand(bin))
+        .and_then(move |output| {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            if !output.status.success() {
+                // The package doesn't exist in any repository
+                return if stderr.contains("No matching Packages") {
+                    future::ok(Versions { installed: None, available: None })
+                } else {
+                    future::err(format!("Error running `{} list`: {}", bin, stderr).into())
+                };
+            }
+
+            let mut versions = Versions { installed: None, available: None };
+            let mut available = false;
+            for line in stdout.lines() {
+                if line.starts_with("Installed Packages") {
+                    available = false;
+                } else if line.starts_with("Available Packages") {
+                    available = true;
+                } else if let Some(cap) = re.captures(line) {
+                    let version = Some(cap[2].to_owned());
+                    if available {
+                        versions.available = version;
+                    } else {
+                        versions.installed = version;
+                    }
+                }
+            }
+
+            future::ok(versions)
+        }))
+}
@@ -0,0 +1,61 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, ExecOptions};
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use super::PackageProvider;
+use tokio_process::CommandExt;
+
+/// The Snap `Package` provider.
+///
+/// Snaps usually live alongside the system package manager, so this
+/// provider is never selected automatically. To use it, set it explicitly
+/// with [`Host::set_package()`](../../host/trait.Host.html#tymethod.set_package).
+pub struct Snap;
+
+impl PackageProvider for Snap {
+    fn available() -> Result<bool> {
+        Ok(hooks::command("/usr/bin/type", &["snap"])
+            .status()
+            .chain_err(|| "Could not determine provider availability")?
+            .success())
+    }
+
+    fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(hooks::command("snap", &["list", name])
+            .output_async(host.handle())
+            .chain_err(|| "Could not get installed packages")
+            .map(|output| output.status.success()))
+    }
+
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["snap", "install", name], opts)
+    }
+
+    fn install_from(&self, host: &Local, name: &str, source: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["snap", "install", &format!("--channel={}", source), name], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["snap", "install"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, _: &Local, _: &ExecOptions) -> FutureResult<Child, Error> {
+        // snapd refreshes its store metadata itself, so there is no
+        // local index to refresh.
+        future::ok(Child::detached())
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["snap", "remove", name], opts)
+    }
+}
@@ -4,16 +4,14 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{self, Child};
-use error_chain::ChainedError;
+use command::{hooks, Child, ExecOptions};
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use regex::Regex;
-use std::process;
-use super::PackageProvider;
+use regex::{self, Regex};
+use super::{rpm, PackageProvider};
 use tokio_process::CommandExt;
 
 /// The Yum `Package` provider.
@@ -21,8 +19,7 @@ pub struct Yum;
 
 impl PackageProvider for Yum {
     fn available() -> Result<bool> {
-        Ok(process::Command::new("/usr/bin/type")
-            .arg("yum")
+        Ok(hooks::command("/usr/bin/type", &["yum"])
             .status()
             .chain_err(|| "Could not determine provider availability")?
             .success())
@@ -30,15 +27,17 @@ impl PackageProvider for Yum {
 
     fn installed(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
-        let arch = host.telemetry().os.arch.clone();
+        let arch = match host.try_telemetry() {
+            Ok(t) => t.os.arch.clone(),
+            Err(e) => return Box::new(future::err(e)),
+        };
 
-        Box::new(process::Command::new("yum")
-            .args(&["list", "installed"])
+        Box::new(hooks::command("yum", &["list", "installed"])
             .output_async(host.handle())
             .chain_err(|| "Could not get installed packages")
             .and_then(move |output| {
                 if output.status.success() {
-                    let re = match Regex::new(&format!("(?m)^{}\\.({}|noarch)\\s+", name, arch)) {
+                    let re = match Regex::new(&format!("(?m)^{}\\.({}|noarch)\\s+", regex::escape(&name), regex::escape(&arch))) {
                         Ok(r) => r,
                         Err(e) => return future::err(ErrorKind::Regex(e).into()),
                     };
@@ -51,19 +50,42 @@ impl PackageProvider for Yum {
             }))
     }
 
-    fn install(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["yum", "-y", "install", name])
+    fn install(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["yum", "-y", "install", name], opts)
     }
 
-    fn uninstall(&self, host: &Local, name: &str) -> FutureResult<Child, Error> {
-        let cmd = match command::factory() {
-            Ok(c) => c,
-            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
-        };
-        cmd.exec(host, &["yum", "-y", "remove", name])
+    fn install_from(&self, host: &Local, name: &str, source: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["yum", "-y", &format!("--enablerepo={}", source), "install", name], opts)
+    }
+
+    fn install_file(&self, host: &Local, path: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["yum", "-y", "localinstall", path], opts)
+    }
+
+    fn install_many(&self, host: &Local, names: &[String], opts: &ExecOptions) -> FutureResult<Child, Error> {
+        let mut args = vec!["yum", "-y", "install"];
+        args.extend(names.iter().map(|n| n.as_str()));
+        super::run(host, &args, opts)
+    }
+
+    fn refresh(&self, host: &Local, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["yum", "makecache"], opts)
+    }
+
+    fn uninstall(&self, host: &Local, name: &str, opts: &ExecOptions) -> FutureResult<Child, Error> {
+        super::run(host, &["yum", "-y", "remove", name], opts)
+    }
+
+    fn installed_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(rpm::versions("yum", host, name).map(|v| v.installed))
+    }
+
+    // Only newer versions are listed as available
+    fn candidate_version(&self, host: &Local, name: &str) -> Box<Future<Item = Option<String>, Error = Error>> {
+        Box::new(rpm::versions("yum", host, name).map(|v| v.available.or(v.installed)))
+    }
+
+    fn exists_in_repo(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        super::succeeds(host, "yum", &["info", name])
     }
 }
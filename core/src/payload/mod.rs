@@ -0,0 +1,269 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Endpoint for running payloads.
+//!
+//! A payload is an external executable, such as a compiled Rust binary,
+//! that manages a host using this API. This lets you package reusable
+//! configuration as standalone programs and run them against any `Host`.
+//!
+//! When a payload is run, the API listens on a private Unix socket and
+//! passes its path to the payload in the `INTECTURE_SOCKET` environment
+//! variable. The payload connects to it with [`connect()`](fn.connect.html),
+//! which returns a `Plain` host. Every request the payload sends through
+//! that host is forwarded to the host that is running the payload.
+
+use command::{hooks, Child};
+use errors::*;
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use host::Host;
+use host::remote::{GzipSwitch, JsonLineProto, Plain};
+use message::{error_to_msg, ok_to_msg, InMessage};
+use request::{Envelope, Hello};
+use serde_json::{self as json, Value};
+use std::{env, fs, io, process};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use tokio_core::reactor::Handle;
+use tokio_process::CommandExt;
+use tokio_proto::BindServer;
+use tokio_proto::streaming::Message;
+use tokio_service::Service;
+use tokio_uds::UnixListener;
+
+/// The environment variable that holds the path of the socket a payload
+/// should connect to.
+pub const SOCKET_ENV: &'static str = "INTECTURE_SOCKET";
+
+/// Represents an executable to be run against a host.
+///
+///## Example
+///
+/// Run a payload against the local machine.
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::prelude::*;
+///use intecture_api::payload::Payload;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = Local::new(&handle).wait().unwrap();
+///
+///let payload = Payload::new(&host, "/path/to/nginx_payload");
+///let result = payload.run().and_then(|child| child.result().unwrap());
+///
+///core.run(result).unwrap();
+///# }
+///```
+///
+/// Inside the payload, connect back to the host and use it like any other.
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::prelude::*;
+///use intecture_api::payload;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = core.run(payload::connect(&handle)).unwrap();
+///let nginx = Package::new(&host, "nginx").unwrap();
+///core.run(nginx.install()).unwrap();
+///# }
+///```
+pub struct Payload<H> {
+    host: H,
+    path: PathBuf,
+    args: Vec<String>,
+}
+
+// Serves the requests a payload sends over its socket.
+struct PayloadService<H> {
+    host: H,
+    gzip: GzipSwitch,
+}
+
+impl<H> Payload<H>
+    where H: Host + Service<Request = InMessage, Response = InMessage, Error = Error> + 'static,
+          H::Future: 'static
+{
+    /// Create a new `Payload` for the executable at `path`.
+    pub fn new<P: Into<PathBuf>>(host: &H, path: P) -> Payload<H> {
+        Payload {
+            host: host.clone(),
+            path: path.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Add an argument to pass to the executable.
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Run the payload.
+    ///
+    /// The returned `Child` holds the payload's output and exit status. See
+    /// [`Command` docs](../command/struct.Command.html) for detailed usage.
+    /// The payload's socket is closed once its `ExitStatus` has been
+    /// resolved.
+    pub fn run(&self) -> Box<Future<Item = Child, Error = Error>> {
+        Box::new(future::result(self.spawn()))
+    }
+
+    fn spawn(&self) -> Result<Child> {
+        // Any request sent through the socket is run on the host, so only
+        // this user may connect to it. The socket is created inside a
+        // directory that nobody else can enter, which also keeps other
+        // users from swapping it out before the payload connects.
+        let dir = socket_dir();
+        fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .chain_err(|| format!("Could not create payload socket directory {}", dir.display()))?;
+
+        let result = self.spawn_in(&dir);
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        result
+    }
+
+    fn spawn_in(&self, dir: &Path) -> Result<Child> {
+        let path = self.path.to_str()
+            .ok_or_else(|| format!("Payload path {} is not valid UTF-8", self.path.display()))?;
+        let args: Vec<&str> = self.args.iter().map(|a| a.as_str()).collect();
+        let handle = self.host.handle().clone();
+        let socket = dir.join("payload.sock");
+
+        let listener = UnixListener::bind(&socket, &handle)
+            .chain_err(|| format!("Could not bind payload socket {}", socket.display()))?;
+
+        // Serve each connection the payload makes until it exits
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let host = self.host.clone();
+        let server_handle = handle.clone();
+        let server = listener.incoming()
+            .for_each(move |(stream, _)| {
+                let gzip = GzipSwitch::default();
+                JsonLineProto {
+                    gzip: gzip.clone(),
+                    ..JsonLineProto::default()
+                }.bind_server(&server_handle, stream, PayloadService {
+                    host: host.clone(),
+                    gzip: gzip,
+                });
+                Ok(())
+            })
+            .map_err(|e| error!("Payload socket failed: {}", e))
+            .select(stop_rx.map_err(|_| ()))
+            .map(|_| ())
+            .map_err(|_| ());
+        handle.spawn(server);
+
+        let child = hooks::command(path, &args)
+            .env(SOCKET_ENV, &socket)
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn_async(&handle)
+            .chain_err(|| ErrorKind::Spawn { cmd: self.path.display().to_string() })?;
+
+        let dir = dir.to_owned();
+        Ok(Child::from(child).on_exit(move || {
+            let _ = stop_tx.send(());
+            let _ = fs::remove_dir_all(&dir);
+        }))
+    }
+}
+
+/// Connect to the host that is running this payload.
+///
+/// This reads the socket path from the `INTECTURE_SOCKET` environment
+/// variable, which is set when the payload is run with
+/// [`Payload::run()`](struct.Payload.html#method.run).
+pub fn connect(handle: &Handle) -> Box<Future<Item = Plain, Error = Error>> {
+    match env::var(SOCKET_ENV) {
+        Ok(path) => Plain::connect_unix(path, handle),
+        Err(e) => Box::new(future::err(Error::with_chain(e, format!("Could not read {}. Is this running as a payload?", SOCKET_ENV)))),
+    }
+}
+
+impl<H> Service for PayloadService<H>
+    where H: Service<Request = InMessage, Response = InMessage, Error = Error>,
+          H::Future: 'static
+{
+    type Request = InMessage;
+    type Response = InMessage;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, mut req: Self::Request) -> Self::Future {
+        let body = req.take_body();
+        let envelope: Envelope = match json::from_value(req.into_inner())
+            .chain_err(|| "Malformed Request")
+        {
+            Ok(e) => e,
+            Err(e) => return Box::new(future::ok(error_to_msg(e))),
+        };
+
+        match envelope {
+            Envelope::Request { cid, request, .. } => {
+                debug!("Forwarding payload request {}", cid);
+
+                Box::new(self.host.call(match body {
+                        Some(b) => Message::WithBody(request, b),
+                        None => Message::WithoutBody(request),
+                    })
+                    .then(|result| Ok(match result {
+                        Ok(msg) => ok_to_msg(msg),
+                        Err(e) => error_to_msg(e),
+                    })))
+            },
+            Envelope::Hello(client) => {
+                let hello = Hello {
+                    version: ::VERSION.into(),
+                    protocol: ::PROTOCOL_VERSION,
+                    gzip: self.gzip.negotiate(true, client.gzip),
+                };
+                Box::new(future::ok(match json::to_value(hello) {
+                    Ok(v) => ok_to_msg(Message::WithoutBody(v)),
+                    Err(e) => error_to_msg(Error::with_chain(e, "Could not serialize handshake")),
+                }))
+            },
+            // Requests are forwarded as soon as they arrive, so there is
+            // nothing to cancel or flush.
+            Envelope::Cancel { .. } | Envelope::Close => {
+                Box::new(future::ok(ok_to_msg(Message::WithoutBody(Value::Null))))
+            },
+        }
+    }
+}
+
+// Generate a path for the socket's directory that is unique to this
+// process and payload.
+fn socket_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    env::temp_dir().join(format!("intecture-payload-{}-{}",
+        process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)))
+}
@@ -0,0 +1,119 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Storage for providers registered at runtime.
+//!
+//! Each endpoint keeps a `Registry` of provider factories alongside its
+//! built-in providers. When a host picks a default provider, factories with
+//! a priority above `BUILTIN_PRIORITY` are tried first, then the built-in
+//! providers, then the remaining factories. Factories with equal priority
+//! are tried in the order they were registered, and the first one to return
+//! a provider wins.
+
+use errors::*;
+use std::sync::{Arc, RwLock};
+
+/// The priority given to the built-in providers.
+///
+/// Registered providers with a higher priority are tried before the built-in
+/// providers. Those with an equal or lower priority are only tried if none of
+/// the built-in providers are available. Providers with equal priority are
+/// tried in the order they were registered.
+pub const BUILTIN_PRIORITY: i32 = 0;
+
+pub struct Registry<F: ?Sized> {
+    entries: RwLock<Vec<(i32, Arc<F>)>>,
+}
+
+/// Records why each built-in provider was rejected, so that the error
+/// returned when no provider is available can explain why.
+pub struct Rejections {
+    reasons: Vec<String>,
+}
+
+impl<F: ?Sized> Registry<F> {
+    pub fn new() -> Registry<F> {
+        Registry {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, priority: i32, factory: Arc<F>) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        // Keep entries sorted by descending priority. Entries with the same
+        // priority are tried in the order they were registered.
+        let pos = entries.iter()
+            .position(|&(p, _)| p < priority)
+            .unwrap_or(entries.len());
+        entries.insert(pos, (priority, factory));
+    }
+
+    /// Return the first available provider, trying the registered providers
+    /// and the built-in providers in order of priority.
+    pub fn resolve<T, B, C>(&self, builtin: B, call: C) -> Result<Option<T>>
+        where B: FnOnce() -> Result<Option<T>>,
+              C: Fn(&F) -> Result<Option<T>>
+    {
+        // Take a copy so that the lock isn't held while calling factories
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let (before, after): (Vec<_>, Vec<_>) = entries.into_iter()
+            .partition(|&(p, _)| p > BUILTIN_PRIORITY);
+
+        for (_, factory) in before {
+            if let Some(provider) = call(&factory)? {
+                return Ok(Some(provider));
+            }
+        }
+
+        if let Some(provider) = builtin()? {
+            return Ok(Some(provider));
+        }
+
+        for (_, factory) in after {
+            if let Some(provider) = call(&factory)? {
+                return Ok(Some(provider));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Rejections {
+    pub fn new() -> Rejections {
+        Rejections {
+            reasons: Vec::new(),
+        }
+    }
+
+    /// Return whether the provider `name` is available, given the result
+    /// of its `available()` check, and record the reason if it isn't.
+    pub fn check(&mut self, name: &str, available: Result<bool>) -> bool {
+        match available {
+            Ok(true) => true,
+            Ok(false) => {
+                self.reasons.push(format!("{}: not available", name));
+                false
+            },
+            Err(e) => {
+                let cause: Vec<_> = e.iter().map(|e| e.to_string()).collect();
+                self.reasons.push(format!("{}: {}", name, cause.join(": ")));
+                false
+            },
+        }
+    }
+
+    /// Create an `ErrorKind::ProviderUnavailable` error for `endpoint`,
+    /// caused by the recorded reasons.
+    pub fn into_error(self, endpoint: &'static str) -> Error {
+        let reasons: Error = if self.reasons.is_empty() {
+            "No providers were tried".into()
+        } else {
+            self.reasons.join(", ").into()
+        };
+        Error::with_chain(reasons, ErrorKind::ProviderUnavailable(endpoint))
+    }
+}
@@ -4,19 +4,77 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+use bytes::Bytes;
 use errors::*;
 use futures::{future, Future};
 use host::Host;
 use host::local::Local;
-use message::{FromMessage, IntoMessage, InMessage};
+use message::{drain_body, FromMessage, IntoMessage, InMessage};
 use serde_json as json;
+use std::{io, result};
 use tokio_core::reactor::Handle;
-use tokio_proto::streaming::Message;
+use tokio_proto::streaming::{Body, Message};
+
+/// Transport header that wraps every request sent to a remote host.
+///
+/// Each request is tagged with an id that is unique to its connection, which
+/// allows the client to refer back to a request that is still in flight. It
+/// also carries a correlation id (`cid`), which is logged by both the client
+/// and the agent so that a request can be traced across their logs.
+///
+/// `Close` tells the agent that the client is about to disconnect. As
+/// responses are pipelined, it is only answered once every earlier request
+/// has been answered.
+///
+/// `Hello` is sent by the client when it connects, and the agent answers with
+/// its own `Hello`, so that each side knows the other's version.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub enum Envelope {
+    Request { id: u64, cid: String, request: json::Value },
+    Cancel { id: u64 },
+    Close,
+    Hello(Hello),
+}
+
+/// Version information exchanged when a client connects to an agent.
+#[doc(hidden)]
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    /// Version of the client or agent, e.g. "0.4.0"
+    pub version: String,
+    /// See `PROTOCOL_VERSION`
+    pub protocol: u32,
+    /// Whether the sender can gzip message bodies. Bodies are only
+    /// compressed once both sides have offered it.
+    #[serde(default)]
+    pub gzip: bool,
+}
 
 pub trait Executable {
     type Response: FromMessage + IntoMessage;
     type Future: Future<Item = Self::Response, Error = Error>;
 
+    /// Whether this request could change the host. Read only hosts refuse
+    /// requests that return `true`, so only queries should override this.
+    fn mutates(&self) -> bool {
+        true
+    }
+
+    /// The command line this request runs, if it only runs a command. Read
+    /// only hosts still send mutating requests whose command line has been
+    /// allowed with `Host::allow_command()`.
+    fn command_line(&self) -> Option<String> {
+        None
+    }
+
+    /// Hand the request the body of the message it was received in.
+    /// Requests are decoded from the message header alone, and most don't
+    /// expect a body, so by default the body is handed back to be drained.
+    fn set_body(&mut self, body: Body<Bytes, io::Error>) -> Option<Body<Bytes, io::Error>> {
+        Some(body)
+    }
+
     fn exec(self, &Local) -> Self::Future;
 }
 
@@ -33,6 +91,48 @@ macro_rules! buildreq {
         }
 
         impl Request {
+            /// The name of this request type, e.g. "CommandExec".
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(Request::$i(_) => stringify!($i)),+
+                }
+            }
+
+            /// The name of the request type serialized in `header`, even if
+            /// the request itself is malformed. Returns `None` if `header`
+            /// isn't a request type we know.
+            #[doc(hidden)]
+            pub fn name_of(header: &json::Value) -> Option<&'static str> {
+                let map = match header.as_object() {
+                    Some(map) if map.len() == 1 => map,
+                    _ => return None,
+                };
+                match map.keys().next().map(|k| k.as_str()) {
+                    $(Some(stringify!($i)) => Some(stringify!($i)),)+
+                    _ => None,
+                }
+            }
+
+            /// See `Executable::mutates()`.
+            pub fn mutates(&self) -> bool {
+                match *self {
+                    $(Request::$i(ref req) => req.mutates()),+
+                }
+            }
+
+            /// See `Executable::command_line()`.
+            pub fn command_line(&self) -> Option<String> {
+                match *self {
+                    $(Request::$i(ref req) => req.command_line()),+
+                }
+            }
+
+            fn set_body(&mut self, body: Body<Bytes, io::Error>) -> Option<Body<Bytes, io::Error>> {
+                match *self {
+                    $(Request::$i(ref mut req) => req.set_body(body)),+
+                }
+            }
+
             pub fn exec(self, host: &Local) -> Box<Future<Item = InMessage, Error = Error>> {
                 let host = host.clone();
 
@@ -46,20 +146,70 @@ macro_rules! buildreq {
             }
         }
 
-        impl FromMessage for Request {
-            fn from_msg(mut msg: InMessage) -> Result<Self> {
+        impl Request {
+            /// Deserialize a `Request`, draining the message body if the
+            /// request could not be deserialized or doesn't expect a body.
+            #[doc(hidden)]
+            pub fn from_msg_with_handle(msg: InMessage, handle: &Handle) -> Result<Self> {
+                match Request::parse(msg) {
+                    Ok((request, body)) => {
+                        if let Some(b) = body {
+                            drain_body(b, handle);
+                        }
+                        Ok(request)
+                    },
+                    Err((e, body)) => {
+                        if let Some(b) = body {
+                            drain_body(b, handle);
+                        }
+                        Err(e)
+                    },
+                }
+            }
+
+            /// Deserialize the `Request` in a message header, ignoring any
+            /// message body.
+            #[doc(hidden)]
+            pub fn from_header(header: &json::Value) -> Result<Self> {
+                Request::parse(Message::WithoutBody(header.clone()))
+                    .map(|(request, _)| request)
+                    .map_err(|(e, _)| e)
+            }
+
+            // The request is decoded from the header alone, so that the body
+            // is never lost if decoding fails. Any body that the request
+            // didn't take is returned, alongside the request or the error,
+            // so that the caller can dispose of it.
+            fn parse(mut msg: InMessage) -> result::Result<(Self, Option<Body<Bytes, io::Error>>), (Error, Option<Body<Bytes, io::Error>>)> {
                 let body = msg.take_body();
-                let values: RequestValues = json::from_value(msg.into_inner())
-                    .chain_err(|| "Could not deserialize Request")?;
-
-                let request = match values {
-                    $(RequestValues::$i(v) => Request::$i(::$m::$i::from_msg(match body {
-                        Some(b) => Message::WithBody(v, b),
-                        None => Message::WithoutBody(v),
-                    })?)),+
+                let values: RequestValues = match json::from_value(msg.into_inner())
+                    .chain_err(|| "Could not deserialize Request")
+                {
+                    Ok(v) => v,
+                    Err(e) => return Err((e, body)),
                 };
 
-                Ok(request)
+                let mut request = match values {
+                    $(RequestValues::$i(v) => match ::$m::$i::from_msg(Message::WithoutBody(v)) {
+                        Ok(r) => Request::$i(r),
+                        Err(e) => return Err((e, body)),
+                    }),+
+                };
+
+                let body = match body {
+                    Some(b) => request.set_body(b),
+                    None => None,
+                };
+
+                Ok((request, body))
+            }
+        }
+
+        impl FromMessage for Request {
+            fn from_msg(msg: InMessage) -> Result<Self> {
+                Request::parse(msg)
+                    .map(|(request, _)| request)
+                    .map_err(|(e, _)| e)
             }
         }
 
@@ -74,13 +224,91 @@ macro_rules! buildreq {
 
 buildreq!(
     [ command, CommandExec ],
+    [ file, FileDownload ],
+    [ file, FileEnsureLine ],
+    [ file, FileMatches ],
+    [ file, FileWrite ],
+    [ host, MetricsLoad ],
+    [ host, Ping ],
+    [ host, PortCheck ],
+    [ host, Reboot ],
+    [ host, SetHostname ],
+    [ host, Shutdown ],
+    [ host, Which ],
     [ package, PackageInstalled ],
     [ package, PackageInstall ],
+    [ package, PackageInstallFile ],
+    [ package, PackageInstallMany ],
+    [ package, PackageRefresh ],
     [ package, PackageUninstall ],
+    [ package, PackageRequiresRoot ],
+    [ package, PackageCandidate ],
+    [ package, PackageSearch ],
     [ service, ServiceRunning ],
     [ service, ServiceAction ],
+    [ service, ServiceList ],
     [ service, ServiceEnabled ],
     [ service, ServiceEnable ],
     [ service, ServiceDisable ],
+    [ service, ServiceLogs ],
+    [ service, ServiceRequiresRoot ],
     [ telemetry, TelemetryLoad ]
 );
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::Sink;
+    use tokio_core::reactor::Core;
+    use tokio_proto::streaming::{Body, Message};
+    use super::*;
+
+    fn header(s: &str) -> json::Value {
+        json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_malformed_request_returns_body() {
+        let (_tx, body) = Body::pair();
+        // `cmd` is missing, so `CommandExec::from_msg()` fails
+        let msg = Message::WithBody(header(r#"{"CommandExec": {"bogus": true}}"#), body);
+
+        match Request::parse(msg) {
+            Err((_, body)) => assert!(body.is_some()),
+            Ok(_) => panic!("Malformed request was parsed"),
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed_envelope_returns_body() {
+        let (_tx, body) = Body::pair();
+        let msg = Message::WithBody(header(r#"{"NotARequest": {}}"#), body);
+
+        match Request::parse(msg) {
+            Err((_, body)) => assert!(body.is_some()),
+            Ok(_) => panic!("Malformed request was parsed"),
+        }
+    }
+
+    #[test]
+    fn test_name_of_malformed_request() {
+        assert_eq!(Request::name_of(&header(r#"{"CommandExec": {"bogus": true}}"#)), Some("CommandExec"));
+        assert_eq!(Request::name_of(&header(r#"{"NotARequest": {}}"#)), None);
+        assert_eq!(Request::name_of(&header(r#""CommandExec""#)), None);
+    }
+
+    #[test]
+    fn test_from_msg_with_handle_drains_body() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let (tx, body) = Body::pair();
+        let msg = Message::WithBody(header(r#"{"CommandExec": {"bogus": true}}"#), body);
+        assert!(Request::from_msg_with_handle(msg, &handle).is_err());
+
+        // If the body had been dropped, the sender would fail. Instead the
+        // body is drained, so the peer can carry on sending.
+        let tx = core.run(tx.send(Ok(Bytes::from("chunk")))).unwrap();
+        core.run(tx.send(Ok(Bytes::from("another chunk")))).unwrap();
+    }
+}
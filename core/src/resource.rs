@@ -0,0 +1,174 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Declarative resources that compose several endpoints.
+//!
+//! Most automation follows the same pattern: install a package, write its
+//! config file, then enable and start its service. A `Resource` declares
+//! that end state once, and [`apply()`](struct.Resource.html#method.apply)
+//! brings a host in line with it using the idempotent `Package`, `File` and
+//! `Service` operations.
+
+use command::Child;
+use errors::*;
+use file::{File, FileOptions};
+use futures::{future, stream, Future, Stream};
+use host::Host;
+use package::Package;
+use service::Service;
+
+/// The desired state of a group of packages, files and services.
+///
+/// Each part is applied in the order it was added, and the next part is
+/// only applied once the previous one has finished, so declare packages
+/// before the files and services that depend on them.
+///
+///## Example
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::file::FileOptions;
+///use intecture_api::prelude::*;
+///use intecture_api::resource::Resource;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = Local::new(&handle).wait().unwrap();
+///
+///let nginx = Resource::new()
+///    .package("nginx")
+///    .file("/etc/nginx/nginx.conf", b"...", FileOptions::default())
+///    .service("nginx");
+///
+///let changes = core.run(nginx.apply(&host)).unwrap();
+///for change in changes.iter().filter(|c| c.changed) {
+///    println!("Changed {:?}", change.part);
+///}
+///# }
+///```
+#[derive(Clone, Debug, Default)]
+pub struct Resource {
+    steps: Vec<Step>,
+}
+
+/// A part of a `Resource`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+    /// A package that is installed, by name
+    Package(String),
+    /// A file with fixed contents, by path
+    File(String),
+    /// A service that is enabled and running, by name
+    Service(String),
+}
+
+/// Whether applying a part of a `Resource` changed the host.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub part: Part,
+    pub changed: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Package(String),
+    File(String, Vec<u8>, FileOptions),
+    Service(String),
+}
+
+impl Resource {
+    /// Create an empty `Resource`.
+    pub fn new() -> Resource {
+        Resource::default()
+    }
+
+    /// Make sure the package `name` is installed.
+    pub fn package(mut self, name: &str) -> Self {
+        self.steps.push(Step::Package(name.into()));
+        self
+    }
+
+    /// Make sure the file at `path` contains exactly `content`, with the
+    /// given `options`. See
+    /// [`File::ensure_content()`](../file/struct.File.html#method.ensure_content).
+    pub fn file(mut self, path: &str, content: &[u8], options: FileOptions) -> Self {
+        self.steps.push(Step::File(path.into(), content.into(), options));
+        self
+    }
+
+    /// Make sure the service `name` is enabled and running.
+    pub fn service(mut self, name: &str) -> Self {
+        self.steps.push(Step::Service(name.into()));
+        self
+    }
+
+    /// Bring `host` in line with this resource.
+    ///
+    /// Resolves to a `Change` for each part, in the order they were added.
+    /// If a part fails, the parts after it are not applied and its error is
+    /// returned.
+    pub fn apply<H: Host + 'static>(&self, host: &H) -> Box<Future<Item = Vec<Change>, Error = Error>> {
+        let host = host.clone();
+
+        Box::new(stream::iter_ok(self.steps.clone())
+            .and_then(move |step| {
+                let part = step.part();
+                step.apply(&host).map(move |changed| Change { part, changed })
+            })
+            .collect())
+    }
+}
+
+impl Step {
+    fn part(&self) -> Part {
+        match *self {
+            Step::Package(ref name) => Part::Package(name.clone()),
+            Step::File(ref path, _, _) => Part::File(path.clone()),
+            Step::Service(ref name) => Part::Service(name.clone()),
+        }
+    }
+
+    fn apply<H: Host + 'static>(self, host: &H) -> Box<Future<Item = bool, Error = Error>> {
+        match self {
+            Step::Package(name) => {
+                let package = match Package::new(host, &name) {
+                    Ok(p) => p,
+                    Err(e) => return Box::new(future::err(e)),
+                };
+                Box::new(package.install().and_then(finish))
+            },
+            Step::File(path, content, options) => File::new(host, &path).ensure_content(&content, &options),
+            Step::Service(name) => {
+                let service = match Service::new(host, &name) {
+                    Ok(s) => s,
+                    Err(e) => return Box::new(future::err(e)),
+                };
+                Box::new(service.enable()
+                    .and_then(move |enabled| service.action("start")
+                        .and_then(finish)
+                        .map(move |started| enabled.is_some() || started)))
+            },
+        }
+    }
+}
+
+// Wait for the command behind an idempotent operation to finish, returning
+// whether it ran.
+fn finish(child: Option<Child>) -> Box<Future<Item = bool, Error = Error>> {
+    match child.map(|c| c.result()) {
+        Some(Some(result)) => Box::new(result.map(|_| true)),
+        // The output stream has been taken, so we can't wait for it
+        Some(None) => Box::new(future::ok(true)),
+        None => Box::new(future::ok(false)),
+    }
+}
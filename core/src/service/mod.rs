@@ -13,15 +13,23 @@ mod providers;
 
 use command::Child;
 use errors::*;
-use futures::{future, Future};
+use futures::{future, stream, Future, Stream};
 use futures::future::FutureResult;
 use host::Host;
+use host::local::Local;
+use message::{FromMessage, IntoMessage, InMessage};
+use request::Executable;
+use serde_json as json;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_proto::streaming::Message;
+use validate;
 #[doc(hidden)]
 pub use self::providers::{
-    factory, ServiceProvider, Debian, Homebrew, Launchctl,
-    Rc, Redhat, Systemd
+    factory, provider, ServiceProvider, Debian, Homebrew, Launchctl,
+    NixOS, Rc, Redhat, Systemd, SysVInit, Upstart
 };
-pub use self::providers::Provider;
+pub use self::providers::{register_service_provider, Operation, Provider, ServiceProviderFactory, SystemdScope};
 
 /// Represents a system service to be managed for a host.
 ///
@@ -45,7 +53,7 @@ pub use self::providers::Provider;
 ///
 ///let host = Local::new(&handle).wait().unwrap();
 ///
-///let nginx = Service::new(&host, "nginx");
+///let nginx = Service::new(&host, "nginx").unwrap();
 ///let result = nginx.enable()
 ///    .and_then(|_| {
 ///        nginx.action("start")
@@ -63,65 +71,221 @@ pub use self::providers::Provider;
 pub struct Service<H: Host> {
     host: H,
     name: String,
+    provider: Option<Provider>,
+}
+
+/// How [`Service::action_with()`](struct.Service.html#method.action_with)
+/// decides whether an action needs to run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Idempotence {
+    /// Always run the action.
+    Always,
+    /// Only run the action if the service isn't running, like "start".
+    EnsureRunning,
+    /// Only run the action if the service is running, like "stop".
+    EnsureStopped,
+}
+
+/// A service found by [`Service::list()`](struct.Service.html#method.list).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    /// The name to pass to [`Service::new()`](struct.Service.html#method.new)
+    pub name: String,
+    /// Whether the service is running
+    pub running: bool,
+    /// Whether the service starts at boot
+    pub enabled: bool,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "bool"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct ServiceList {}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct ServiceRunning {
+    #[serde(deserialize_with = "validate::de_service_name")]
     name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "Child"]
-#[future = "FutureResult<Self::Response, Error>"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct ServiceAction {
+    #[serde(deserialize_with = "validate::de_service_name")]
     name: String,
+    #[serde(deserialize_with = "validate::de_service_action")]
     action: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "bool"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct ServiceEnabled {
+    #[serde(deserialize_with = "validate::de_service_name")]
     name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "()"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct ServiceEnable {
+    #[serde(deserialize_with = "validate::de_service_name")]
     name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 #[doc(hidden)]
-#[derive(Serialize, Deserialize, FromMessage, IntoMessage, Executable)]
-#[response = "()"]
-#[hostarg = "true"]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
 pub struct ServiceDisable {
+    #[serde(deserialize_with = "validate::de_service_name")]
     name: String,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct ServiceLogs {
+    #[serde(deserialize_with = "validate::de_service_name")]
+    name: String,
+    lines: usize,
+    #[serde(default)]
+    provider: Option<Provider>,
+}
+
+#[doc(hidden)]
+#[derive(Serialize, Deserialize, FromMessage, IntoMessage)]
+pub struct ServiceRequiresRoot {
+    op: Operation,
+    #[serde(default)]
+    provider: Option<Provider>,
 }
 
 impl<H: Host + 'static> Service<H> {
     /// Create a new `Service` with the default [`Provider`](enum.Provider.html).
-    pub fn new(host: &H, name: &str) -> Service<H> {
-        Service {
+    ///
+    /// Returns `ErrorKind::InvalidName` if `name` contains characters that
+    /// are unsafe to pass to a shell command.
+    pub fn new(host: &H, name: &str) -> Result<Service<H>> {
+        validate::service_name(name)?;
+
+        Ok(Service {
             host: host.clone(),
             name: name.into(),
-        }
+            provider: None,
+        })
+    }
+
+    /// Manage this service with the given provider, rather than the host's
+    /// default `Service` provider.
+    ///
+    /// This doesn't change the provider for any other `Service`.
+    /// Operations return `ErrorKind::ProviderUnavailable` if the provider
+    /// isn't available on the host.
+    pub fn using(mut self, provider: Provider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Check whether an operation on the service requires root privileges.
+    ///
+    /// Combined with [`User::is_root()`](../telemetry/struct.User.html#method.is_root),
+    /// this lets you fail fast before attempting an action that will be
+    /// denied. Queries, such as [`running()`](#method.running), never
+    /// require root.
+    pub fn requires_root(&self, op: Operation) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(self.host.request(ServiceRequiresRoot { op, provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "requires_root" }))
+    }
+
+    /// Check whether performing an action on the service requires root
+    /// privileges.
+    pub fn action_requires_root(&self) -> Box<Future<Item = bool, Error = Error>> {
+        self.requires_root(Operation::Action)
+    }
+
+    /// Check whether enabling the service requires root privileges.
+    pub fn enable_requires_root(&self) -> Box<Future<Item = bool, Error = Error>> {
+        self.requires_root(Operation::Enable)
+    }
+
+    /// Check whether disabling the service requires root privileges.
+    pub fn disable_requires_root(&self) -> Box<Future<Item = bool, Error = Error>> {
+        self.requires_root(Operation::Disable)
     }
 
     /// Check if the service is currently running.
     pub fn running(&self) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(self.host.request(ServiceRunning { name: self.name.clone() })
+        Box::new(self.host.request(ServiceRunning { name: self.name.clone(), provider: self.provider })
             .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "running" }))
     }
 
+    /// Get the last `lines` lines of the service's logs.
+    ///
+    /// Where the logs come from depends on the provider. Systemd services
+    /// are read from the journal, Launchd services from the unified log, and
+    /// other providers read the service's log file under `/var/log`.
+    pub fn logs(&self, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        Box::new(self.host.request(ServiceLogs { name: self.name.clone(), lines, provider: self.provider })
+            .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "logs" }))
+    }
+
+    /// List the services that the host's default provider knows about.
+    ///
+    /// This is useful for inventory, where you don't know the names of the
+    /// services in advance. Every built-in provider supports this, but
+    /// custom providers may return an error.
+    pub fn list(host: &H) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        Box::new(host.request(ServiceList {})
+            .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "list" }))
+    }
+
+    /// Wait until the service is running (`desired == true`) or stopped
+    /// (`desired == false`).
+    ///
+    /// The service's state is checked immediately, then once every `poll`
+    /// until it matches `desired`. If it has not done so within `timeout`,
+    /// this returns `ErrorKind::WaitTimeout`.
+    ///
+    /// This is useful after starting a service, which may take some time to
+    /// come up.
+    pub fn wait_until(&self, desired: bool, timeout: Duration, poll: Duration) -> Box<Future<Item = (), Error = Error>> {
+        let interval = match Interval::new(poll, self.host.handle()) {
+            Ok(i) => i,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create poll interval"))),
+        };
+        let deadline = match Timeout::new(timeout, self.host.handle()) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create timeout"))),
+        };
+
+        let host = self.host.clone();
+        let name = self.name.clone();
+        let provider = self.provider;
+        let state = if desired { "running" } else { "stopped" };
+        let waiting_for = format!("service '{}' to be {}", name, state);
+
+        let checks = stream::once(Ok(()))
+            .chain(interval.map_err(|e| Error::with_chain(e, "Poll interval failed")))
+            .and_then(move |_| host.request(ServiceRunning { name: name.clone(), provider })
+                .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "wait_until" }))
+            .filter(move |running| *running == desired)
+            .into_future()
+            .map(|_| ())
+            .map_err(|(e, _)| e);
+
+        let deadline = deadline.then(move |_| Err(ErrorKind::WaitTimeout(waiting_for).into()));
+
+        Box::new(checks.select(deadline)
+            .map(|(r, _)| r)
+            .map_err(|(e, _)| e))
+    }
+
     /// Perform an action for the service, e.g. "start".
     ///
     ///## Cross-platform services
@@ -131,6 +295,10 @@ impl<H: Host + 'static> Service<H> {
     /// wrote the service configuration, thus users should take care that they
     /// adhere to the configuration for each platform they target.
     ///
+    /// An action is passed to the service manager as a command argument, so
+    /// it may only contain letters, numbers, `_` and `-`, and may not start
+    /// with `-`.
+    ///
     ///## Idempotence
     ///
     /// This function is idempotent when running either the "start" or "stop"
@@ -146,34 +314,62 @@ impl<H: Host + 'static> Service<H> {
     /// [`Command` docs](../command/struct.Command.html) for detailed
     /// usage.
     pub fn action(&self, action: &str) -> Box<Future<Item = Option<Child>, Error = Error>> {
-        if action == "start" || action == "stop" {
-            let host = self.host.clone();
-            let name = self.name.clone();
-            let action = action.to_owned();
-
-            Box::new(self.running()
-                .and_then(move |running| {
-                    if (running && action == "start") || (!running && action == "stop") {
-                        Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
-                    } else {
-                        Box::new(Self::do_action(&host, &name, &action)
-                            .map(|c| Some(c)))
-                    }
-                }))
-        } else {
-            Box::new(Self::do_action(&self.host, &self.name, action)
-                .map(|c| Some(c)))
+        let idempotence = match action {
+            "start" => Idempotence::EnsureRunning,
+            "stop" => Idempotence::EnsureStopped,
+            _ => Idempotence::Always,
+        };
+        self.action_with(action, idempotence)
+    }
+
+    /// Perform an action for the service, skipping it if the service is
+    /// already in the state the action leaves it in.
+    ///
+    /// This lets custom actions share the idempotence of "start" and "stop"
+    /// in [`action()`](#method.action). For example, a "reload-or-restart"
+    /// action leaves the service running, so
+    /// `action_with("reload-or-restart", Idempotence::EnsureRunning)` only
+    /// runs it if the service is stopped.
+    pub fn action_with(&self, action: &str, idempotence: Idempotence) -> Box<Future<Item = Option<Child>, Error = Error>> {
+        if idempotence == Idempotence::Always {
+            return Box::new(Self::do_action(&self.host, &self.name, action, self.provider)
+                .map(|c| Some(c)));
         }
+
+        let host = self.host.clone();
+        let name = self.name.clone();
+        let action = action.to_owned();
+        let provider = self.provider;
+
+        Box::new(self.running()
+            .and_then(move |running| {
+                let done = match idempotence {
+                    Idempotence::EnsureRunning => running,
+                    Idempotence::EnsureStopped => !running,
+                    Idempotence::Always => false,
+                };
+
+                if done {
+                    Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
+                } else {
+                    Box::new(Self::do_action(&host, &name, &action, provider)
+                        .map(|c| Some(c)))
+                }
+            }))
     }
 
-    fn do_action(host: &H, name: &str, action: &str) -> Box<Future<Item = Child, Error = Error>> {
-        Box::new(host.request(ServiceAction { name: name.into(), action: action.into() })
+    fn do_action(host: &H, name: &str, action: &str, provider: Option<Provider>) -> Box<Future<Item = Child, Error = Error>> {
+        if let Err(e) = validate::service_action(action) {
+            return Box::new(future::err(e));
+        }
+
+        Box::new(host.request(ServiceAction { name: name.into(), action: action.into(), provider })
             .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "action" }))
     }
 
     /// Check if the service will start at boot.
     pub fn enabled(&self) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(self.host.request(ServiceEnabled { name: self.name.clone() })
+        Box::new(self.host.request(ServiceEnabled { name: self.name.clone(), provider: self.provider })
             .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "enabled" }))
     }
 
@@ -195,13 +391,14 @@ impl<H: Host + 'static> Service<H> {
     {
         let host = self.host.clone();
         let name = self.name.clone();
+        let provider = self.provider;
 
         Box::new(self.enabled()
             .and_then(move |enabled| {
                 if enabled {
                     Box::new(future::ok(None)) as Box<Future<Item = _, Error = Error>>
                 } else {
-                    Box::new(host.request(ServiceEnable { name: name.into() })
+                    Box::new(host.request(ServiceEnable { name, provider })
                         .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "enable" })
                         .map(|_| Some(())))
                 }
@@ -226,11 +423,12 @@ impl<H: Host + 'static> Service<H> {
     {
         let host = self.host.clone();
         let name = self.name.clone();
+        let provider = self.provider;
 
         Box::new(self.enabled()
             .and_then(move |enabled| {
                 if enabled {
-                    Box::new(host.request(ServiceDisable { name: name.into() })
+                    Box::new(host.request(ServiceDisable { name, provider })
                         .chain_err(|| ErrorKind::Request { endpoint: "Service", func: "disable" })
                         .map(|_| Some(())))
                 } else {
@@ -239,3 +437,144 @@ impl<H: Host + 'static> Service<H> {
             }))
     }
 }
+
+impl Executable for ServiceRunning {
+    type Response = bool;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.running(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for ServiceList {
+    type Response = Vec<ServiceInfo>;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        host.service().list(host)
+    }
+}
+
+impl Executable for ServiceAction {
+    type Response = Child;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (name, action) = (self.name, self.action);
+        match with_provider(host, self.provider, |p| p.action(host, &name, &action)) {
+            Ok(f) => f,
+            Err(e) => future::err(e),
+        }
+    }
+}
+
+impl Executable for ServiceEnabled {
+    type Response = bool;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.enabled(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for ServiceEnable {
+    type Response = ();
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.enable(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for ServiceDisable {
+    type Response = ();
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let name = self.name;
+        match with_provider(host, self.provider, |p| p.disable(host, &name)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for ServiceLogs {
+    type Response = Vec<String>;
+    type Future = Box<Future<Item = Self::Response, Error = Error>>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let (name, lines) = (self.name, self.lines);
+        match with_provider(host, self.provider, |p| p.logs(host, &name, lines)) {
+            Ok(f) => f,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
+
+impl Executable for ServiceRequiresRoot {
+    type Response = bool;
+    type Future = FutureResult<Self::Response, Error>;
+
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        let op = self.op;
+        future::result(with_provider(host, self.provider, |p| p.requires_root(op)))
+    }
+}
+
+impl FromMessage for Vec<ServiceInfo> {
+    fn from_msg(msg: InMessage) -> Result<Self> {
+        json::from_value(msg.into_inner()).chain_err(|| "Could not deserialize ServiceInfo list")
+    }
+}
+
+impl IntoMessage for Vec<ServiceInfo> {
+    fn into_msg(self, _: &Handle) -> Result<InMessage> {
+        let value = json::to_value(self).chain_err(|| "Could not convert type into Message")?;
+        Ok(Message::WithoutBody(value))
+    }
+}
+
+// Call `f` with the provider chosen for an operation, or the host's default
+// provider if there isn't one.
+fn with_provider<F, R>(host: &Local, provider: Option<Provider>, f: F) -> Result<R>
+    where F: FnOnce(&ServiceProvider) -> R
+{
+    match provider {
+        Some(p) => Ok(f(&*providers::provider(p, host.try_telemetry()?)?)),
+        None => Ok(f(&**host.service())),
+    }
+}
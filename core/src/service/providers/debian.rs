@@ -4,7 +4,7 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{Child, factory};
+use command::{hooks, Child, factory};
 use error_chain::ChainedError;
 use errors::*;
 use futures::{future, Future};
@@ -12,9 +12,9 @@ use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
 use regex::Regex;
+use service::ServiceInfo;
 use std::fs::read_dir;
-use std::process;
-use super::ServiceProvider;
+use super::{ServiceProvider, describe, init_scripts, tail_log};
 use telemetry::{LinuxDistro, OsFamily, Telemetry};
 use tokio_process::CommandExt;
 
@@ -26,8 +26,7 @@ impl ServiceProvider for Debian {
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(match process::Command::new("service")
-            .args(&[name, "status"])
+        Box::new(match hooks::command("service", &[name, "status"])
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is running")
         {
@@ -48,7 +47,7 @@ impl ServiceProvider for Debian {
     fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("/sbin/runlevel")
+        Box::new(hooks::command("/sbin/runlevel", &[])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("/sbin/runlevel")))
             .and_then(move |output| {
@@ -87,8 +86,7 @@ impl ServiceProvider for Debian {
     }
 
     fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/update-rc.d")
-            .args(&["enable", name])
+        Box::new(hooks::command("/usr/sbin/update-rc.d", &["enable", name])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("update-rc.d enable <service>")))
             .and_then(|out| {
@@ -101,8 +99,7 @@ impl ServiceProvider for Debian {
     }
 
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/update-rc.d")
-            .args(&["disable", name])
+        Box::new(hooks::command("/usr/sbin/update-rc.d", &["disable", name])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("update-rc.d disable <service>")))
             .and_then(|out| {
@@ -113,4 +110,15 @@ impl ServiceProvider for Debian {
                 }
             }))
     }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        tail_log(host, &[format!("/var/log/{}.log", name), format!("/var/log/{}", name)], lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        match init_scripts() {
+            Ok(names) => describe(self, host, names),
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
 }
@@ -10,32 +10,40 @@ use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::local::Local;
-use std::process;
-use super::{Launchctl, ServiceProvider};
+use package::brew_path;
+use service::ServiceInfo;
+use std::path::PathBuf;
+use super::{Launchctl, Operation, ServiceProvider};
 use telemetry::Telemetry;
 
+/// Homebrew service provider.
+///
+/// Homebrew services are launchd jobs, so this provider is only available on
+/// macOS. Homebrew on Linux uses the host's init system instead.
 pub struct Homebrew {
     inner: Launchctl,
+    prefix: PathBuf,
 }
 
 impl Homebrew {
     #[doc(hidden)]
     pub fn new(telemetry: &Telemetry) -> Homebrew {
+        // `brew` lives in <prefix>/bin
+        let prefix = brew_path()
+            .and_then(|p| p.parent().and_then(|p| p.parent()).map(|p| p.to_owned()))
+            .unwrap_or_else(|| PathBuf::from("/usr/local"));
+
         Homebrew {
             inner: Launchctl::new(telemetry),
+            prefix: prefix,
         }
     }
 }
 
 impl ServiceProvider for Homebrew {
     fn available(telemetry: &Telemetry) -> Result<bool> {
-        let brew = process::Command::new("/usr/bin/type")
-            .arg("brew")
-            .status()
-            .chain_err(|| "Could not determine provider availability")?
-            .success();
-
-        Ok(brew && Launchctl::available(telemetry)?)
+        // Check for launchd first, as that rules out Homebrew on Linux
+        Ok(Launchctl::available(telemetry)? && brew_path().is_some())
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
@@ -48,7 +56,7 @@ impl ServiceProvider for Homebrew {
         let result = if action == "stop" {
             self.inner.uninstall_plist(name)
         } else {
-            let path = format!("/usr/local/opt/{}/homebrew.mxcl.{0}.plist", name);
+            let path = self.prefix.join(format!("opt/{}/homebrew.mxcl.{0}.plist", name));
             self.inner.install_plist(path)
         };
 
@@ -69,4 +77,16 @@ impl ServiceProvider for Homebrew {
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
         self.inner.disable(host, name)
     }
+
+    fn requires_root(&self, op: Operation) -> bool {
+        self.inner.requires_root(op)
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        self.inner.logs(host, name, lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        self.inner.list(host)
+    }
 }
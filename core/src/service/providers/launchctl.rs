@@ -4,17 +4,18 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{Child, factory};
+use command::{hooks, Child, factory};
 use error_chain::ChainedError;
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use regex::Regex;
-use std::{fs, process};
+use regex::{self, Regex};
+use service::ServiceInfo;
+use std::fs;
 use std::path::{Path, PathBuf};
-use super::ServiceProvider;
+use super::{Operation, ServiceProvider, log_lines, stdout};
 use telemetry::{OsFamily, Telemetry};
 use tokio_process::CommandExt;
 
@@ -80,8 +81,7 @@ impl ServiceProvider for Launchctl {
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(match process::Command::new("/bin/launchctl")
-            .args(&["blame", &format!("{}/{}", self.domain_target, name)])
+        Box::new(match hooks::command("/bin/launchctl", &["blame", &format!("{}/{}", self.domain_target, name)])
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is running")
         {
@@ -92,11 +92,11 @@ impl ServiceProvider for Launchctl {
     }
 
     fn action(&self, host: &Local, name: &str, action: &str) -> FutureResult<Child, Error> {
-        let action = match action {
-            "start" => "bootstrap",
-            "stop" => "bootout",
-            "restart" => "kickstart -k",
-            _ => action,
+        let action: &[&str] = match action {
+            "start" => &["bootstrap"],
+            "stop" => &["bootout"],
+            "restart" => &["kickstart", "-k"],
+            _ => &[action],
         };
 
         let cmd = match factory() {
@@ -104,26 +104,23 @@ impl ServiceProvider for Launchctl {
             Err(e) => return future::err(format!("{}", e.display_chain()).into()),
         };
 
-        // Run through shell as `action` may contain multiple args with spaces.
-        // If we passed `action` as a single argument, it would automatically
-        // be quoted and multiple args would appear as a single quoted arg.
-        cmd.exec(host, &[
-            "/bin/sh",
-            "-c",
-            &format!("/bin/launchctl {} {} {}/{}.plist", action, self.domain_target, self.service_path.display(), name)
-        ])
+        let plist = format!("{}/{}.plist", self.service_path.display(), name);
+        let mut args = vec!["/bin/launchctl"];
+        args.extend_from_slice(action);
+        args.push(&self.domain_target);
+        args.push(&plist);
+        cmd.exec(host, &args)
     }
 
     fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("/bin/launchctl")
-            .args(&["print-disabled", &self.domain_target])
+        Box::new(hooks::command("/bin/launchctl", &["print-disabled", &self.domain_target])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("launchctl print-disabled <domain_target>")))
             .and_then(move |out| {
                 if out.status.success() {
-                    let re = match Regex::new(&format!("^\\s+\"{}\" => false", name)) {
+                    let re = match Regex::new(&format!("^\\s+\"{}\" => false", regex::escape(&name))) {
                         Ok(r) => r,
                         Err(e) => return future::err(Error::with_chain(e, ErrorKind::Msg("Could not create Launchctl::enabled Regex".into())))
                     };
@@ -138,8 +135,7 @@ impl ServiceProvider for Launchctl {
     }
 
     fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/bin/launchctl")
-            .args(&["enable", &format!("{}/{}", self.domain_target, name)])
+        Box::new(hooks::command("/bin/launchctl", &["enable", &format!("{}/{}", self.domain_target, name)])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("launchctl enable <service>")))
             .and_then(|out| {
@@ -152,8 +148,7 @@ impl ServiceProvider for Launchctl {
     }
 
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/bin/launchctl")
-            .args(&["disable", &format!("{}/{}", self.domain_target, name)])
+        Box::new(hooks::command("/bin/launchctl", &["disable", &format!("{}/{}", self.domain_target, name)])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("launchctl disable <service>")))
             .and_then(|out| {
@@ -164,4 +159,42 @@ impl ServiceProvider for Launchctl {
                 }
             }))
     }
+
+    // Services are managed in the user's domain unless we are already root
+    fn requires_root(&self, op: Operation) -> bool {
+        op != Operation::Query && self.domain_target == "system"
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        let cmd = hooks::command("/usr/bin/log", &["show", "--style", "compact", "--last", "1d", "--predicate", &format!("process == \"{}\"", name)]);
+        log_lines(host, cmd, lines, "log show")
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        let list = stdout(host, hooks::command("/bin/launchctl", &["list"]), "launchctl list");
+        let disabled = stdout(host, hooks::command("/bin/launchctl", &["print-disabled", &self.domain_target]),
+            "launchctl print-disabled <domain_target>");
+
+        // Jobs are listed as "<pid>\t<status>\t<label>" after a header, with
+        // a pid of "-" if the job isn't running.
+        Box::new(list.join(disabled).map(|(list, disabled)| {
+            list.lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let cols: Vec<_> = line.split('\t').collect();
+                    if cols.len() < 3 {
+                        return None;
+                    }
+
+                    // Consistent with `enabled()`
+                    let entry = format!("\"{}\" => false", cols[2]);
+                    Some(ServiceInfo {
+                        name: cols[2].into(),
+                        running: cols[0] != "-",
+                        enabled: !disabled.lines().any(|l| l.trim() == entry),
+                    })
+                })
+                .collect()
+        }))
+    }
 }
@@ -9,22 +9,48 @@
 mod debian;
 mod homebrew;
 mod launchctl;
+mod nixos;
 mod rc;
 mod redhat;
 mod systemd;
+mod sysvinit;
+mod upstart;
 
-use command::Child;
+use command::{hooks, Child};
 use errors::*;
-use futures::Future;
+use futures::{future, Future};
 use futures::future::FutureResult;
+use host::Host;
 use host::local::Local;
+use registry::{Registry, Rejections};
 pub use self::debian::Debian;
 pub use self::homebrew::Homebrew;
 pub use self::launchctl::Launchctl;
+pub use self::nixos::NixOS;
 pub use self::rc::Rc;
 pub use self::redhat::Redhat;
-pub use self::systemd::Systemd;
+pub use self::systemd::{Systemd, SystemdScope};
+pub use self::sysvinit::SysVInit;
+pub use self::upstart::Upstart;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use super::ServiceInfo;
 use telemetry::Telemetry;
+use tokio_process::CommandExt;
+
+/// An operation on a service, used to ask a provider whether the operation
+/// requires root privileges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    /// Start, stop or restart a service, or any other action
+    Action,
+    Enable,
+    Disable,
+    /// Any operation that only queries the host, e.g. `running()`
+    Query,
+}
 
 /// Specific implementation of `Service`
 #[derive(Clone, Copy, Serialize, Deserialize)]
@@ -32,9 +58,12 @@ pub enum Provider {
     Debian,
     Homebrew,
     Launchctl,
+    NixOS,
     Rc,
     Redhat,
     Systemd,
+    SysVInit,
+    Upstart,
 }
 
 pub trait ServiceProvider {
@@ -44,23 +73,170 @@ pub trait ServiceProvider {
     fn enabled(&self, &Local, &str) -> Box<Future<Item = bool, Error = Error>>;
     fn enable(&self, &Local, &str) -> Box<Future<Item = (), Error = Error>>;
     fn disable(&self, &Local, &str) -> Box<Future<Item = (), Error = Error>>;
+    fn logs(&self, &Local, &str, usize) -> Box<Future<Item = Vec<String>, Error = Error>>;
+
+    /// List the services this provider manages, and their state.
+    fn list(&self, _: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        Box::new(future::err("This service provider cannot list services".into()))
+    }
+
+    /// Whether an operation requires root privileges. By default, only
+    /// queries don't.
+    fn requires_root(&self, op: Operation) -> bool {
+        op != Operation::Query
+    }
+}
+
+/// A function that creates a custom `ServiceProvider`.
+///
+/// The function should return `None` if the provider is not available on the
+/// host described by the `Telemetry`.
+pub type ServiceProviderFactory = Fn(&Telemetry) -> Result<Option<Box<ServiceProvider>>> + Send + Sync;
+
+lazy_static! {
+    static ref REGISTRY: Registry<ServiceProviderFactory> = Registry::new();
+}
+
+/// Register a custom `ServiceProvider`.
+///
+/// `factory` is called with the host's `Telemetry` when the host picks its
+/// default `ServiceProvider`, and is ordered against the built-in providers
+/// by `priority` (see
+/// [`BUILTIN_PRIORITY`](../constant.BUILTIN_PRIORITY.html)).
+pub fn register_service_provider<F>(priority: i32, factory: F)
+    where F: Fn(&Telemetry) -> Result<Option<Box<ServiceProvider>>> + Send + Sync + 'static
+{
+    REGISTRY.register(priority, Arc::new(factory));
 }
 
 #[doc(hidden)]
 pub fn factory(telemetry: &Telemetry) -> Result<Box<ServiceProvider>> {
-    if Systemd::available(telemetry)? {
-        Ok(Box::new(Systemd))
-    } else if Debian::available(telemetry)? {
-        Ok(Box::new(Debian))
-    } else if Homebrew::available(telemetry)? {
-        Ok(Box::new(Homebrew::new(telemetry)))
-    } else if Launchctl::available(telemetry)? {
-        Ok(Box::new(Launchctl::new(telemetry)))
-    } else if Rc::available(telemetry)? {
-        Ok(Box::new(Rc))
-    } else if Redhat::available(telemetry)? {
-        Ok(Box::new(Redhat))
+    let mut rejections = Rejections::new();
+    let provider = REGISTRY.resolve(|| builtin(telemetry, &mut rejections), |f| f(telemetry))?;
+    provider.ok_or_else(|| rejections.into_error("Service"))
+}
+
+/// Create the given provider, or return `ErrorKind::ProviderUnavailable` if
+/// it isn't available on the host described by the `Telemetry`.
+#[doc(hidden)]
+pub fn provider(provider: Provider, telemetry: &Telemetry) -> Result<Box<ServiceProvider>> {
+    let (available, p): (bool, Box<ServiceProvider>) = match provider {
+        Provider::Debian => (Debian::available(telemetry)?, Box::new(Debian)),
+        Provider::Homebrew => (Homebrew::available(telemetry)?, Box::new(Homebrew::new(telemetry))),
+        Provider::Launchctl => (Launchctl::available(telemetry)?, Box::new(Launchctl::new(telemetry))),
+        Provider::NixOS => (NixOS::available(telemetry)?, Box::new(NixOS::new(telemetry))),
+        Provider::Rc => (Rc::available(telemetry)?, Box::new(Rc)),
+        Provider::Redhat => (Redhat::available(telemetry)?, Box::new(Redhat)),
+        Provider::Systemd => (Systemd::available(telemetry)?, Box::new(Systemd::new(telemetry))),
+        Provider::SysVInit => (SysVInit::available(telemetry)?, Box::new(SysVInit::new())),
+        Provider::Upstart => (Upstart::available(telemetry)?, Box::new(Upstart)),
+    };
+
+    if available {
+        Ok(p)
     } else {
         Err(ErrorKind::ProviderUnavailable("Service").into())
     }
 }
+
+fn builtin(telemetry: &Telemetry, rejections: &mut Rejections) -> Result<Option<Box<ServiceProvider>>> {
+    if rejections.check("nixos", NixOS::available(telemetry)) {
+        Ok(Some(Box::new(NixOS::new(telemetry))))
+    } else if rejections.check("systemd", Systemd::available(telemetry)) {
+        Ok(Some(Box::new(Systemd::new(telemetry))))
+    } else if rejections.check("upstart", Upstart::available(telemetry)) {
+        Ok(Some(Box::new(Upstart)))
+    } else if rejections.check("debian", Debian::available(telemetry)) {
+        Ok(Some(Box::new(Debian)))
+    } else if rejections.check("homebrew", Homebrew::available(telemetry)) {
+        Ok(Some(Box::new(Homebrew::new(telemetry))))
+    } else if rejections.check("launchctl", Launchctl::available(telemetry)) {
+        Ok(Some(Box::new(Launchctl::new(telemetry))))
+    } else if rejections.check("rc", Rc::available(telemetry)) {
+        Ok(Some(Box::new(Rc)))
+    } else if rejections.check("redhat", Redhat::available(telemetry)) {
+        Ok(Some(Box::new(Redhat)))
+    } else if rejections.check("sysvinit", SysVInit::available(telemetry)) {
+        Ok(Some(Box::new(SysVInit::new())))
+    } else {
+        Ok(None)
+    }
+}
+
+// Get the last `lines` lines of the first log file in `paths` that exists.
+fn tail_log(host: &Local, paths: &[String], lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+    let path = match paths.iter().find(|p| Path::new(p).exists()) {
+        Some(p) => p,
+        None => return Box::new(future::err(format!("Could not find a log file in {}", paths.join(", ")).into())),
+    };
+
+    let cmd = hooks::command("tail", &["-n", &lines.to_string(), path]);
+    log_lines(host, cmd, lines, "tail")
+}
+
+// Run a command that prints log lines, and return the last `lines` of them.
+fn log_lines(host: &Local, mut cmd: process::Command, lines: usize, name: &'static str) -> Box<Future<Item = Vec<String>, Error = Error>> {
+    Box::new(cmd.output_async(host.handle())
+        .map_err(move |e| Error::with_chain(e, ErrorKind::SystemCommand(name)))
+        .and_then(move |out| {
+            if out.status.success() {
+                let stdout = String::from_utf8_lossy(&out.stdout);
+                let all: Vec<_> = stdout.lines().map(|l| l.to_owned()).collect();
+                let skip = all.len().saturating_sub(lines);
+                future::ok(all.into_iter().skip(skip).collect())
+            } else {
+                future::err(format!("Could not get service logs: {}", String::from_utf8_lossy(&out.stderr)).into())
+            }
+        }))
+}
+
+// Run a command and return its stdout, or an error if it fails.
+fn stdout(host: &Local, mut cmd: process::Command, name: &'static str) -> Box<Future<Item = String, Error = Error>> {
+    Box::new(cmd.output_async(host.handle())
+        .map_err(move |e| Error::with_chain(e, ErrorKind::SystemCommand(name)))
+        .and_then(move |out| {
+            if out.status.success() {
+                future::ok(String::from_utf8_lossy(&out.stdout).into_owned())
+            } else {
+                future::err(format!("Error running `{}`: {}", name, String::from_utf8_lossy(&out.stderr)).into())
+            }
+        }))
+}
+
+// Look up whether each of `names` is running and enabled, for providers
+// that can only list service names.
+fn describe(provider: &ServiceProvider, host: &Local, names: Vec<String>) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+    let services: Vec<_> = names.into_iter()
+        .map(|name| provider.running(host, &name)
+            .join(provider.enabled(host, &name))
+            .map(move |(running, enabled)| ServiceInfo { name, running, enabled }))
+        .collect();
+
+    Box::new(future::join_all(services))
+}
+
+// The names of the executable scripts in /etc/init.d, skipping helpers
+// like `README` and `functions` that aren't services.
+fn init_scripts() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir("/etc/init.d").chain_err(|| ErrorKind::SystemFile("/etc/init.d"))? {
+        let entry = entry.chain_err(|| ErrorKind::SystemFile("/etc/init.d"))?;
+        if is_executable(&entry.path()) {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,80 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::Child;
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::local::Local;
+use service::ServiceInfo;
+use super::{Operation, ServiceProvider, Systemd};
+use telemetry::{OsPlatform, Telemetry};
+
+const SYSTEMCTL: &'static str = "/run/current-system/sw/bin/systemctl";
+const JOURNALCTL: &'static str = "/run/current-system/sw/bin/journalctl";
+
+/// The NixOS `Service` provider.
+///
+/// NixOS runs systemd, so this provider wraps `Systemd`. However services
+/// are configured declaratively in `configuration.nix`, and unit files live
+/// on a read-only filesystem, so `enable()` and `disable()` return an error
+/// explaining how to make the change instead.
+pub struct NixOS {
+    inner: Systemd,
+}
+
+impl NixOS {
+    #[doc(hidden)]
+    pub fn new(telemetry: &Telemetry) -> NixOS {
+        NixOS {
+            inner: Systemd::new(telemetry)
+                .with_systemctl(SYSTEMCTL)
+                .with_journalctl(JOURNALCTL),
+        }
+    }
+}
+
+impl ServiceProvider for NixOS {
+    fn available(telemetry: &Telemetry) -> Result<bool> {
+        Ok(telemetry.os.platform == OsPlatform::Nixos && Systemd::available(telemetry)?)
+    }
+
+    fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        self.inner.running(host, name)
+    }
+
+    fn action(&self, host: &Local, name: &str, action: &str) -> FutureResult<Child, Error> {
+        self.inner.action(host, name, action)
+    }
+
+    fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        self.inner.enabled(host, name)
+    }
+
+    fn enable(&self, _: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(future::err(format!("Services cannot be enabled imperatively on NixOS. \
+            Add `systemd.services.{}.wantedBy = [ \"multi-user.target\" ];` to your \
+            configuration.nix and run `nixos-rebuild switch` instead", name).into()))
+    }
+
+    fn disable(&self, _: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(future::err(format!("Services cannot be disabled imperatively on NixOS. \
+            Set `systemd.services.{}.wantedBy = pkgs.lib.mkForce [ ];` in your \
+            configuration.nix and run `nixos-rebuild switch` instead", name).into()))
+    }
+
+    fn requires_root(&self, op: Operation) -> bool {
+        self.inner.requires_root(op)
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        self.inner.logs(host, name, lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        self.inner.list(host)
+    }
+}
@@ -4,7 +4,7 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{Child, factory};
+use command::{hooks, Child, factory};
 use error_chain::ChainedError;
 use errors::*;
 use futures::{future, Future};
@@ -12,8 +12,8 @@ use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
 use regex::Regex;
-use std::process;
-use super::ServiceProvider;
+use service::ServiceInfo;
+use super::{ServiceProvider, describe, stdout, tail_log};
 use telemetry::{OsFamily, Telemetry};
 use tokio_process::CommandExt;
 
@@ -25,8 +25,7 @@ impl ServiceProvider for Rc {
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(match process::Command::new("service")
-            .args(&[name, "status"])
+        Box::new(match hooks::command("service", &[name, "status"])
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is running")
         {
@@ -47,8 +46,7 @@ impl ServiceProvider for Rc {
     fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
         let name = name.to_owned();
 
-        Box::new(process::Command::new("/usr/sbin/sysrc")
-            .arg(&format!("{}_enable", name)) // XXX Assuming "_enable" is the correct suffix
+        Box::new(hooks::command("/usr/sbin/sysrc", &[&format!("{}_enable", name)]) // XXX Assuming "_enable" is the correct suffix
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("/usr/sbin/sysrc <service>_enable")))
             .and_then(move |output| {
@@ -70,8 +68,7 @@ impl ServiceProvider for Rc {
     }
 
     fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/sysrc")
-            .arg(&format!("{}_enable=\"YES\"", name))
+        Box::new(hooks::command("/usr/sbin/sysrc", &[&format!("{}_enable=\"YES\"", name)])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("systemctl enable <service>")))
             .and_then(|out| {
@@ -84,8 +81,7 @@ impl ServiceProvider for Rc {
     }
 
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/sysrc")
-            .arg(&format!("{}_enable=\"NO\"", name))
+        Box::new(hooks::command("/usr/sbin/sysrc", &[&format!("{}_enable=\"NO\"", name)])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("systemctl disable <service>")))
             .and_then(|out| {
@@ -96,4 +92,22 @@ impl ServiceProvider for Rc {
                 }
             }))
     }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        tail_log(host, &[format!("/var/log/{}.log", name), format!("/var/log/{}", name)], lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        let host = host.clone();
+
+        Box::new(stdout(&host, hooks::command("service", &["-l"]), "service -l")
+            .and_then(move |out| {
+                let names = out.lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_owned())
+                    .collect();
+                describe(&Rc, &host, names)
+            }))
+    }
 }
@@ -4,15 +4,15 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{Child, factory};
+use command::{hooks, Child, factory};
 use error_chain::ChainedError;
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use std::process;
-use super::ServiceProvider;
+use service::ServiceInfo;
+use super::{ServiceProvider, describe, init_scripts, tail_log};
 use telemetry::{LinuxDistro, OsFamily, Telemetry};
 use tokio_process::CommandExt;
 
@@ -24,8 +24,7 @@ impl ServiceProvider for Redhat {
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(match process::Command::new("service")
-            .args(&[name, "status"])
+        Box::new(match hooks::command("service", &[name, "status"])
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is running")
         {
@@ -44,8 +43,7 @@ impl ServiceProvider for Redhat {
     }
 
     fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        match process::Command::new("/usr/sbin/chkconfig")
-            .arg(name)
+        match hooks::command("/usr/sbin/chkconfig", &[name])
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is enabled")
         {
@@ -56,8 +54,7 @@ impl ServiceProvider for Redhat {
     }
 
     fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/chkconfig")
-            .args(&[name, "on"])
+        Box::new(hooks::command("/usr/sbin/chkconfig", &[name, "on"])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("chkconfig <service> on")))
             .and_then(|out| {
@@ -70,8 +67,7 @@ impl ServiceProvider for Redhat {
     }
 
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("/usr/sbin/chkconfig")
-            .args(&[name, "off"])
+        Box::new(hooks::command("/usr/sbin/chkconfig", &[name, "off"])
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("chkconfig <service> off")))
             .and_then(|out| {
@@ -82,4 +78,15 @@ impl ServiceProvider for Redhat {
                 }
             }))
     }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        tail_log(host, &[format!("/var/log/{}.log", name), format!("/var/log/{}", name)], lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        match init_scripts() {
+            Ok(names) => describe(self, host, names),
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
 }
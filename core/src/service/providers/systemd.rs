@@ -4,38 +4,89 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use command::{Child, factory};
+use command::{hooks, Child, factory};
 use error_chain::ChainedError;
 use errors::*;
 use futures::{future, Future};
 use futures::future::FutureResult;
 use host::Host;
 use host::local::Local;
-use std::process;
-use super::ServiceProvider;
+use service::ServiceInfo;
+use std::collections::BTreeMap;
+use super::{Operation, ServiceProvider, log_lines, stdout};
 use telemetry::Telemetry;
 use tokio_process::CommandExt;
 
-pub struct Systemd;
+pub struct Systemd {
+    scope: SystemdScope,
+    systemctl: &'static str,
+    journalctl: &'static str,
+}
 
-impl ServiceProvider for Systemd {
-    fn available(_: &Telemetry) -> Result<bool> {
-        let output = process::Command::new("/usr/bin/stat")
-            .args(&["--format=%N", "/proc/1/exe"])
-            .output()
-            .chain_err(|| "Could not determine provider availability")?;
-
-        if output.status.success() {
-            let out = String::from_utf8_lossy(&output.stdout);
-            Ok(out.contains("systemd"))
+/// The systemd instance that services are managed through.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SystemdScope {
+    /// The system instance, i.e. `systemctl`
+    System,
+    /// The calling user's instance, i.e. `systemctl --user`
+    User,
+}
+
+impl Systemd {
+    /// Create a new `Systemd` provider, which manages system services if
+    /// the current user is root, or the user's own services otherwise.
+    #[doc(hidden)]
+    pub fn new(telemetry: &Telemetry) -> Systemd {
+        let scope = if telemetry.user.is_root() {
+            SystemdScope::System
         } else {
-            Err(ErrorKind::SystemCommand("/usr/bin/stat").into())
+            SystemdScope::User
+        };
+
+        Systemd { scope, systemctl: "systemctl", journalctl: "journalctl" }
+    }
+
+    /// Create a new `Systemd` provider for the given scope.
+    pub fn with_scope(scope: SystemdScope) -> Systemd {
+        Systemd { scope, systemctl: "systemctl", journalctl: "journalctl" }
+    }
+
+    // Use the `systemctl` binary at the given path, rather than searching
+    // `PATH` for it.
+    #[doc(hidden)]
+    pub fn with_systemctl(mut self, path: &'static str) -> Systemd {
+        self.systemctl = path;
+        self
+    }
+
+    // Use the `journalctl` binary at the given path, rather than searching
+    // `PATH` for it.
+    #[doc(hidden)]
+    pub fn with_journalctl(mut self, path: &'static str) -> Systemd {
+        self.journalctl = path;
+        self
+    }
+
+    // Prepend `--user` to `args` if we're managing user services.
+    fn args<'a>(&self, args: &[&'a str]) -> Vec<&'a str> {
+        let mut v = Vec::with_capacity(args.len() + 1);
+        if self.scope == SystemdScope::User {
+            v.push("--user");
         }
+        v.extend_from_slice(args);
+        v
+    }
+}
+
+impl ServiceProvider for Systemd {
+    fn available(telemetry: &Telemetry) -> Result<bool> {
+        // Systemd can only manage services if it is PID 1, which usually
+        // isn't the case inside a container.
+        Ok(telemetry.init_system == "systemd")
     }
 
     fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        Box::new(match process::Command::new("systemctl")
-            .args(&["is-active", name])
+        Box::new(match hooks::command(self.systemctl, &self.args(&["is-active", name]))
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is running")
         {
@@ -50,12 +101,13 @@ impl ServiceProvider for Systemd {
             Ok(c) => c,
             Err(e) => return future::err(format!("{}", e.display_chain()).into()),
         };
-        cmd.exec(host, &["systemctl", action, name])
+        let mut args = vec![self.systemctl];
+        args.extend(self.args(&[action, name]));
+        cmd.exec(host, &args)
     }
 
     fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
-        match process::Command::new("systemctl")
-            .args(&["is-enabled", name])
+        match hooks::command(self.systemctl, &self.args(&["is-enabled", name]))
             .status_async2(host.handle())
             .chain_err(|| "Error checking if service is enabled")
         {
@@ -66,8 +118,7 @@ impl ServiceProvider for Systemd {
     }
 
     fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("systemctl")
-            .args(&["enable", name])
+        Box::new(hooks::command(self.systemctl, &self.args(&["enable", name]))
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("systemctl enable <service>")))
             .and_then(|out| {
@@ -80,8 +131,7 @@ impl ServiceProvider for Systemd {
     }
 
     fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
-        Box::new(process::Command::new("systemctl")
-            .args(&["disable", name])
+        Box::new(hooks::command(self.systemctl, &self.args(&["disable", name]))
             .output_async(host.handle())
             .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("systemctl disable <service>")))
             .and_then(|out| {
@@ -92,4 +142,72 @@ impl ServiceProvider for Systemd {
                 }
             }))
     }
+
+    fn requires_root(&self, op: Operation) -> bool {
+        op != Operation::Query && self.scope == SystemdScope::System
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        let cmd = hooks::command(self.journalctl, &self.args(&["--no-pager", "-u", name, "-n", &lines.to_string()]));
+        log_lines(host, cmd, lines, "journalctl")
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        let files = stdout(host, hooks::command(self.systemctl,
+            &self.args(&["list-unit-files", "--type=service", "--no-legend", "--no-pager"])), "systemctl list-unit-files");
+        let units = stdout(host, hooks::command(self.systemctl,
+            &self.args(&["list-units", "--type=service", "--all", "--no-legend", "--no-pager", "--plain"])), "systemctl list-units");
+
+        Box::new(files.join(units).map(|(files, units)| {
+            let mut services = BTreeMap::new();
+
+            // Unit files are listed as "<unit> <state> [<preset>]"
+            for line in files.lines() {
+                let mut cols = line.split_whitespace();
+                if let (Some(name), Some(state)) = (cols.next().and_then(unit_name), cols.next()) {
+                    services.insert(name.clone(), ServiceInfo { name, running: false, enabled: state == "enabled" });
+                }
+            }
+
+            // Loaded units are listed as "<unit> <load> <active> <sub> <description>"
+            for line in units.lines() {
+                let mut cols = line.split_whitespace();
+                if let (Some(name), Some(active)) = (cols.next().and_then(unit_name), cols.nth(1)) {
+                    services.entry(name.clone())
+                        .or_insert(ServiceInfo { name, running: false, enabled: false })
+                        .running = active == "active";
+                }
+            }
+
+            services.into_iter().map(|(_, s)| s).collect()
+        }))
+    }
+}
+
+// The service name of a unit, e.g. "nginx" for "nginx.service". Templates
+// like "getty@.service" can't be started by name, so they are skipped.
+fn unit_name(unit: &str) -> Option<String> {
+    if unit.ends_with(".service") && !unit.ends_with("@.service") {
+        Some(unit[..unit.len() - ".service".len()].into())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_root() {
+        let system = Systemd::with_scope(SystemdScope::System);
+        assert!(system.requires_root(Operation::Action));
+        assert!(system.requires_root(Operation::Enable));
+        assert!(system.requires_root(Operation::Disable));
+        assert!(!system.requires_root(Operation::Query));
+
+        let user = Systemd::with_scope(SystemdScope::User);
+        assert!(!user.requires_root(Operation::Action));
+        assert!(!user.requires_root(Operation::Query));
+    }
 }
@@ -0,0 +1,108 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, factory};
+use error_chain::ChainedError;
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use service::ServiceInfo;
+use std::path::Path;
+use super::{Debian, Redhat, ServiceProvider, describe, init_scripts, tail_log};
+use telemetry::{OsFamily, Telemetry};
+use tokio_process::CommandExt;
+
+/// The classic System V init `Service` provider.
+///
+/// Services are controlled by calling their `/etc/init.d` scripts directly,
+/// and enabled using whichever runlevel tool the host provides
+/// (`update-rc.d` or `chkconfig`).
+pub struct SysVInit {
+    rc: RcTool,
+}
+
+enum RcTool {
+    Chkconfig,
+    UpdateRcD,
+}
+
+impl SysVInit {
+    #[doc(hidden)]
+    pub fn new() -> SysVInit {
+        let rc = if Path::new("/usr/sbin/update-rc.d").exists() {
+            RcTool::UpdateRcD
+        } else {
+            RcTool::Chkconfig
+        };
+
+        SysVInit { rc }
+    }
+}
+
+impl ServiceProvider for SysVInit {
+    fn available(telemetry: &Telemetry) -> Result<bool> {
+        let linux = match telemetry.os.family {
+            OsFamily::Linux(_) => true,
+            _ => false,
+        };
+
+        Ok(linux && Path::new("/etc/init.d").is_dir() &&
+            (Path::new("/usr/sbin/update-rc.d").exists() || Path::new("/usr/sbin/chkconfig").exists()))
+    }
+
+    fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(match hooks::command(&format!("/etc/init.d/{}", name), &["status"])
+            .status_async2(host.handle())
+            .chain_err(|| "Error checking if service is running")
+        {
+            Ok(s) => s.map(|s| s.success())
+                .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("/etc/init.d/<service> status"))),
+            Err(e) => return Box::new(future::err(e)),
+        })
+    }
+
+    fn action(&self, host: &Local, name: &str, action: &str) -> FutureResult<Child, Error> {
+        let cmd = match factory() {
+            Ok(c) => c,
+            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
+        };
+        cmd.exec(host, &[&format!("/etc/init.d/{}", name), action])
+    }
+
+    fn enabled(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        match self.rc {
+            RcTool::Chkconfig => Redhat.enabled(host, name),
+            RcTool::UpdateRcD => Debian.enabled(host, name),
+        }
+    }
+
+    fn enable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        match self.rc {
+            RcTool::Chkconfig => Redhat.enable(host, name),
+            RcTool::UpdateRcD => Debian.enable(host, name),
+        }
+    }
+
+    fn disable(&self, host: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        match self.rc {
+            RcTool::Chkconfig => Redhat.disable(host, name),
+            RcTool::UpdateRcD => Debian.disable(host, name),
+        }
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        tail_log(host, &[format!("/var/log/{}.log", name), format!("/var/log/{}", name)], lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        match init_scripts() {
+            Ok(names) => describe(self, host, names),
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+}
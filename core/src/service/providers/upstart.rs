@@ -0,0 +1,152 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::{hooks, Child, factory};
+use error_chain::ChainedError;
+use errors::*;
+use futures::{future, Future};
+use futures::future::FutureResult;
+use host::Host;
+use host::local::Local;
+use regex::Regex;
+use service::ServiceInfo;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use super::{ServiceProvider, describe, stdout, tail_log};
+use telemetry::Telemetry;
+use tokio_process::CommandExt;
+
+/// The Upstart `Service` provider.
+///
+/// Upstart has no notion of enabling a job. Instead, a job is prevented
+/// from starting at boot by adding the `manual` stanza to its override file
+/// (`/etc/init/<service>.override`), which is what `enable()` and
+/// `disable()` manage.
+pub struct Upstart;
+
+impl ServiceProvider for Upstart {
+    fn available(_: &Telemetry) -> Result<bool> {
+        if !Path::new("/sbin/initctl").exists() {
+            return Ok(false);
+        }
+
+        let output = hooks::command("/sbin/initctl", &["version"])
+            .output()
+            .chain_err(|| "Could not determine provider availability")?;
+
+        Ok(output.status.success() && String::from_utf8_lossy(&output.stdout).contains("upstart"))
+    }
+
+    fn running(&self, host: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        Box::new(hooks::command("/sbin/initctl", &["status", name])
+            .output_async(host.handle())
+            .map_err(|e| Error::with_chain(e, ErrorKind::SystemCommand("initctl status <service>")))
+            .map(|out| {
+                // `initctl status` exits successfully for stopped jobs too,
+                // so we have to inspect the goal/state it reports.
+                out.status.success() && String::from_utf8_lossy(&out.stdout).contains("start/running")
+            }))
+    }
+
+    fn action(&self, host: &Local, name: &str, action: &str) -> FutureResult<Child, Error> {
+        let cmd = match factory() {
+            Ok(c) => c,
+            Err(e) => return future::err(format!("{}", e.display_chain()).into()),
+        };
+        cmd.exec(host, &["/sbin/initctl", action, name])
+    }
+
+    fn enabled(&self, _: &Local, name: &str) -> Box<Future<Item = bool, Error = Error>> {
+        let result = has_manual_stanza(&format!("/etc/init/{}.conf", name))
+            .and_then(|conf| Ok(!conf && !has_manual_stanza(&format!("/etc/init/{}.override", name))?));
+
+        Box::new(match result {
+            Ok(enabled) => future::ok(enabled),
+            Err(e) => future::err(e),
+        })
+    }
+
+    fn enable(&self, _: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        let path = format!("/etc/init/{}.override", name);
+        let result = read_file(&path).and_then(|contents| {
+            let re = manual_regex()?;
+            let contents = re.replace_all(&contents, "");
+
+            if contents.trim().is_empty() {
+                if Path::new(&path).exists() {
+                    fs::remove_file(&path).chain_err(|| "Could not remove Upstart override file")?;
+                }
+            } else {
+                let mut fh = fs::File::create(&path).chain_err(|| "Could not write Upstart override file")?;
+                fh.write_all(contents.as_bytes()).chain_err(|| "Could not write Upstart override file")?;
+            }
+
+            Ok(())
+        });
+
+        Box::new(match result {
+            Ok(_) => future::ok(()),
+            Err(e) => future::err(e),
+        })
+    }
+
+    fn disable(&self, _: &Local, name: &str) -> Box<Future<Item = (), Error = Error>> {
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&format!("/etc/init/{}.override", name))
+            .and_then(|mut fh| fh.write_all(b"manual\n"))
+            .chain_err(|| "Could not write Upstart override file");
+
+        Box::new(match result {
+            Ok(_) => future::ok(()),
+            Err(e) => future::err(e),
+        })
+    }
+
+    fn logs(&self, host: &Local, name: &str, lines: usize) -> Box<Future<Item = Vec<String>, Error = Error>> {
+        tail_log(host, &[format!("/var/log/upstart/{}.log", name)], lines)
+    }
+
+    fn list(&self, host: &Local) -> Box<Future<Item = Vec<ServiceInfo>, Error = Error>> {
+        let host = host.clone();
+
+        // Jobs are listed as "<name> <goal>/<state>[, process <pid>]", and
+        // instances of a job as "<name> (<instance>) ...", which share the
+        // job's name.
+        Box::new(stdout(&host, hooks::command("/sbin/initctl", &["list"]), "initctl list")
+            .and_then(move |out| {
+                let mut names: Vec<String> = out.lines()
+                    .filter_map(|l| l.split_whitespace().next())
+                    .map(|n| n.to_owned())
+                    .collect();
+                names.sort();
+                names.dedup();
+                describe(&Upstart, &host, names)
+            }))
+    }
+}
+
+fn manual_regex() -> Result<Regex> {
+    Regex::new(r"(?m)^\s*manual\s*$\n?").chain_err(|| "Could not create Upstart manual stanza Regex")
+}
+
+fn has_manual_stanza(path: &str) -> Result<bool> {
+    Ok(manual_regex()?.is_match(&read_file(path)?))
+}
+
+// Read a job file, treating a missing file as empty.
+fn read_file(path: &str) -> Result<String> {
+    let mut contents = String::new();
+
+    if Path::new(path).exists() {
+        let mut fh = fs::File::open(path).chain_err(|| format!("Could not open {}", path))?;
+        fh.read_to_string(&mut contents).chain_err(|| format!("Could not read {}", path))?;
+    }
+
+    Ok(contents)
+}
@@ -0,0 +1,287 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::hooks;
+use errors::*;
+use hostname::get_hostname;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use telemetry::{FsMount, User};
+use users::{get_group_by_gid, get_user_by_uid, get_current_uid};
+use users::os::unix::UserExt;
+
+pub fn hostname() -> Result<String> {
+    match get_hostname() {
+        Some(name) => Ok(name),
+        None => Err("Could not determine hostname".into()),
+    }
+}
+
+// Mounted filesystems, from the output of `df -P`, which has the same
+// columns on every platform.
+pub fn fs() -> Result<Vec<FsMount>> {
+    // Force `df`'s own messages and number formats to be untranslated
+    let out = hooks::command("df", &["-P", "-k"])
+        .env("LC_ALL", "C")
+        .output()
+        .chain_err(|| ErrorKind::SystemCommand("df"))?;
+    let out = String::from_utf8(out.stdout).chain_err(|| ErrorKind::SystemCommandOutput("df"))?;
+
+    parse_df(&out, &mount_info()?)
+}
+
+// Parse the output of `df -P -k`, whose sizes are in 1024 byte blocks, into
+// mounts with sizes in bytes.
+//
+// Each line is formatted as
+// "<dev> <blocks> <used> <available> <capacity>% <mount>", after a header
+// line that may be translated. Both the device and the mountpoint may
+// contain spaces. A device name too long for its column is printed on a
+// line of its own, with the rest of its fields on the next line.
+fn parse_df(out: &str, mounts: &HashMap<String, MountInfo>) -> Result<Vec<FsMount>> {
+    let regex = Regex::new(r"^(?P<fs>.+?)\s+(?P<size>[0-9]+)\s+(?P<used>[0-9]+)\s+(?P<available>[0-9]+)\s+(?:(?P<capacity>[0-9]{1,3})%|-)\s+(?P<mount>/.*)$").unwrap();
+    let mut fs = vec!();
+    let mut wrapped: Option<String> = None;
+
+    for line in out.lines().skip(1) {
+        let line = match wrapped.take() {
+            Some(dev) => format!("{} {}", dev, line.trim()),
+            None => line.trim().to_owned(),
+        };
+
+        let cap = match regex.captures(&line) {
+            Some(cap) => cap,
+            None => {
+                if line.split_whitespace().count() == 1 {
+                    wrapped = Some(line);
+                }
+                continue;
+            },
+        };
+
+        let mountpoint = cap["mount"].to_string();
+        let info = mounts.get(&mountpoint).cloned().unwrap_or_default();
+        let blocks = |name: &str, desc: &str| -> Result<u64> {
+            let n = cap[name].parse::<u64>()
+                .chain_err(|| format!("could not discern {} from df output", desc))?;
+            Ok(n * 1024)
+        };
+
+        fs.push(FsMount {
+            filesystem: cap["fs"].to_string(),
+            fs_type: info.fs_type,
+            options: info.options,
+            mountpoint: mountpoint,
+            size: blocks("size", "size of mount")?,
+            used: blocks("used", "used space")?,
+            available: blocks("available", "available space")?,
+            // Filesystems without a size report their capacity as "-"
+            capacity: match cap.name("capacity") {
+                Some(c) => c.as_str().parse::<f32>()
+                    .chain_err(|| format!("could not discern {} from df output", "mount capacity"))? / 100f32,
+                None => 0f32,
+            },
+        });
+    }
+
+    Ok(fs)
+}
+
+// Filesystem type and mount options for a single mountpoint.
+#[derive(Clone, Default)]
+struct MountInfo {
+    fs_type: String,
+    options: Vec<String>,
+}
+
+// Map each mountpoint to its filesystem type and mount options. On Linux
+// these come from /proc/mounts, where each line is formatted as
+// "<dev> <mount> <type> <opts> <dump> <pass>". Elsewhere we fall back to the
+// output of `mount`, which BSD and macOS format as
+// "<dev> on <mount> (<type>, <opts>)".
+fn mount_info() -> Result<HashMap<String, MountInfo>> {
+    let mut mounts = String::new();
+    if File::open("/proc/mounts").and_then(|mut f| f.read_to_string(&mut mounts)).is_ok() {
+        return Ok(mounts.lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() < 4 {
+                    return None;
+                }
+
+                // Whitespace in the mountpoint is octal escaped
+                let mountpoint = fields[1].replace("\\040", " ").replace("\\011", "\t");
+                Some((mountpoint, MountInfo {
+                    fs_type: fields[2].to_string(),
+                    options: split_options(fields[3]),
+                }))
+            })
+            .collect());
+    }
+
+    let out = hooks::command("mount", &[])
+        .output()
+        .chain_err(|| ErrorKind::SystemCommand("mount"))?;
+    let out = String::from_utf8(out.stdout).chain_err(|| ErrorKind::SystemCommandOutput("mount"))?;
+
+    let regex = Regex::new(r"(?m)^.+? on (?P<mount>/.*?) (?:type (?P<type>[^\s]+) \((?P<opts>[^)]*)\)|\((?P<bsdtype>[^,)]+),?(?P<bsdopts>[^)]*)\))").unwrap();
+
+    Ok(regex.captures_iter(&out)
+        .filter_map(|cap| {
+            let (fs_type, options) = match cap.name("type") {
+                Some(t) => (t, cap.name("opts")),
+                None => (cap.name("bsdtype")?, cap.name("bsdopts")),
+            };
+
+            Some((cap["mount"].to_string(), MountInfo {
+                fs_type: fs_type.as_str().to_string(),
+                options: options.map(|o| split_options(o.as_str())).unwrap_or_default(),
+            }))
+        })
+        .collect())
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    options.split(',')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+        .map(|o| o.to_string())
+        .collect()
+}
+
+// The nameservers listed in /etc/resolv.conf. IPv6 addresses may carry a
+// zone index (e.g. "fe80::1%eth0"), which `IpAddr` can't represent, so it is
+// dropped.
+pub fn dns_servers() -> Vec<IpAddr> {
+    let mut conf = String::new();
+    if File::open("/etc/resolv.conf").and_then(|mut f| f.read_to_string(&mut conf)).is_err() {
+        return Vec::new();
+    }
+
+    conf.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("nameserver") => parts.next(),
+                _ => None,
+            }
+        })
+        .filter_map(|addr| addr.split('%').next().unwrap().parse().ok())
+        .collect()
+}
+
+// The distinct users with a login session, from the output of `who`, where
+// the first column of each line is the user's name.
+pub fn logged_in_users() -> Result<Vec<String>> {
+    let out = hooks::command("who", &[])
+        .output()
+        .chain_err(|| ErrorKind::SystemCommand("who"))?;
+    let out = String::from_utf8(out.stdout).chain_err(|| ErrorKind::SystemCommandOutput("who"))?;
+
+    let mut users: Vec<String> = out.lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|user| user.to_owned())
+        .collect();
+    users.sort();
+    users.dedup();
+    Ok(users)
+}
+
+pub fn user() -> Result<User> {
+    let user = match get_user_by_uid(get_current_uid()) {
+        Some(u) => u,
+        None => return Err("Could not resolve current user".into()),
+    };
+
+    let group = match get_group_by_gid(user.primary_group_id()) {
+        Some(g) => g,
+        None => return Err("Could not resolve current group".into()),
+    };
+
+    Ok(User {
+        user: user.name().into(),
+        uid: user.uid(),
+        group: group.name().into(),
+        gid: group.gid(),
+        home_dir: user.home_dir().into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    const LINUX_DF: &'static str = "\
+Filesystem     1024-blocks    Used Available Capacity Mounted on
+/dev/sda1         41152736 2064392  37174924       6% /
+tmpfs               817860       0    817860       0% /dev/shm
+";
+
+    #[test]
+    fn test_parse_df_sizes_in_bytes() {
+        let mut mounts = HashMap::new();
+        mounts.insert("/".to_string(), MountInfo {
+            fs_type: "ext4".into(),
+            options: vec!["rw".into(), "relatime".into()],
+        });
+
+        let fs = parse_df(LINUX_DF, &mounts).unwrap();
+
+        assert_eq!(fs.len(), 2);
+        assert_eq!(fs[0].filesystem, "/dev/sda1");
+        assert_eq!(fs[0].fs_type, "ext4");
+        assert_eq!(fs[0].mountpoint, "/");
+        assert_eq!(fs[0].size, 41152736 * 1024);
+        assert_eq!(fs[0].used, 2064392 * 1024);
+        assert_eq!(fs[0].available, 37174924 * 1024);
+        assert_eq!(fs[0].capacity, 0.06);
+        assert_eq!(fs[1].mountpoint, "/dev/shm");
+        assert_eq!(fs[1].size, 817860 * 1024);
+        assert_eq!(fs[1].capacity, 0.0);
+    }
+
+    #[test]
+    fn test_parse_df_wrapped_device() {
+        let out = "\
+Filesystem                                   1024-blocks    Used Available Capacity Mounted on
+/dev/mapper/vg_workstation_with_a_long_name-lv_root
+                                                41152736 2064392  37174924       6% /
+/dev/sda1                                         508580  102400    406180      21% /boot
+";
+        let fs = parse_df(out, &HashMap::new()).unwrap();
+
+        assert_eq!(fs.len(), 2);
+        assert_eq!(fs[0].filesystem, "/dev/mapper/vg_workstation_with_a_long_name-lv_root");
+        assert_eq!(fs[0].mountpoint, "/");
+        assert_eq!(fs[0].size, 41152736 * 1024);
+        assert_eq!(fs[1].filesystem, "/dev/sda1");
+        assert_eq!(fs[1].mountpoint, "/boot");
+        assert_eq!(fs[1].capacity, 0.21);
+    }
+
+    #[test]
+    fn test_parse_df_localized_header() {
+        let out = "\
+Dateisystem    1024-Blöcke Benutzt Verfügbar Kapazität Eingehängt auf
+/dev/sda1         41152736 2064392  37174924        6% /
+map auto_home            0       0         0        - /System/Volumes/Data/home
+/dev/sdb1          1000000  500000    500000       50% /mnt/Meine Daten
+";
+        let fs = parse_df(out, &HashMap::new()).unwrap();
+
+        assert_eq!(fs.len(), 3);
+        assert_eq!(fs[0].filesystem, "/dev/sda1");
+        assert_eq!(fs[0].available, 37174924 * 1024);
+        assert_eq!(fs[1].filesystem, "map auto_home");
+        assert_eq!(fs[1].capacity, 0.0);
+        assert_eq!(fs[2].mountpoint, "/mnt/Meine Daten");
+        assert_eq!(fs[2].capacity, 0.5);
+    }
+}
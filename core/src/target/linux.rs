@@ -0,0 +1,248 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use command::hooks;
+use errors::*;
+use regex::Regex;
+use std::{fs, str};
+use std::collections::HashSet;
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Eq, PartialEq)]
+pub enum LinuxFlavour {
+    Centos,
+    Debian,
+    Fedora,
+    Redhat,
+    Ubuntu,
+    Nixos,
+}
+
+pub fn fingerprint_os() -> Option<LinuxFlavour> {
+    // @todo Cache this result
+
+    // CentOS
+    if let Ok(_) = fs::metadata("/etc/centos-release") {
+        Some(LinuxFlavour::Centos)
+    }
+    // Ubuntu
+    else if let Ok(_) = fs::metadata("/etc/lsb-release") {
+        Some(LinuxFlavour::Ubuntu)
+    }
+    // Debian
+    else if let Ok(_) = fs::metadata("/etc/debian_version") {
+        Some(LinuxFlavour::Debian)
+    }
+    // Fedora
+    else if let Ok(_) = fs::metadata("/etc/fedora-release") {
+        Some(LinuxFlavour::Fedora)
+    }
+    // RedHat
+    else if let Ok(_) = fs::metadata("/etc/redhat-release") {
+        Some(LinuxFlavour::Redhat)
+    }
+    // NixOS
+    else if let Ok(_) = fs::metadata("/etc/nixos/configuration.nix") {
+        Some(LinuxFlavour::Nixos)
+    } else {
+        None
+    }
+}
+
+pub fn cpu_vendor() -> Result<String> {
+    get_cpu_item("vendor_id")
+}
+
+pub fn cpu_brand_string() -> Result<String> {
+    get_cpu_item("model name")
+}
+
+pub fn cpu_cores() -> Result<u32> {
+    Ok(get_cpu_item("cpu cores")?
+        .parse::<u32>()
+        .chain_err(|| ErrorKind::InvalidTelemetryKey {
+            cmd: "/proc/cpuinfo",
+            key: "cpu cores".into()
+        })?)
+}
+
+pub fn cpu_physical_cores() -> Result<u32> {
+    let cpuinfo = read_cpuinfo()?;
+    let regex = Regex::new(r"(?m)^(physical id|core id)\s+: (.+)$").unwrap();
+
+    // Each logical CPU is listed as a separate block, so count the unique
+    // (physical id, core id) pairs to find the number of physical cores.
+    let mut cores = HashSet::new();
+    for block in cpuinfo.split("\n\n") {
+        let mut physical_id = None;
+        let mut core_id = None;
+        for cap in regex.captures_iter(block) {
+            match cap.get(1).unwrap().as_str() {
+                "physical id" => physical_id = Some(cap.get(2).unwrap().as_str()),
+                _ => core_id = Some(cap.get(2).unwrap().as_str()),
+            }
+        }
+
+        if let (Some(p), Some(c)) = (physical_id, core_id) {
+            cores.insert((p, c));
+        }
+    }
+
+    if cores.is_empty() {
+        cpu_cores()
+    } else {
+        Ok(cores.len() as u32)
+    }
+}
+
+pub fn cpu_threads() -> Result<u32> {
+    let cpuinfo = read_cpuinfo()?;
+    let regex = Regex::new(r"(?m)^processor\s+:").unwrap();
+    Ok(regex.find_iter(&cpuinfo).count() as u32)
+}
+
+pub fn cpu_mhz() -> Option<u32> {
+    get_cpu_item("cpu MHz").ok()
+        .and_then(|mhz| mhz.parse::<f32>().ok())
+        .map(|mhz| mhz as u32)
+}
+
+fn read_cpuinfo() -> Result<String> {
+    // @todo Cache file content
+    let mut fh = fs::File::open("/proc/cpuinfo").chain_err(|| ErrorKind::SystemFile("/proc/cpuinfo"))?;
+    let mut cpuinfo = String::new();
+    fh.read_to_string(&mut cpuinfo).chain_err(|| ErrorKind::SystemFileOutput("/proc/cpuinfo"))?;
+    Ok(cpuinfo)
+}
+
+fn get_cpu_item(item: &str) -> Result<String> {
+    let cpuinfo = read_cpuinfo()?;
+
+    let pattern = format!(r"(?m)^{}\s+: (.+)$", item);
+    let regex = Regex::new(&pattern).unwrap();
+    let capture = regex.captures(&cpuinfo);
+
+    if let Some(cap) = capture {
+        Ok(cap.get(1).unwrap().as_str().to_string())
+    } else {
+        Err(ErrorKind::InvalidTelemetryKey { cmd: "/proc/cpuinfo", key: item.into() }.into())
+    }
+}
+
+pub fn machine_id() -> Option<String> {
+    for path in &["/etc/machine-id", "/var/lib/dbus/machine-id"] {
+        let mut id = String::new();
+        if let Ok(mut fh) = fs::File::open(path) {
+            if fh.read_to_string(&mut id).is_ok() && !id.trim().is_empty() {
+                return Some(id.trim().into());
+            }
+        }
+    }
+
+    None
+}
+
+pub fn memory() -> Result<u64> {
+    let output = hooks::command("free", &["-b"]).output().chain_err(|| ErrorKind::SystemCommand("free"))?;
+    let regex = Regex::new(r"(?m)^Mem:\s+([0-9]+)").chain_err(|| "could not create new Regex instance")?;
+    let capture = regex.captures(str::from_utf8(&output.stdout).chain_err(|| ErrorKind::SystemCommandOutput("free"))?.trim());
+
+    if let Some(cap) = capture {
+        Ok(cap.get(1).unwrap().as_str().parse::<u64>().chain_err(|| ErrorKind::SystemFileOutput("/etc/redhat-release"))?)
+    } else {
+        Err(ErrorKind::SystemCommandOutput("free").into())
+    }
+}
+
+// The time the host booted, in seconds since the Unix epoch, from the "btime"
+// line in /proc/stat.
+pub fn last_boot() -> Result<u64> {
+    let mut stat = String::new();
+    fs::File::open("/proc/stat")
+        .and_then(|mut fh| fh.read_to_string(&mut stat))
+        .chain_err(|| ErrorKind::SystemFile("/proc/stat"))?;
+
+    stat.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("btime") => parts.next(),
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or(ErrorKind::SystemFileOutput("/proc/stat"))?
+        .parse()
+        .chain_err(|| ErrorKind::SystemFileOutput("/proc/stat"))
+}
+
+// The name of the init system running as PID 1, e.g. "systemd". In a
+// container, PID 1 is often the container's entrypoint (e.g. "bash"), in
+// which case that is returned instead.
+pub fn init_system() -> String {
+    let mut comm = String::new();
+    if fs::File::open("/proc/1/comm").and_then(|mut fh| fh.read_to_string(&mut comm)).is_err() {
+        return "unknown".into();
+    }
+
+    match comm.trim() {
+        "" => "unknown".into(),
+        // A plain "init" could be either SysVinit or Upstart
+        "init" => if Path::new("/sbin/initctl").exists() && Path::new("/etc/init").is_dir() {
+            "upstart".into()
+        } else {
+            "sysvinit".into()
+        },
+        name => name.into(),
+    }
+}
+
+// The virtualization or container technology the host is running in, e.g.
+// "kvm" or "docker", or `None` if it is running on bare metal.
+pub fn virtualization() -> Option<String> {
+    if let Ok(out) = hooks::command("systemd-detect-virt", &[]).output() {
+        // `systemd-detect-virt` prints "none" and exits with an error if no
+        // virtualization is detected
+        let virt = String::from_utf8_lossy(&out.stdout).trim().to_owned();
+        return if out.status.success() && !virt.is_empty() && virt != "none" {
+            Some(virt)
+        } else {
+            None
+        };
+    }
+
+    // Without systemd, we can only detect containers
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker".into());
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman".into());
+    }
+
+    let mut cgroup = String::new();
+    if fs::File::open("/proc/1/cgroup").and_then(|mut fh| fh.read_to_string(&mut cgroup)).is_ok() {
+        for &(pattern, name) in &[("docker", "docker"), ("kubepods", "kubernetes"), ("lxc", "lxc")] {
+            if cgroup.contains(pattern) {
+                return Some(name.into());
+            }
+        }
+    }
+
+    None
+}
+
+// The default gateway, from the output of `ip route show default`, which is
+// formatted as "default via <addr> dev <iface> ...".
+pub fn default_gateway() -> Option<IpAddr> {
+    let out = hooks::command("ip", &["route", "show", "default"])
+        .output()
+        .ok()?;
+    let regex = Regex::new(r"(?m)^default via ([^\s]+)").unwrap();
+    regex.captures(&String::from_utf8_lossy(&out.stdout))
+        .and_then(|cap| cap[1].parse().ok())
+}
@@ -0,0 +1,140 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! End-of-life dates for supported operating systems.
+//!
+//! The dates in this module come from an embedded table, so they are only as
+//! current as the version of this crate you are using. Releases that are
+//! missing from the table are reported as unknown rather than supported.
+
+use super::{Os, OsPlatform};
+
+/// A calendar date, used to compare against an OS's end-of-life date.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+// (platform, major version, minor version, end-of-life date)
+//
+// A minor version of `None` matches every minor release of that major
+// version. Where a vendor offers extended support (e.g. Debian LTS), the end
+// of that support is used.
+static EOL_TABLE: &'static [(OsPlatform, u32, Option<u32>, Date)] = &[
+    (OsPlatform::Centos, 5, None, Date { year: 2017, month: 3, day: 31 }),
+    (OsPlatform::Centos, 6, None, Date { year: 2020, month: 11, day: 30 }),
+    (OsPlatform::Centos, 7, None, Date { year: 2024, month: 6, day: 30 }),
+    (OsPlatform::Debian, 7, None, Date { year: 2018, month: 5, day: 31 }),
+    (OsPlatform::Debian, 8, None, Date { year: 2020, month: 6, day: 30 }),
+    (OsPlatform::Debian, 9, None, Date { year: 2022, month: 6, day: 30 }),
+    (OsPlatform::Fedora, 24, None, Date { year: 2017, month: 8, day: 8 }),
+    (OsPlatform::Fedora, 25, None, Date { year: 2017, month: 12, day: 12 }),
+    (OsPlatform::Fedora, 26, None, Date { year: 2018, month: 5, day: 29 }),
+    (OsPlatform::Fedora, 27, None, Date { year: 2018, month: 11, day: 30 }),
+    (OsPlatform::Freebsd, 10, Some(3), Date { year: 2018, month: 4, day: 30 }),
+    (OsPlatform::Freebsd, 10, Some(4), Date { year: 2018, month: 10, day: 31 }),
+    (OsPlatform::Freebsd, 11, Some(0), Date { year: 2017, month: 11, day: 30 }),
+    (OsPlatform::Freebsd, 11, Some(1), Date { year: 2018, month: 9, day: 30 }),
+    (OsPlatform::Nixos, 16, Some(9), Date { year: 2017, month: 4, day: 30 }),
+    (OsPlatform::Nixos, 17, Some(3), Date { year: 2017, month: 10, day: 31 }),
+    (OsPlatform::Ubuntu, 12, Some(4), Date { year: 2017, month: 4, day: 28 }),
+    (OsPlatform::Ubuntu, 14, Some(4), Date { year: 2019, month: 4, day: 30 }),
+    (OsPlatform::Ubuntu, 16, Some(4), Date { year: 2021, month: 4, day: 30 }),
+    (OsPlatform::Ubuntu, 16, Some(10), Date { year: 2017, month: 7, day: 20 }),
+    (OsPlatform::Ubuntu, 17, Some(4), Date { year: 2018, month: 1, day: 13 }),
+    (OsPlatform::Ubuntu, 17, Some(10), Date { year: 2018, month: 7, day: 19 }),
+];
+
+impl Date {
+    pub fn new(year: u16, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+}
+
+/// Look up the end-of-life date for a given OS release.
+///
+/// Returns `None` if the release is not in the table.
+pub fn lookup(platform: &OsPlatform, version_maj: u32, version_min: u32) -> Option<Date> {
+    EOL_TABLE.iter()
+        .find(|&&(ref p, maj, min, _)| {
+            p == platform && maj == version_maj && min.map_or(true, |m| m == version_min)
+        })
+        .map(|&(_, _, _, date)| date)
+}
+
+impl Os {
+    /// The date this OS release stops receiving support, if known.
+    pub fn eol(&self) -> Option<Date> {
+        lookup(&self.platform, self.version_maj, self.version_min)
+    }
+
+    /// Whether this OS release is unsupported as of `now`.
+    ///
+    /// Returns `None` if the end-of-life date for this release is not known.
+    pub fn is_eol(&self, now: Date) -> Option<bool> {
+        self.eol().map(|date| now > date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telemetry::{ArchDetail, Endian, LinuxDistro, OsFamily};
+
+    fn os(platform: OsPlatform, version_maj: u32, version_min: u32) -> Os {
+        Os {
+            arch: "x86_64".into(),
+            arch_detail: ArchDetail { bits: 64, endian: Endian::Little, features: Vec::new() },
+            family: OsFamily::Linux(LinuxDistro::Standalone),
+            platform: platform,
+            version_str: format!("{}.{}", version_maj, version_min),
+            version_maj: version_maj,
+            version_min: version_min,
+            version_patch: 0,
+            kernel_version: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        // (platform, major, minor, expected end-of-life date)
+        let cases = [
+            // A `None` minor version matches every minor release
+            (OsPlatform::Centos, 7, 0, Some(Date::new(2024, 6, 30))),
+            (OsPlatform::Centos, 7, 4, Some(Date::new(2024, 6, 30))),
+            (OsPlatform::Debian, 9, 3, Some(Date::new(2022, 6, 30))),
+            // Otherwise the minor version must match
+            (OsPlatform::Ubuntu, 16, 4, Some(Date::new(2021, 4, 30))),
+            (OsPlatform::Ubuntu, 16, 10, Some(Date::new(2017, 7, 20))),
+            (OsPlatform::Ubuntu, 16, 5, None),
+            (OsPlatform::Freebsd, 11, 1, Some(Date::new(2018, 9, 30))),
+            // Releases that aren't in the table are unknown
+            (OsPlatform::Fedora, 40, 0, None),
+            (OsPlatform::Macos, 10, 13, None),
+        ];
+
+        for &(ref platform, maj, min, expected) in cases.iter() {
+            assert_eq!(lookup(platform, maj, min), expected, "{:?} {}.{}", platform, maj, min);
+        }
+    }
+
+    #[test]
+    fn test_is_eol() {
+        let xenial = os(OsPlatform::Ubuntu, 16, 4);
+        assert_eq!(xenial.is_eol(Date::new(2021, 4, 29)), Some(false));
+        // Still supported on the day itself
+        assert_eq!(xenial.is_eol(Date::new(2021, 4, 30)), Some(false));
+        assert_eq!(xenial.is_eol(Date::new(2021, 5, 1)), Some(true));
+        assert_eq!(xenial.is_eol(Date::new(2022, 1, 1)), Some(true));
+    }
+
+    #[test]
+    fn test_is_eol_unknown() {
+        assert_eq!(os(OsPlatform::Macos, 10, 13).is_eol(Date::new(2017, 1, 1)), None);
+    }
+}
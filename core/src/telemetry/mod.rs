@@ -9,32 +9,67 @@
 //! Telemetry is retrieved automatically when you create a new `Host`, which is
 //! nice of it. Call [`Host.telemetry()`](../host/trait.Host.html#tymethod.telemetry)
 //! to access it.
+//!
+//! Loading telemetry fails with `ErrorKind::TelemetryTimeout` if it takes
+//! longer than [`LOAD_TIMEOUT_SECS`](constant.LOAD_TIMEOUT_SECS.html), or
+//! the timeout given to [`set_load_timeout()`](fn.set_load_timeout.html).
+//!
+//! To store telemetry or share it with other systems, use
+//! [`Telemetry::to_json()`](struct.Telemetry.html#method.to_json), which
+//! follows a stable, versioned schema.
 
+pub mod eol;
 mod providers;
+mod schema;
 #[doc(hidden)] pub mod serializable;
 
+use command::hooks;
 use errors::*;
 use futures::{future, Future};
+use futures::future::Either;
+use futures::sync::oneshot;
 use host::Host;
 use host::local::Local;
 use message::{FromMessage, IntoMessage, InMessage};
 use pnet::datalink::NetworkInterface;
 use request::Executable;
 use self::providers::factory;
+pub use self::schema::SCHEMA_VERSION;
 use serde_json as json;
+use std::net::IpAddr;
+use std::panic;
 use std::path::PathBuf;
-use tokio_core::reactor::Handle;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
 use tokio_proto::streaming::Message;
 
 /// Top level structure that contains static information about a `Host`.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Telemetry {
     /// Information on the CPU
     pub cpu: Cpu,
+    /// Address of the default gateway, if there is one
+    pub default_gateway: Option<IpAddr>,
+    /// Addresses of the DNS servers the host resolves names with
+    pub dns_servers: Vec<IpAddr>,
     /// Information on the filesystem
     pub fs: Vec<FsMount>,
     /// Host's FQDN
     pub hostname: String,
+    /// Name of the init system, e.g. "systemd", or "unknown" if it could not
+    /// be determined. In a container this may be the container's entrypoint.
+    pub init_system: String,
+    /// Time the host last booted, in seconds since the Unix epoch, if it
+    /// could be determined
+    pub last_boot: Option<u64>,
+    /// Names of the users that currently have a login session, e.g. over SSH
+    /// or at the console, if they could be determined
+    pub logged_in_users: Option<Vec<String>>,
+    /// Stable unique identifier for the machine, if available
+    pub machine_id: Option<String>,
     /// Amount of RAM, in bytes
     pub memory: u64,
     /// Information on network interfaces
@@ -43,10 +78,13 @@ pub struct Telemetry {
     pub os: Os,
     /// Information on the current user
     pub user: User,
+    /// Virtualization or container technology the host is running in, e.g.
+    /// "kvm" or "docker", or `None` if running on bare metal or unknown
+    pub virtualization: Option<String>,
 }
 
 /// Information about the `Host`s CPU.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cpu {
     /// Processor vendor, e.g. "GenuineIntel"
     pub vendor: String,
@@ -54,30 +92,43 @@ pub struct Cpu {
     pub brand_string: String,
     /// Number of cores in the processor
     pub cores: u32,
+    /// Total number of physical cores across all processors
+    pub physical_cores: u32,
+    /// Number of logical CPUs (hardware threads)
+    pub threads: u32,
+    /// Processor frequency in MHz, if known
+    pub mhz: Option<u32>,
 }
 
 /// Information about a specific filesystem mount.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FsMount {
     /// The device path, e.g. /dev/sd0s1
     pub filesystem: String,
+    /// Filesystem type, e.g. "ext4" or "tmpfs", or an empty string if it
+    /// could not be determined
+    pub fs_type: String,
+    /// Mount options, e.g. ["rw", "noexec", "relatime"]
+    pub options: Vec<String>,
     /// Path to where the device is mounted, e.g. /boot
     pub mountpoint: String,
-    /// Capacity of device in Kb
+    /// Capacity of device in bytes
     pub size: u64,
-    /// Amount used in Kb
+    /// Amount used in bytes
     pub used: u64,
-    /// Remaining capacity available in Kb
+    /// Remaining capacity available in bytes
     pub available: u64,
-    /// Percentage used as a decimal
+    /// Fraction of the device that is used, from 0.0 to 1.0
     pub capacity: f32,
 }
 
 /// Information about the `Host`s OS.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Os {
     /// OS architecture, e.g. "x86_64"
     pub arch: String,
+    /// Finer grained information about the architecture
+    pub arch_detail: ArchDetail,
     /// OS family
     pub family: OsFamily,
     /// OS name
@@ -90,10 +141,41 @@ pub struct Os {
     pub version_min: u32,
     /// Patch version number, e.g. "0"
     pub version_patch: u32,
+    /// Release of the running kernel, as reported by `uname -r`, e.g.
+    /// "4.13.0-16-generic".
+    pub kernel_version: String,
+}
+
+/// Details of a `Host`s architecture.
+///
+/// Like `Os::arch`, `bits` and `endian` describe the build of the API (or
+/// for remote hosts, the agent) that loaded the telemetry, which normally
+/// matches the host.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchDetail {
+    /// Pointer width in bits, e.g. 64
+    pub bits: u8,
+    /// Byte order
+    pub endian: Endian,
+    /// Features supported by the CPU, in lower case and as named by the OS,
+    /// e.g. ["avx2", "sse4_2"] on Linux or ["avx2", "sse4.2"] on macOS.
+    /// Empty if they could not be determined.
+    pub features: Vec<String>,
+}
+
+/// Byte order
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Endian {
+    Big,
+    Little,
 }
 
 /// Operating system family
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+///
+/// New families may be added in future releases, so downstream `match`es
+/// must include a wildcard arm.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum OsFamily {
     Bsd,
     Darwin,
@@ -101,7 +183,12 @@ pub enum OsFamily {
 }
 
 /// Operating system name
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+///
+/// New platforms may be added in future releases, so downstream `match`es
+/// must include a wildcard arm. Use [`as_str()`](#method.as_str) to handle
+/// platforms by name instead.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum OsPlatform {
     Centos,
     Debian,
@@ -113,7 +200,11 @@ pub enum OsPlatform {
 }
 
 /// Linux distribution name
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+///
+/// New distributions may be added in future releases, so downstream
+/// `match`es must include a wildcard arm.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum LinuxDistro {
     Debian,
     RHEL,
@@ -121,7 +212,7 @@ pub enum LinuxDistro {
 }
 
 /// Information on the current user
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct User {
     pub user: String,
     pub uid: u32,
@@ -139,6 +230,128 @@ impl Telemetry {
         Box::new(host.request(TelemetryLoad)
             .chain_err(|| ErrorKind::Request { endpoint: "Telemetry", func: "load" }))
     }
+
+    /// Whether any users were logged in to the host when this telemetry was
+    /// loaded. Use this to avoid disruptive maintenance, like rebooting,
+    /// while people are using the host.
+    ///
+    /// Note that if you connect to the host over SSH to run the agent, your
+    /// own session counts as active. If the logged in users could not be
+    /// determined, this errs on the side of caution and returns `true`.
+    pub fn has_active_sessions(&self) -> bool {
+        self.logged_in_users.as_ref().map(|u| !u.is_empty()).unwrap_or(true)
+    }
+}
+
+/// How long loading telemetry may take by default, in seconds, before it
+/// fails with `ErrorKind::TelemetryTimeout`.
+pub const LOAD_TIMEOUT_SECS: u64 = 60;
+
+lazy_static! {
+    static ref LOAD_TIMEOUT: RwLock<Duration> = RwLock::new(Duration::from_secs(LOAD_TIMEOUT_SECS));
+    static ref LOADING: Mutex<Option<Loading>> = Mutex::new(None);
+}
+
+/// Set how long loading telemetry may take before it fails with
+/// `ErrorKind::TelemetryTimeout`, instead of
+/// [`LOAD_TIMEOUT_SECS`](constant.LOAD_TIMEOUT_SECS.html).
+///
+/// This only applies to telemetry loaded by this process. A remote host
+/// loads its own telemetry, so set `telemetry_timeout_secs` in the agent's
+/// config instead.
+pub fn set_load_timeout(timeout: Duration) {
+    *LOAD_TIMEOUT.write().unwrap_or_else(|e| e.into_inner()) = timeout;
+}
+
+// Load telemetry for the local machine, blocking until it's done.
+#[doc(hidden)]
+pub fn load_local() -> Result<Telemetry> {
+    factory()?.load().wait()
+}
+
+// Load telemetry for the local machine on its own thread, so that a hung
+// probe can't block the reactor, and give up after the load timeout.
+// There's no way to interrupt a hung probe, so its thread is left behind.
+// Loads that start while it's running wait for it rather than starting
+// another, so that a hung probe only ever ties up one thread.
+#[doc(hidden)]
+pub fn load_local_async(handle: &Handle) -> Box<Future<Item = Telemetry, Error = Error>> {
+    let duration = *LOAD_TIMEOUT.read().unwrap_or_else(|e| e.into_inner());
+    let timeout = match Timeout::new(duration, handle) {
+        Ok(t) => t,
+        Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create timeout"))),
+    };
+
+    let (tx, rx) = oneshot::channel();
+    let last_command = {
+        let mut loading = LOADING.lock().unwrap_or_else(|e| e.into_inner());
+        match *loading {
+            Some(ref mut l) => {
+                l.waiters.push(tx);
+                l.last_command.clone()
+            },
+            None => {
+                // Most probes are system commands, so the last one started
+                // is the likeliest to be stuck if the load times out
+                let last_command = Arc::new(Mutex::new(None));
+                let watcher = last_command.clone();
+                *loading = Some(Loading { waiters: vec![tx], last_command: last_command.clone() });
+
+                thread::spawn(move || {
+                    hooks::watch_thread(watcher);
+                    let result = panic::catch_unwind(load_local)
+                        .unwrap_or_else(|_| Err("Telemetry thread panicked".into()));
+                    finish_loading(result);
+                });
+
+                last_command
+            },
+        }
+    };
+
+    let load = rx.then(|r| match r {
+        Ok(t) => t,
+        Err(_) => Err("Telemetry thread exited without a result".into()),
+    });
+
+    Box::new(load.select2(timeout).then(move |r| match r {
+        Ok(Either::A((t, _))) => Ok(t),
+        Err(Either::A((e, _))) => Err(e),
+        Ok(Either::B(_)) => {
+            let last = last_command.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            Err(ErrorKind::TelemetryTimeout(duration, last).into())
+        },
+        Err(Either::B((e, _))) => Err(Error::with_chain(e, "Telemetry timeout failed")),
+    }))
+}
+
+// A telemetry load that is running on its own thread.
+struct Loading {
+    // Everyone waiting for the load, including any that have timed out
+    waiters: Vec<oneshot::Sender<Result<Telemetry>>>,
+    last_command: Arc<Mutex<Option<String>>>,
+}
+
+// Send the result of the running load to everyone waiting for it, so that
+// the next load starts afresh.
+fn finish_loading(result: Result<Telemetry>) {
+    let mut waiters = LOADING.lock().unwrap_or_else(|e| e.into_inner())
+        .take()
+        .map(|l| l.waiters)
+        .unwrap_or_default();
+
+    // Errors can't be cloned, so only one waiter gets the original. A
+    // receiver only goes away if its load was abandoned.
+    let original = waiters.pop();
+    for tx in waiters {
+        let _ = tx.send(match result {
+            Ok(ref t) => Ok(t.clone()),
+            Err(ref e) => Err(e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ").into()),
+        });
+    }
+    if let Some(tx) = original {
+        let _ = tx.send(result);
+    }
 }
 
 impl FromMessage for Telemetry {
@@ -161,17 +374,168 @@ impl Executable for TelemetryLoad {
     type Response = Telemetry;
     type Future = Box<Future<Item = Self::Response, Error = Error>>;
 
-    fn exec(self, _: &Local) -> Self::Future {
-        match factory() {
-            Ok(p) => p.load(),
-            Err(e) => Box::new(future::err(e)) as Box<Future<Item = _, Error = _>>,
+    fn mutates(&self) -> bool {
+        false
+    }
+
+    fn exec(self, host: &Local) -> Self::Future {
+        load_local_async(host.handle())
+    }
+}
+
+impl OsPlatform {
+    /// The platform's name in lowercase, e.g. "centos".
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OsPlatform::Centos => "centos",
+            OsPlatform::Debian => "debian",
+            OsPlatform::Fedora => "fedora",
+            OsPlatform::Freebsd => "freebsd",
+            OsPlatform::Macos => "macos",
+            OsPlatform::Nixos => "nixos",
+            OsPlatform::Ubuntu => "ubuntu",
         }
     }
 }
 
+impl FromStr for OsPlatform {
+    type Err = Error;
+
+    /// Parse a platform name, as returned by `as_str()`. Matching is case
+    /// insensitive.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "centos" => Ok(OsPlatform::Centos),
+            "debian" => Ok(OsPlatform::Debian),
+            "fedora" => Ok(OsPlatform::Fedora),
+            "freebsd" => Ok(OsPlatform::Freebsd),
+            "macos" => Ok(OsPlatform::Macos),
+            "nixos" => Ok(OsPlatform::Nixos),
+            "ubuntu" => Ok(OsPlatform::Ubuntu),
+            _ => Err(format!("Unknown OS platform '{}'", s).into()),
+        }
+    }
+}
+
+impl FsMount {
+    /// The capacity of the device in binary units, e.g. "39.2 GiB".
+    pub fn size_human(&self) -> String {
+        human_bytes(self.size)
+    }
+
+    /// The amount used in binary units, e.g. "2.0 GiB".
+    pub fn used_human(&self) -> String {
+        human_bytes(self.used)
+    }
+
+    /// The remaining capacity in binary units, e.g. "35.5 GiB".
+    pub fn available_human(&self) -> String {
+        human_bytes(self.available)
+    }
+
+    /// The percentage of the device that is used, from 0.0 to 100.0.
+    pub fn percent_used(&self) -> f32 {
+        (self.capacity * 100.0).max(0.0).min(100.0)
+    }
+}
+
+// Format `bytes` with the largest binary prefix that keeps the value at or
+// above 1, e.g. 1536 is "1.5 KiB".
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&'static str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
 impl User {
     // Whether this user is root, which is calculated as `uid == 0`.
     pub fn is_root(&self) -> bool {
         self.uid == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use futures::sync::oneshot;
+    use std::f32;
+    use std::sync::{Arc, Mutex};
+    use super::{finish_loading, human_bytes, FsMount, Loading, LOADING};
+
+    fn mount(size: u64, used: u64, capacity: f32) -> FsMount {
+        FsMount {
+            filesystem: "/dev/sda1".into(),
+            fs_type: "ext4".into(),
+            options: Vec::new(),
+            mountpoint: "/".into(),
+            size: size,
+            used: used,
+            available: size - used,
+            capacity: capacity,
+        }
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1024), "1.0 KiB");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(1024 * 1024 - 1), "1024.0 KiB");
+        assert_eq!(human_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(human_bytes(u64::max_value()), "16.0 EiB");
+    }
+
+    #[test]
+    fn test_fs_mount_human() {
+        let gib = 1024 * 1024 * 1024;
+        let fs = mount(40 * gib, 4 * gib + gib / 5, 0.105);
+        assert_eq!(fs.size_human(), "40.0 GiB");
+        assert_eq!(fs.used_human(), "4.2 GiB");
+        assert_eq!(fs.available_human(), "35.8 GiB");
+    }
+
+    #[test]
+    fn test_percent_used() {
+        assert!((mount(100, 6, 0.06).percent_used() - 6.0).abs() < 0.001);
+        assert_eq!(mount(100, 0, 0.0).percent_used(), 0.0);
+        assert_eq!(mount(100, 100, 1.0).percent_used(), 100.0);
+    }
+
+    #[test]
+    fn test_percent_used_is_clamped() {
+        assert_eq!(mount(100, 100, 1.5).percent_used(), 100.0);
+        assert_eq!(mount(100, 0, -0.5).percent_used(), 0.0);
+        assert_eq!(mount(0, 0, f32::NAN).percent_used(), 0.0);
+    }
+
+    #[test]
+    fn test_finish_loading_answers_every_waiter() {
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        let (tx3, rx3) = oneshot::channel();
+        *LOADING.lock().unwrap() = Some(Loading {
+            waiters: vec![tx1, tx2, tx3],
+            last_command: Arc::new(Mutex::new(None)),
+        });
+        // A waiter that timed out
+        drop(rx2);
+
+        finish_loading(Err("df failed".into()));
+
+        assert_eq!(rx1.wait().unwrap().unwrap_err().to_string(), "df failed");
+        assert_eq!(rx3.wait().unwrap().unwrap_err().to_string(), "df failed");
+        assert!(LOADING.lock().unwrap().is_none());
+    }
+}
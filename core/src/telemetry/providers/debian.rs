@@ -4,10 +4,11 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+use command::hooks;
 use errors::*;
 use futures::{future, Future};
 use pnet::datalink::interfaces;
-use std::{env, process, str};
+use std::{env, str};
 use super::TelemetryProvider;
 use target::{default, linux};
 use target::linux::LinuxFlavour;
@@ -33,34 +34,48 @@ impl TelemetryProvider for Debian {
 }
 
 fn do_load() -> Result<Telemetry> {
-    let (version_str, version_maj, version_min) = version()?;
+    let (version_str, version_maj, version_min) = match super::os_release_version("debian") {
+        Some((version_str, maj, min, _)) => (version_str, maj, min),
+        None => version()?,
+    };
 
     Ok(Telemetry {
         cpu: Cpu {
             vendor: linux::cpu_vendor()?,
             brand_string: linux::cpu_brand_string()?,
             cores: linux::cpu_cores()?,
+            physical_cores: linux::cpu_physical_cores()?,
+            threads: linux::cpu_threads()?,
+            mhz: linux::cpu_mhz(),
         },
+        default_gateway: linux::default_gateway(),
+        dns_servers: default::dns_servers(),
         fs: default::fs().chain_err(|| "could not resolve telemetry data")?,
         hostname: default::hostname()?,
+        init_system: linux::init_system(),
+        last_boot: linux::last_boot().ok(),
+        logged_in_users: default::logged_in_users().ok(),
+        machine_id: linux::machine_id(),
         memory: linux::memory().chain_err(|| "could not resolve telemetry data")?,
         net: interfaces(),
         os: Os {
             arch: env::consts::ARCH.into(),
+            arch_detail: super::arch_detail(super::cpuinfo_features()),
             family: OsFamily::Linux(LinuxDistro::Debian),
             platform: OsPlatform::Debian,
             version_str: version_str,
             version_maj: version_maj,
             version_min: version_min,
             version_patch: 0,
+            kernel_version: super::kernel_version()?,
         },
         user: default::user()?,
+        virtualization: linux::virtualization(),
     })
 }
 
 fn version() -> Result<(String, u32, u32)> {
-    let out = process::Command::new("lsb_release")
-                               .arg("-sr")
+    let out = hooks::command("lsb_release", &["-sr"])
                                .output()
                                .chain_err(|| ErrorKind::SystemCommand("lsb_release"))?;
     let version_str = str::from_utf8(&out.stdout)
@@ -33,27 +33,42 @@ impl TelemetryProvider for Fedora {
 }
 
 fn do_load() -> Result<Telemetry> {
-    let (version_str, version_maj, version_min, version_patch) = redhat::version()?;
+    let (version_str, version_maj, version_min, version_patch) = match super::os_release_version("fedora") {
+        Some(version) => version,
+        None => redhat::version()?,
+    };
 
     Ok(Telemetry {
         cpu: Cpu {
             vendor: linux::cpu_vendor()?,
             brand_string: linux::cpu_brand_string()?,
             cores: linux::cpu_cores()?,
+            physical_cores: linux::cpu_physical_cores()?,
+            threads: linux::cpu_threads()?,
+            mhz: linux::cpu_mhz(),
         },
+        default_gateway: linux::default_gateway(),
+        dns_servers: default::dns_servers(),
         fs: default::fs().chain_err(|| "could not resolve telemetry data")?,
         hostname: default::hostname()?,
+        init_system: linux::init_system(),
+        last_boot: linux::last_boot().ok(),
+        logged_in_users: default::logged_in_users().ok(),
+        machine_id: linux::machine_id(),
         memory: linux::memory().chain_err(|| "could not resolve telemetry data")?,
         net: interfaces(),
         os: Os {
             arch: env::consts::ARCH.into(),
+            arch_detail: super::arch_detail(super::cpuinfo_features()),
             family: OsFamily::Linux(LinuxDistro::RHEL),
             platform: OsPlatform::Fedora,
             version_str: version_str,
             version_maj: version_maj,
             version_min: version_min,
             version_patch: version_patch,
+            kernel_version: super::kernel_version()?,
         },
         user: default::user()?,
+        virtualization: linux::virtualization(),
     })
 }
@@ -4,6 +4,7 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+use command::hooks;
 use errors::*;
 use futures::{future, Future};
 use pnet::datalink::interfaces;
@@ -44,9 +45,25 @@ fn do_load() -> Result<Telemetry> {
                         .chain_err(|| "could not resolve telemetry data")?
                         .parse::<u32>()
                         .chain_err(|| "could not resolve telemetry data")?,
+            physical_cores: unix::get_sysctl_item("kern\\.smp\\.cores")
+                        .chain_err(|| "could not resolve telemetry data")?
+                        .parse::<u32>()
+                        .chain_err(|| "could not resolve telemetry data")?,
+            threads: unix::get_sysctl_item("hw\\.ncpu")
+                        .chain_err(|| "could not resolve telemetry data")?
+                        .parse::<u32>()
+                        .chain_err(|| "could not resolve telemetry data")?,
+            mhz: unix::get_sysctl_item("hw\\.clockrate").ok()
+                        .and_then(|mhz| mhz.parse::<u32>().ok()),
         },
+        default_gateway: unix::default_gateway(),
+        dns_servers: default::dns_servers(),
         fs: default::fs()?,
         hostname: default::hostname()?,
+        init_system: "init".into(),
+        last_boot: unix::last_boot().ok(),
+        logged_in_users: default::logged_in_users().ok(),
+        machine_id: machine_id(),
         memory: unix::get_sysctl_item("hw\\.physmem")
                      .chain_err(|| "could not resolve telemetry data")?
                      .parse::<u64>()
@@ -54,14 +71,17 @@ fn do_load() -> Result<Telemetry> {
         net: interfaces(),
         os: Os {
             arch: env::consts::ARCH.into(),
+            arch_detail: super::arch_detail(dmesg_features()),
             family: OsFamily::Bsd,
             platform: OsPlatform::Freebsd,
             version_str: version_str,
             version_maj: version_maj,
             version_min: version_min,
-            version_patch: 0
+            version_patch: 0,
+            kernel_version: super::kernel_version()?,
         },
         user: default::user()?,
+        virtualization: virtualization(),
     })
 }
 
@@ -78,3 +98,40 @@ fn telemetry_cpu_vendor() -> Result<String> {
         Err(ErrorKind::SystemFileOutput("/var/run/dmesg.boot").into())
     }
 }
+
+// Get the CPU features that the kernel logged at boot, e.g.
+// `Features2=0x7ffafbff<SSE3,PCLMULQDQ,...>`. Each set of features has its
+// own line.
+fn dmesg_features() -> Vec<String> {
+    let mut fc = String::new();
+    if fs::File::open("/var/run/dmesg.boot").and_then(|mut fh| fh.read_to_string(&mut fc)).is_err() {
+        return Vec::new();
+    }
+
+    let regex = Regex::new(r"(?m)^\s+[A-Za-z0-9 ]*Features[0-9]*=0x[0-9a-fA-F]+<([^>]*)>").unwrap();
+    regex.captures_iter(&fc)
+        .flat_map(|cap| cap.get(1).unwrap().as_str().split(',').map(|f| f.to_lowercase()).collect::<Vec<_>>())
+        .collect()
+}
+
+fn machine_id() -> Option<String> {
+    hooks::command("kenv", &["smbios.system.uuid"])
+        .output()
+        .ok()
+        .and_then(|out| if out.status.success() {
+            Some(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+        } else {
+            None
+        })
+        .and_then(|id| if id.is_empty() { None } else { Some(id) })
+}
+
+// Jails take precedence, as a jail may itself be running in a VM.
+fn virtualization() -> Option<String> {
+    if unix::get_sysctl_item("security\\.jail\\.jailed").ok().map_or(false, |j| j == "1") {
+        return Some("jail".into());
+    }
+
+    unix::get_sysctl_item("kern\\.vm_guest").ok()
+        .and_then(|vm| if vm.is_empty() || vm == "none" { None } else { Some(vm) })
+}
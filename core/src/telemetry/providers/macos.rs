@@ -4,10 +4,12 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+use command::hooks;
 use errors::*;
 use futures::{future, Future};
 use pnet::datalink::interfaces;
-use std::{env, process, str};
+use regex::Regex;
+use std::{env, str};
 use super::TelemetryProvider;
 use target::{default, unix};
 use telemetry::{Cpu, Os, OsFamily, OsPlatform, Telemetry};
@@ -41,20 +43,27 @@ fn do_load() -> Result<Telemetry> {
             cores: unix::get_sysctl_item("hw\\.physicalcpu")
                         .chain_err(|| "could not resolve telemetry data")?
                         .parse::<u32>()
+                        .chain_err(|| "could not resolve telemetry data")?,
+            physical_cores: unix::get_sysctl_item("hw\\.physicalcpu")
                         .chain_err(|| "could not resolve telemetry data")?
+                        .parse::<u32>()
+                        .chain_err(|| "could not resolve telemetry data")?,
+            threads: unix::get_sysctl_item("hw\\.ncpu")
+                        .chain_err(|| "could not resolve telemetry data")?
+                        .parse::<u32>()
+                        .chain_err(|| "could not resolve telemetry data")?,
+            mhz: unix::get_sysctl_item("hw\\.cpufrequency").ok()
+                        .and_then(|hz| hz.parse::<u64>().ok())
+                        .map(|hz| (hz / 1_000_000) as u32),
         },
-        fs: default::parse_fs(&[
-            default::FsFieldOrder::Filesystem,
-            default::FsFieldOrder::Size,
-            default::FsFieldOrder::Used,
-            default::FsFieldOrder::Available,
-            default::FsFieldOrder::Capacity,
-            default::FsFieldOrder::Blank,
-            default::FsFieldOrder::Blank,
-            default::FsFieldOrder::Blank,
-            default::FsFieldOrder::Mount,
-        ])?,
+        default_gateway: unix::default_gateway(),
+        dns_servers: default::dns_servers(),
+        fs: default::fs()?,
         hostname: default::hostname()?,
+        init_system: "launchd".into(),
+        last_boot: unix::last_boot().ok(),
+        logged_in_users: default::logged_in_users().ok(),
+        machine_id: machine_id(),
         memory: unix::get_sysctl_item("hw\\.memsize")
                      .chain_err(|| "could not resolve telemetry data")?
                      .parse::<u64>()
@@ -62,20 +71,22 @@ fn do_load() -> Result<Telemetry> {
         net: interfaces(),
         os: Os {
             arch: env::consts::ARCH.into(),
+            arch_detail: super::arch_detail(features()),
             family: OsFamily::Darwin,
             platform: OsPlatform::Macos,
             version_str: version_str,
             version_maj: version_maj,
             version_min: version_min,
-            version_patch: version_patch
+            version_patch: version_patch,
+            kernel_version: super::kernel_version()?,
         },
         user: default::user()?,
+        virtualization: None,
     })
 }
 
 fn version() -> Result<(String, u32, u32, u32)> {
-    let out = process::Command::new("sw_vers")
-                               .arg("-productVersion")
+    let out = hooks::command("sw_vers", &["-productVersion"])
                                .output()
                                .chain_err(|| ErrorKind::SystemCommand("sw_vers"))?;
     let version_str = str::from_utf8(&out.stdout)
@@ -93,3 +104,25 @@ fn version() -> Result<(String, u32, u32, u32)> {
     };
     Ok((version_str, maj, min, patch))
 }
+
+// Get the CPU features from sysctl. Apple silicon doesn't list them here,
+// so they are empty there.
+fn features() -> Vec<String> {
+    ["machdep\\.cpu\\.features", "machdep\\.cpu\\.leaf7_features", "machdep\\.cpu\\.extfeatures"].iter()
+        .filter_map(|item| unix::get_sysctl_item(item).ok())
+        .flat_map(|f| f.split_whitespace().map(|f| f.to_lowercase()).collect::<Vec<_>>())
+        .collect()
+}
+
+fn machine_id() -> Option<String> {
+    let out = match hooks::command("ioreg", &["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+    {
+        Ok(out) => out,
+        Err(_) => return None,
+    };
+
+    let regex = Regex::new(r#""IOPlatformUUID" = "([^"]+)""#).unwrap();
+    regex.captures(&String::from_utf8_lossy(&out.stdout))
+        .map(|cap| cap.get(1).unwrap().as_str().to_owned())
+}
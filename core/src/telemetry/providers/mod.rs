@@ -22,9 +22,12 @@ pub use self::macos::Macos;
 pub use self::nixos::Nixos;
 pub use self::ubuntu::Ubuntu;
 
+use command::hooks;
 use errors::*;
 use futures::Future;
-use super::Telemetry;
+use std::{fs, str};
+use std::io::Read;
+use super::{ArchDetail, Endian, Telemetry};
 
 pub trait TelemetryProvider {
     fn available() -> bool where Self: Sized;
@@ -57,3 +60,126 @@ pub fn factory() -> Result<Box<TelemetryProvider>> {
         Err(ErrorKind::ProviderUnavailable("Telemetry").into())
     }
 }
+
+// Get the release of the running kernel on Unix hosts.
+fn kernel_version() -> Result<String> {
+    let out = hooks::command("uname", &["-r"])
+        .output()
+        .chain_err(|| ErrorKind::SystemCommand("uname"))?;
+    let release = str::from_utf8(&out.stdout)
+        .chain_err(|| ErrorKind::SystemCommandOutput("uname"))?;
+    Ok(release.trim().into())
+}
+
+// Get the architecture details for this build, with the CPU's `features`.
+// The pointer width and byte order are fixed when compiling.
+fn arch_detail(mut features: Vec<String>) -> ArchDetail {
+    features.sort();
+    features.dedup();
+
+    ArchDetail {
+        bits: if cfg!(target_pointer_width = "64") {
+            64
+        } else if cfg!(target_pointer_width = "16") {
+            16
+        } else {
+            32
+        },
+        endian: if cfg!(target_endian = "big") { Endian::Big } else { Endian::Little },
+        features: features,
+    }
+}
+
+// Get the CPU features listed in `/proc/cpuinfo`, which are called "flags"
+// on x86 and "Features" on ARM. Every processor has the same list, so only
+// the first is read.
+fn cpuinfo_features() -> Vec<String> {
+    let mut content = String::new();
+    if fs::File::open("/proc/cpuinfo").and_then(|mut fh| fh.read_to_string(&mut content)).is_err() {
+        return Vec::new();
+    }
+
+    content.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next().map(|k| k.trim()), parts.next()) {
+                (Some("flags"), Some(v)) | (Some("Features"), Some(v)) => Some(v),
+                _ => None,
+            }
+        })
+        .next()
+        .map(|v| v.split_whitespace().map(|f| f.to_lowercase()).collect())
+        .unwrap_or_default()
+}
+
+// The identifying fields of `/etc/os-release`, which all modern Linux
+// distributions ship. See os-release(5).
+struct OsRelease {
+    id: String,
+    version: Option<String>,
+    version_id: Option<String>,
+}
+
+impl OsRelease {
+    // Split `VERSION_ID` into major, minor and patch numbers. Missing parts
+    // are 0, e.g. Debian's "9" is 9.0.0.
+    fn version(&self) -> Option<(String, u32, u32, u32)> {
+        let version_id = self.version_id.as_ref()?;
+        let mut parts = version_id.split('.').map(|p| p.parse::<u32>());
+        let maj = parts.next()?.ok()?;
+        let min = parts.next().unwrap_or(Ok(0)).ok()?;
+        let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+        Some((version_id.clone(), maj, min, patch))
+    }
+}
+
+// Parse `/etc/os-release`, falling back to `/usr/lib/os-release`. Returns
+// `None` if neither file exists or has an `ID`, in which case the provider
+// should fall back to `lsb_release` or its own release file.
+fn os_release() -> Option<OsRelease> {
+    let mut content = String::new();
+    let found = ["/etc/os-release", "/usr/lib/os-release"].iter()
+        .any(|path| fs::File::open(path).and_then(|mut fh| fh.read_to_string(&mut content)).is_ok());
+    if !found {
+        return None;
+    }
+
+    let mut release = OsRelease {
+        id: String::new(),
+        version: None,
+        version_id: None,
+    };
+
+    for line in content.lines() {
+        let mut parts = line.trim().splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = match parts.next() {
+            Some(v) => v.trim().trim_matches(|c| c == '"' || c == '\'').to_owned(),
+            None => continue,
+        };
+
+        match key {
+            "ID" => release.id = value,
+            "VERSION" => release.version = Some(value),
+            "VERSION_ID" => release.version_id = Some(value),
+            _ => (),
+        }
+    }
+
+    if release.id.is_empty() {
+        None
+    } else {
+        Some(release)
+    }
+}
+
+// The version of distribution `id` from `/etc/os-release`, if the file
+// exists and describes that distribution. Derivatives (e.g. Linux Mint,
+// which has `ID_LIKE=ubuntu`) are not matched, as their version numbers
+// are their own.
+fn os_release_version(id: &str) -> Option<(String, u32, u32, u32)> {
+    match os_release() {
+        Some(ref release) if release.id == id => release.version(),
+        _ => None,
+    }
+}
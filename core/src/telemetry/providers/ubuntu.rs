@@ -4,11 +4,12 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
+use command::hooks;
 use errors::*;
 use futures::{future, Future};
 use pnet::datalink::interfaces;
 use regex::Regex;
-use std::{env, process, str};
+use std::{env, str};
 use super::TelemetryProvider;
 use target::{default, linux};
 use target::linux::LinuxFlavour;
@@ -41,40 +42,65 @@ fn do_load() -> Result<Telemetry> {
             vendor: linux::cpu_vendor()?,
             brand_string: linux::cpu_brand_string()?,
             cores: linux::cpu_cores()?,
+            physical_cores: linux::cpu_physical_cores()?,
+            threads: linux::cpu_threads()?,
+            mhz: linux::cpu_mhz(),
         },
+        default_gateway: linux::default_gateway(),
+        dns_servers: default::dns_servers(),
         fs: default::fs().chain_err(|| "could not resolve telemetry data")?,
         hostname: default::hostname()?,
+        init_system: linux::init_system(),
+        last_boot: linux::last_boot().ok(),
+        logged_in_users: default::logged_in_users().ok(),
+        machine_id: linux::machine_id(),
         memory: linux::memory().chain_err(|| "could not resolve telemetry data")?,
         net: interfaces(),
         os: Os {
             arch: env::consts::ARCH.into(),
+            arch_detail: super::arch_detail(super::cpuinfo_features()),
             family: OsFamily::Linux(LinuxDistro::Debian),
             platform: OsPlatform::Ubuntu,
             version_str: version_str,
             version_maj: version_maj,
             version_min: version_min,
             version_patch: version_patch,
+            kernel_version: super::kernel_version()?,
         },
         user: default::user()?,
+        virtualization: linux::virtualization(),
     })
 }
 
+// Ubuntu's `VERSION_ID` omits the point release, so parse the full
+// `VERSION` (e.g. "16.04.3 LTS (Xenial Xerus)") instead.
 fn version() -> Result<(String, u32, u32, u32)> {
-    let out = process::Command::new("lsb_release").arg("-sd").output()?;
+    let release = match super::os_release() {
+        Some(ref r) if r.id == "ubuntu" => r.version.as_ref().and_then(|v| parse_version(v)),
+        _ => None,
+    };
+    if let Some(version) = release {
+        return Ok(version);
+    }
+
+    let out = hooks::command("lsb_release", &["-sd"]).output()?;
     let desc = str::from_utf8(&out.stdout)
                    .chain_err(|| ErrorKind::SystemCommand("Ubuntu-version"))?;
+    parse_version(desc).ok_or(ErrorKind::SystemCommandOutput("lsb_release -sd").into())
+}
 
-    let regex = Regex::new(r"([0-9]+)\.([0-9]+)\.([0-9]+)( LTS)?").unwrap();
-    if let Some(cap) = regex.captures(&desc) {
-        let version_maj = cap.get(1).unwrap().as_str().parse().chain_err(|| ErrorKind::SystemCommandOutput("lsb_release -sd"))?;
-        let version_min = cap.get(2).unwrap().as_str().parse().chain_err(|| ErrorKind::SystemCommandOutput("lsb_release -sd"))?;
-        let version_patch = cap.get(3).unwrap().as_str().parse().chain_err(|| ErrorKind::SystemCommandOutput("lsb_release -sd"))?;
-        let mut version_str = format!("{}.{}.{}", version_maj, version_min, version_patch);
-        if cap.get(4).is_some() {
-            version_str.push_str(" LTS");
-        }
-        Ok((version_str, version_maj, version_min, version_patch))
-    } else {
-        Err(ErrorKind::SystemCommandOutput("lsb_release -sd").into())
+fn parse_version(desc: &str) -> Option<(String, u32, u32, u32)> {
+    let regex = Regex::new(r"([0-9]+)\.([0-9]+)(?:\.([0-9]+))?( LTS)?").unwrap();
+    let cap = regex.captures(desc)?;
+    let version_maj = cap.get(1).unwrap().as_str().parse().ok()?;
+    let version_min = cap.get(2).unwrap().as_str().parse().ok()?;
+    let version_patch = match cap.get(3) {
+        Some(p) => p.as_str().parse().ok()?,
+        None => 0,
+    };
+    let mut version_str = format!("{}.{}.{}", version_maj, version_min, version_patch);
+    if cap.get(4).is_some() {
+        version_str.push_str(" LTS");
     }
+    Some((version_str, version_maj, version_min, version_patch))
 }
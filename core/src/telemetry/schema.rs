@@ -0,0 +1,419 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+// The public JSON representation of `Telemetry`.
+//
+// These structs are deliberately separate from both the public telemetry
+// types and the wire format in `serializable`, so that neither can change the
+// schema by accident. Any change to the JSON produced here must bump
+// `SCHEMA_VERSION`, and `from_json()` must keep reading older versions.
+
+use errors::*;
+use ipnetwork::IpNetwork;
+use pnet::datalink::NetworkInterface;
+use pnet::util::MacAddr;
+use serde_json as json;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use super::{ArchDetail, Cpu, Endian, FsMount, LinuxDistro, Os, OsFamily, OsPlatform, Telemetry, User};
+
+/// The version of the JSON schema written by
+/// [`Telemetry::to_json()`](struct.Telemetry.html#method.to_json).
+pub const SCHEMA_VERSION: u32 = 5;
+
+// Version 2 only changed filesystem sizes from Kb to bytes, version 3 added
+// `os.kernel_version`, version 4 added `os.arch_detail` and version 5 allowed
+// `logged_in_users` and `last_boot` to be null, so all versions share the
+// same layout.
+#[derive(Serialize, Deserialize)]
+struct TelemetryV1 {
+    schema_version: u32,
+    hostname: String,
+    machine_id: Option<String>,
+    cpu: CpuV1,
+    memory: u64,
+    os: OsV1,
+    fs: Vec<FsMountV1>,
+    net: Vec<NetifV1>,
+    default_gateway: Option<IpAddr>,
+    dns_servers: Vec<IpAddr>,
+    user: UserV1,
+    logged_in_users: Option<Vec<String>>,
+    init_system: String,
+    virtualization: Option<String>,
+    last_boot: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CpuV1 {
+    vendor: String,
+    brand_string: String,
+    cores: u32,
+    physical_cores: u32,
+    threads: u32,
+    mhz: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OsV1 {
+    arch: String,
+    family: String,
+    distro: Option<String>,
+    platform: String,
+    version: String,
+    version_maj: u32,
+    version_min: u32,
+    version_patch: u32,
+    #[serde(default)]
+    kernel_version: String,
+    #[serde(default)]
+    arch_detail: Option<ArchDetailV1>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchDetailV1 {
+    bits: u8,
+    endian: String,
+    features: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FsMountV1 {
+    filesystem: String,
+    fs_type: String,
+    options: Vec<String>,
+    mountpoint: String,
+    size: u64,
+    used: u64,
+    available: u64,
+    capacity: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NetifV1 {
+    name: String,
+    index: u32,
+    mac: Option<String>,
+    ips: Vec<String>,
+    flags: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserV1 {
+    name: String,
+    uid: u32,
+    group: String,
+    gid: u32,
+    home_dir: PathBuf,
+}
+
+// Used to read the version before committing to a schema
+#[derive(Deserialize)]
+struct Version {
+    schema_version: u32,
+}
+
+impl Telemetry {
+    /// Serialize this telemetry to JSON that follows a stable, versioned
+    /// schema.
+    ///
+    /// Use this to store telemetry or send it to other systems. Unlike the
+    /// format used to send telemetry between hosts, the schema only changes
+    /// along with [`SCHEMA_VERSION`](constant.SCHEMA_VERSION.html), and
+    /// [`from_json()`](#method.from_json) reads every earlier version.
+    ///
+    /// Version 5 of the schema is an object with these fields:
+    ///
+    ///```json
+    ///{
+    ///  "schema_version": 5,
+    ///  "hostname": "web1.example.com",
+    ///  "machine_id": "4c4c4544004d3510804bb4c04f4e4d32",
+    ///  "cpu": {
+    ///    "vendor": "GenuineIntel",
+    ///    "brand_string": "Intel(R) Xeon(R) CPU E5-2650 v4 @ 2.20GHz",
+    ///    "cores": 4,
+    ///    "physical_cores": 4,
+    ///    "threads": 8,
+    ///    "mhz": 2200
+    ///  },
+    ///  "memory": 8589934592,
+    ///  "os": {
+    ///    "arch": "x86_64",
+    ///    "arch_detail": {
+    ///      "bits": 64,
+    ///      "endian": "little",
+    ///      "features": ["avx", "avx2", "sse4_1", "sse4_2"]
+    ///    },
+    ///    "family": "linux",
+    ///    "distro": "debian",
+    ///    "platform": "ubuntu",
+    ///    "version": "16.04",
+    ///    "version_maj": 16,
+    ///    "version_min": 4,
+    ///    "version_patch": 0,
+    ///    "kernel_version": "4.4.0-97-generic"
+    ///  },
+    ///  "fs": [{
+    ///    "filesystem": "/dev/sda1",
+    ///    "fs_type": "ext4",
+    ///    "options": ["rw", "relatime"],
+    ///    "mountpoint": "/",
+    ///    "size": 42140401664,
+    ///    "used": 2113937408,
+    ///    "available": 38067122176,
+    ///    "capacity": 0.06
+    ///  }],
+    ///  "net": [{
+    ///    "name": "eth0",
+    ///    "index": 2,
+    ///    "mac": "52:54:00:12:34:56",
+    ///    "ips": ["10.0.0.5/24", "fe80::5054:ff:fe12:3456/64"],
+    ///    "flags": 69699
+    ///  }],
+    ///  "default_gateway": "10.0.0.1",
+    ///  "dns_servers": ["10.0.0.2"],
+    ///  "user": {
+    ///    "name": "deploy",
+    ///    "uid": 1000,
+    ///    "group": "deploy",
+    ///    "gid": 1000,
+    ///    "home_dir": "/home/deploy"
+    ///  },
+    ///  "logged_in_users": ["alice"],
+    ///  "init_system": "systemd",
+    ///  "virtualization": "kvm",
+    ///  "last_boot": 1508112000
+    ///}
+    ///```
+    ///
+    /// `endian` is either "big" or "little". `family` is one of "bsd",
+    /// "darwin" or "linux", and
+    /// `distro` is one of "debian", "rhel" or "standalone" for Linux, or
+    /// `null` otherwise. `platform` is as returned by
+    /// [`OsPlatform::as_str()`](enum.OsPlatform.html#method.as_str). Field
+    /// meanings otherwise match the fields of the same name on `Telemetry`,
+    /// so filesystem sizes are in bytes, `capacity` is a fraction from 0.0
+    /// to 1.0, and `logged_in_users` and `last_boot` are `null` if they
+    /// could not be determined.
+    ///
+    /// Version 4 was the same, but `logged_in_users` and `last_boot` were
+    /// never `null`. Version 3 lacked `os.arch_detail`, which is inferred from `os.arch`
+    /// with no features. Version 2 also lacked `os.kernel_version`, which
+    /// reads as an empty string.
+    /// Version 1 also lacked it, and its filesystem sizes were in Kb.
+    pub fn to_json(&self) -> Result<String> {
+        let (family, distro) = match self.os.family {
+            OsFamily::Bsd => ("bsd", None),
+            OsFamily::Darwin => ("darwin", None),
+            OsFamily::Linux(ref d) => ("linux", Some(match *d {
+                LinuxDistro::Debian => "debian",
+                LinuxDistro::RHEL => "rhel",
+                LinuxDistro::Standalone => "standalone",
+            }.to_owned())),
+        };
+
+        let t = TelemetryV1 {
+            schema_version: SCHEMA_VERSION,
+            hostname: self.hostname.clone(),
+            machine_id: self.machine_id.clone(),
+            cpu: CpuV1 {
+                vendor: self.cpu.vendor.clone(),
+                brand_string: self.cpu.brand_string.clone(),
+                cores: self.cpu.cores,
+                physical_cores: self.cpu.physical_cores,
+                threads: self.cpu.threads,
+                mhz: self.cpu.mhz,
+            },
+            memory: self.memory,
+            os: OsV1 {
+                arch: self.os.arch.clone(),
+                family: family.into(),
+                distro: distro,
+                platform: self.os.platform.as_str().into(),
+                version: self.os.version_str.clone(),
+                version_maj: self.os.version_maj,
+                version_min: self.os.version_min,
+                version_patch: self.os.version_patch,
+                kernel_version: self.os.kernel_version.clone(),
+                arch_detail: Some(ArchDetailV1 {
+                    bits: self.os.arch_detail.bits,
+                    endian: match self.os.arch_detail.endian {
+                        Endian::Big => "big",
+                        Endian::Little => "little",
+                    }.into(),
+                    features: self.os.arch_detail.features.clone(),
+                }),
+            },
+            fs: self.fs.iter().map(|m| FsMountV1 {
+                filesystem: m.filesystem.clone(),
+                fs_type: m.fs_type.clone(),
+                options: m.options.clone(),
+                mountpoint: m.mountpoint.clone(),
+                size: m.size,
+                used: m.used,
+                available: m.available,
+                capacity: m.capacity,
+            }).collect(),
+            net: self.net.iter().map(|iface| NetifV1 {
+                name: iface.name.clone(),
+                index: iface.index,
+                mac: iface.mac.map(|addr| addr.to_string()),
+                ips: iface.ips.iter().map(|net| net.to_string()).collect(),
+                flags: iface.flags,
+            }).collect(),
+            default_gateway: self.default_gateway,
+            dns_servers: self.dns_servers.clone(),
+            user: UserV1 {
+                name: self.user.user.clone(),
+                uid: self.user.uid,
+                group: self.user.group.clone(),
+                gid: self.user.gid,
+                home_dir: self.user.home_dir.clone(),
+            },
+            logged_in_users: self.logged_in_users.clone(),
+            init_system: self.init_system.clone(),
+            virtualization: self.virtualization.clone(),
+            last_boot: self.last_boot,
+        };
+
+        json::to_string(&t).chain_err(|| "Could not serialize Telemetry")
+    }
+
+    /// Deserialize telemetry from JSON written by
+    /// [`to_json()`](#method.to_json).
+    ///
+    /// Returns an error if the JSON was written with a newer schema than
+    /// this version of the crate understands. Filesystem sizes from older
+    /// schemas are converted to bytes.
+    pub fn from_json(s: &str) -> Result<Telemetry> {
+        let version: Version = json::from_str(s).chain_err(|| "Telemetry JSON has no schema version")?;
+        if version.schema_version == 0 || version.schema_version > SCHEMA_VERSION {
+            return Err(format!("Unsupported telemetry schema version {} (expected {})",
+                version.schema_version, SCHEMA_VERSION).into());
+        }
+
+        let mut t: TelemetryV1 = json::from_str(s).chain_err(|| "Could not deserialize Telemetry")?;
+
+        if version.schema_version == 1 {
+            for m in &mut t.fs {
+                m.size *= 1024;
+                m.used *= 1024;
+                m.available *= 1024;
+            }
+        }
+
+        let family = match (t.os.family.as_str(), t.os.distro.as_ref().map(|d| d.as_str())) {
+            ("bsd", _) => OsFamily::Bsd,
+            ("darwin", _) => OsFamily::Darwin,
+            ("linux", Some("debian")) => OsFamily::Linux(LinuxDistro::Debian),
+            ("linux", Some("rhel")) => OsFamily::Linux(LinuxDistro::RHEL),
+            ("linux", Some("standalone")) => OsFamily::Linux(LinuxDistro::Standalone),
+            (f, d) => return Err(format!("Unknown OS family '{}' (distro {:?})", f, d).into()),
+        };
+
+        let arch_detail = match t.os.arch_detail {
+            Some(d) => ArchDetail {
+                bits: d.bits,
+                endian: match d.endian.as_str() {
+                    "big" => Endian::Big,
+                    "little" => Endian::Little,
+                    e => return Err(format!("Unknown endianness '{}'", e).into()),
+                },
+                features: d.features,
+            },
+            None => infer_arch_detail(&t.os.arch),
+        };
+
+        let mut net = Vec::new();
+        for iface in t.net {
+            let mac = match iface.mac {
+                Some(m) => Some(MacAddr::from_str(&m).map_err(|_| format!("Invalid MAC address '{}'", m))?),
+                None => None,
+            };
+            let ips = iface.ips.iter()
+                .map(|ip| IpNetwork::from_str(ip).map_err(|_| format!("Invalid IP network '{}'", ip).into()))
+                .collect::<Result<Vec<_>>>()?;
+
+            net.push(NetworkInterface {
+                name: iface.name,
+                index: iface.index,
+                mac: mac,
+                ips: ips,
+                flags: iface.flags,
+            });
+        }
+
+        Ok(Telemetry {
+            cpu: Cpu {
+                vendor: t.cpu.vendor,
+                brand_string: t.cpu.brand_string,
+                cores: t.cpu.cores,
+                physical_cores: t.cpu.physical_cores,
+                threads: t.cpu.threads,
+                mhz: t.cpu.mhz,
+            },
+            default_gateway: t.default_gateway,
+            dns_servers: t.dns_servers,
+            fs: t.fs.into_iter().map(|m| FsMount {
+                filesystem: m.filesystem,
+                fs_type: m.fs_type,
+                options: m.options,
+                mountpoint: m.mountpoint,
+                size: m.size,
+                used: m.used,
+                available: m.available,
+                capacity: m.capacity,
+            }).collect(),
+            hostname: t.hostname,
+            init_system: t.init_system,
+            last_boot: t.last_boot,
+            logged_in_users: t.logged_in_users,
+            machine_id: t.machine_id,
+            memory: t.memory,
+            net: net,
+            os: Os {
+                arch: t.os.arch,
+                arch_detail: arch_detail,
+                family: family,
+                platform: OsPlatform::from_str(&t.os.platform)?,
+                version_str: t.os.version,
+                version_maj: t.os.version_maj,
+                version_min: t.os.version_min,
+                version_patch: t.os.version_patch,
+                kernel_version: t.os.kernel_version,
+            },
+            user: User {
+                user: t.user.name,
+                uid: t.user.uid,
+                group: t.user.group,
+                gid: t.user.gid,
+                home_dir: t.user.home_dir,
+            },
+            virtualization: t.virtualization,
+        })
+    }
+}
+
+// Guess the pointer width and byte order for telemetry that predates
+// `os.arch_detail`, from an architecture name as in `std::env::consts::ARCH`.
+fn infer_arch_detail(arch: &str) -> ArchDetail {
+    let bits = match arch {
+        "x86_64" | "aarch64" | "mips64" | "powerpc64" | "s390x" | "sparc64" => 64,
+        _ => 32,
+    };
+    let endian = match arch {
+        "mips" | "mips64" | "powerpc" | "powerpc64" | "s390x" | "sparc64" => Endian::Big,
+        _ => Endian::Little,
+    };
+
+    ArchDetail {
+        bits: bits,
+        endian: endian,
+        features: Vec::new(),
+    }
+}
@@ -12,18 +12,25 @@ use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 use pnet::datalink::NetworkInterface;
 use pnet::util::MacAddr;
 use std::convert::From;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
 #[derive(Serialize, Deserialize)]
 pub struct Telemetry {
     pub cpu: super::Cpu,
+    pub default_gateway: Option<IpAddr>,
+    pub dns_servers: Vec<IpAddr>,
     pub fs: Vec<super::FsMount>,
     pub hostname: String,
+    pub init_system: String,
+    pub last_boot: Option<u64>,
+    pub logged_in_users: Option<Vec<String>>,
+    pub machine_id: Option<String>,
     pub memory: u64,
     pub net: Vec<Netif>,
     pub os: super::Os,
     pub user: super::User,
+    pub virtualization: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -74,12 +81,19 @@ impl From<super::Telemetry> for Telemetry {
 
         Telemetry {
             cpu: t.cpu,
+            default_gateway: t.default_gateway,
+            dns_servers: t.dns_servers,
             fs: t.fs,
             hostname: t.hostname,
+            init_system: t.init_system,
+            last_boot: t.last_boot,
+            logged_in_users: t.logged_in_users,
+            machine_id: t.machine_id,
             memory: t.memory,
             net: net,
             os: t.os,
             user: t.user,
+            virtualization: t.virtualization,
         }
     }
 }
@@ -99,12 +113,19 @@ impl From<Telemetry> for super::Telemetry {
 
         super::Telemetry {
             cpu: t.cpu,
+            default_gateway: t.default_gateway,
+            dns_servers: t.dns_servers,
             fs: t.fs,
             hostname: t.hostname,
+            init_system: t.init_system,
+            last_boot: t.last_boot,
+            logged_in_users: t.logged_in_users,
+            machine_id: t.machine_id,
             memory: t.memory,
             net: net,
             os: t.os,
             user: t.user,
+            virtualization: t.virtualization,
         }
     }
 }
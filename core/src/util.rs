@@ -0,0 +1,113 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Helpers for composing operations.
+
+use errors::*;
+use futures::{future, Future};
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Run an operation, retrying it if it fails.
+///
+/// `op` is called to create the operation's future. If the future fails,
+/// `op` is called again after waiting `backoff`, up to a total of `attempts`
+/// times. The wait doubles after each failure. If every attempt fails, the
+/// last error is returned.
+///
+/// **Only use this for idempotent operations.** An operation that fails
+/// part way through will be run again from the start, so it must be safe to
+/// run repeatedly, e.g. `Package::install()` or `Service::action()`.
+///
+///## Example
+///
+///```no_run
+///extern crate futures;
+///extern crate intecture_api;
+///extern crate tokio_core;
+///
+///use futures::Future;
+///use intecture_api::prelude::*;
+///use intecture_api::util::retry;
+///use std::time::Duration;
+///use tokio_core::reactor::Core;
+///
+///# fn main() {
+///let mut core = Core::new().unwrap();
+///let handle = core.handle();
+///
+///let host = Local::new(&handle).wait().unwrap();
+///let nginx = Package::new(&host, "nginx").unwrap();
+///
+///let install = retry(&handle, 3, Duration::from_secs(1), move || nginx.install());
+///core.run(install).unwrap();
+///# }
+///```
+pub fn retry<F, T>(handle: &Handle, attempts: u32, backoff: Duration, op: F) -> Box<Future<Item = T::Item, Error = Error>>
+    where F: FnMut() -> T + 'static,
+          T: Future<Error = Error> + 'static
+{
+    retry_if(handle, attempts, backoff, op, |_| true)
+}
+
+/// Run an operation, retrying it if it fails with a retryable error.
+///
+/// This is the same as [`retry()`](fn.retry.html), except that the operation
+/// is only retried if `retryable` returns `true` for the error. Any other
+/// error is returned immediately.
+///
+///## Example
+///
+/// Only retry errors returned by a remote host, not local errors.
+///
+///```no_run
+///# extern crate futures;
+///# extern crate intecture_api;
+///# extern crate tokio_core;
+///# use intecture_api::errors::*;
+///# use intecture_api::util::retry_if;
+///# use std::time::Duration;
+///# use tokio_core::reactor::Core;
+///# fn main() {
+///# let core = Core::new().unwrap();
+///# let handle = core.handle();
+///# let op = || -> Box<futures::Future<Item = (), Error = Error>> { unimplemented!() };
+///retry_if(&handle, 3, Duration::from_secs(1), op, |e| match *e.kind() {
+///    ErrorKind::Remote(_) => true,
+///    _ => false,
+///});
+///# }
+///```
+pub fn retry_if<F, T, P>(handle: &Handle, attempts: u32, backoff: Duration, op: F, retryable: P) -> Box<Future<Item = T::Item, Error = Error>>
+    where F: FnMut() -> T + 'static,
+          T: Future<Error = Error> + 'static,
+          P: Fn(&Error) -> bool + 'static
+{
+    attempt(handle.clone(), attempts.max(1), backoff, op, retryable)
+}
+
+fn attempt<F, T, P>(handle: Handle, remaining: u32, delay: Duration, mut op: F, retryable: P) -> Box<Future<Item = T::Item, Error = Error>>
+    where F: FnMut() -> T + 'static,
+          T: Future<Error = Error> + 'static,
+          P: Fn(&Error) -> bool + 'static
+{
+    Box::new(op().or_else(move |e| {
+        if remaining <= 1 || !retryable(&e) {
+            return Box::new(future::err(e)) as Box<Future<Item = _, Error = _>>;
+        }
+
+        warn!("Operation failed, retrying in {:?}: {}", delay, e);
+
+        let timeout = match Timeout::new(delay, &handle) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "Could not create retry timeout"))),
+        };
+
+        Box::new(timeout
+            .map_err(|e| Error::with_chain(e, "Retry timeout failed"))
+            .and_then(move |_| attempt(handle, remaining - 1, delay * 2, op, retryable)))
+    }))
+}
@@ -0,0 +1,192 @@
+// Copyright 2015-2017 Intecture Developers.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Validation for names that are passed to system commands.
+//!
+//! Providers interpolate service and package names into commands that are
+//! often run as root, so names are restricted to a safe set of characters.
+
+use errors::*;
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use std::result;
+
+/// Check that a service name only contains `[A-Za-z0-9._@-]`.
+pub fn service_name(name: &str) -> Result<()> {
+    validate(name, |c| c.is_ascii_alphanumeric() || "._@-".contains(c))
+}
+
+/// Check that a service action, e.g. `start` or `reload-or-restart`, only
+/// contains `[A-Za-z0-9_-]`.
+pub fn service_action(action: &str) -> Result<()> {
+    validate(action, |c| c.is_ascii_alphanumeric() || "_-".contains(c))
+}
+
+/// Check that a package name only contains `[A-Za-z0-9._@+/:-]`.
+///
+/// This is slightly more permissive than a service name, to allow for names
+/// like `g++`, `libc6:i386` and `@scope/package`.
+pub fn package_name(name: &str) -> Result<()> {
+    validate(name, |c| c.is_ascii_alphanumeric() || "._@+/:-".contains(c))
+}
+
+/// Check that a path to a package file is not empty, doesn't start with a
+/// dash and doesn't contain control characters.
+///
+/// Paths are only ever passed to commands as a single argument, never
+/// through a shell, so other characters are allowed.
+pub fn package_file(path: &str) -> Result<()> {
+    validate(path, |c| !c.is_control())
+}
+
+/// Check that a user name only contains `[A-Za-z0-9._-]`.
+pub fn user_name(name: &str) -> Result<()> {
+    validate(name, |c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+/// Check that a hostname only contains `[A-Za-z0-9.-]` and is no longer than
+/// 253 characters.
+pub fn hostname(name: &str) -> Result<()> {
+    if name.len() > 253 {
+        return Err(ErrorKind::InvalidName(name.into()).into());
+    }
+    validate(name, |c| c.is_ascii_alphanumeric() || ".-".contains(c))
+}
+
+// Deserialize and validate a service name, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_service_name<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    service_name(&name).map_err(|e| de::Error::custom(e.to_string()))?;
+    Ok(name)
+}
+
+// Deserialize and validate a service action, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_service_action<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    let action = String::deserialize(deserializer)?;
+    service_action(&action).map_err(|e| de::Error::custom(e.to_string()))?;
+    Ok(action)
+}
+
+// Deserialize and validate a package name, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_package_name<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    package_name(&name).map_err(|e| de::Error::custom(e.to_string()))?;
+    Ok(name)
+}
+
+// Deserialize and validate a list of package names, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_package_names<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<Vec<String>, D::Error> {
+    let names: Vec<String> = Vec::deserialize(deserializer)?;
+    for name in &names {
+        package_name(name).map_err(|e| de::Error::custom(e.to_string()))?;
+    }
+    Ok(names)
+}
+
+// Deserialize and validate a path to a package file, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_package_file<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    let path = String::deserialize(deserializer)?;
+    package_file(&path).map_err(|e| de::Error::custom(e.to_string()))?;
+    Ok(path)
+}
+
+// Deserialize and validate a hostname, for use with
+// `#[serde(deserialize_with)]`.
+pub fn de_hostname<'de, D: Deserializer<'de>>(deserializer: D) -> result::Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    hostname(&name).map_err(|e| de::Error::custom(e.to_string()))?;
+    Ok(name)
+}
+
+fn validate<F: Fn(char) -> bool>(name: &str, allowed: F) -> Result<()> {
+    // A leading dash would be interpreted as a command line flag
+    if name.is_empty() || name.starts_with('-') || !name.chars().all(allowed) {
+        Err(ErrorKind::InvalidName(name.into()).into())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json as json;
+    use super::*;
+
+    #[test]
+    fn test_package_name() {
+        for name in &["nginx", "g++", "libc6:i386", "@scope/pkg", "python3.6", "lib_foo-dev"] {
+            assert!(package_name(name).is_ok(), "{} should be accepted", name);
+        }
+
+        for name in &["", "-y", "--allow-unauthenticated", "nginx; rm -rf /", "$(reboot)",
+                      "`reboot`", "nginx && reboot", "a b", "nginx|sh", "pkg\n"] {
+            assert!(package_name(name).is_err(), "{:?} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn test_service_name() {
+        for name in &["nginx", "getty@tty1", "php7.0-fpm", "dbus-org.freedesktop.login1"] {
+            assert!(service_name(name).is_ok(), "{} should be accepted", name);
+        }
+
+        for name in &["", "-h", "nginx;reboot", "$(reboot)", "nginx reboot", "a/b", "g++"] {
+            assert!(service_name(name).is_err(), "{:?} should be rejected", name);
+        }
+    }
+
+    #[test]
+    fn test_service_action() {
+        for action in &["start", "reload-or-restart", "force_reload"] {
+            assert!(service_action(action).is_ok(), "{} should be accepted", action);
+        }
+
+        for action in &["", "-k", "--now", "start; rm -rf /", "$(reboot)", "kickstart -k", "start\n"] {
+            assert!(service_action(action).is_err(), "{:?} should be rejected", action);
+        }
+    }
+
+    #[test]
+    fn test_package_file() {
+        assert!(package_file("/tmp/nginx 1.0.deb").is_ok());
+        assert!(package_file("").is_err());
+        assert!(package_file("-i.deb").is_err());
+        assert!(package_file("/tmp/a\nb.deb").is_err());
+    }
+
+    #[test]
+    fn test_user_name() {
+        assert!(user_name("www-data").is_ok());
+        assert!(user_name("root;id").is_err());
+        assert!(user_name("-u").is_err());
+    }
+
+    #[test]
+    fn test_hostname() {
+        assert!(hostname("web-01.example.com").is_ok());
+        assert!(hostname("web_01").is_err());
+        assert!(hostname("-web").is_err());
+        assert!(hostname(&"a".repeat(254)).is_err());
+    }
+
+    #[derive(Deserialize)]
+    struct Action {
+        #[serde(deserialize_with = "de_service_action")]
+        action: String,
+    }
+
+    #[test]
+    fn test_de_service_action() {
+        let action: Action = json::from_str(r#"{"action": "restart"}"#).unwrap();
+        assert_eq!(action.action, "restart");
+        assert!(json::from_str::<Action>(r#"{"action": "start; rm -rf /"}"#).is_err());
+    }
+}